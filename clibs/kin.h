@@ -1,23 +1,69 @@
 #ifndef KIN_VALUE_H
 #define KIN_VALUE_H
 
+// Expose POSIX functions that are hidden by -std=c99
+#ifndef _WIN32
+#define _XOPEN_SOURCE 700
+#endif
+
+#include <errno.h>
+#include <limits.h>
 #include <math.h>
+#include <setjmp.h>
 #include <stdio.h>
 #include <stdbool.h>
 #include <string.h>
 #include <stdint.h>
 #include <stdlib.h>
+#ifdef _WIN32
+#include <direct.h>
+#include <windows.h>
+#else
+#include <dirent.h>
+#include <sys/stat.h>
+#endif
 
 static char** kin_call_stack = NULL;
 static size_t kin_call_stack_len = 0;
 static size_t kin_call_stack_capacity = 0;
 
+// The default maximum call depth, which can be overridden at compile time
+// or with the KIN_MAX_CALL_DEPTH environment variable
+#ifndef KIN_MAX_CALL_DEPTH
+#define KIN_MAX_CALL_DEPTH 10000
+#endif
+
+static size_t kin_max_call_depth = KIN_MAX_CALL_DEPTH;
+
+// The number of frames printed at each end of a long backtrace
+#define KIN_BACKTRACE_EDGE 10
+
 #ifndef min
 #define min(a, b) a < b ? a : b
 #endif
 
+// A point that a panic can unwind to, set by `recover`
+typedef struct KinRecoverPoint {
+    jmp_buf env;
+    size_t call_stack_len;
+    struct KinRecoverPoint* prev;
+} KinRecoverPoint;
+
+// The innermost recover point, or NULL if panics should exit
+static KinRecoverPoint* kin_recover_point = NULL;
+
+// The message of the last recovered panic
+static char kin_panic_message[256];
+
+void kin_panic_impl(char* message);
+
 void kin_push_call_stack(char* call_string) {
     size_t new_len = kin_call_stack_len + 1;
+    if (new_len > kin_max_call_depth) {
+        char str[256];
+        sprintf(str, "Stack overflow: exceeded the maximum call depth of %zu", kin_max_call_depth);
+        kin_panic_impl(str);
+    }
     if (new_len >= kin_call_stack_capacity) {
         kin_call_stack_capacity = kin_call_stack_capacity == 0 ? 1 : kin_call_stack_capacity * 2;
         kin_call_stack = (char**)realloc(kin_call_stack, kin_call_stack_capacity * sizeof(char*));
@@ -30,13 +76,116 @@ void kin_pop_call_stack() {
     kin_call_stack_len -= 1;
 }
 
+#ifdef KIN_LEAK_CHECK
+// An allocation that has not been freed, and the call site that made it
+typedef struct KinAllocation {
+    void* ptr;
+    size_t size;
+    char* site;
+} KinAllocation;
+
+static KinAllocation* kin_allocations = NULL;
+static size_t kin_allocations_len = 0;
+static size_t kin_allocations_capacity = 0;
+
+int kin_compare_allocation_sites(const void* a, const void* b) {
+    return strcmp(((KinAllocation*)a)->site, ((KinAllocation*)b)->site);
+}
+
+// Print the allocations that were never freed, grouped by call site
+void kin_leak_report() {
+    if (kin_allocations_len == 0) return;
+    size_t total = 0;
+    for (size_t i = 0; i < kin_allocations_len; i++) total += kin_allocations[i].size;
+    fprintf(stderr, "\n%zu allocations (%zu bytes) were not freed:\n", kin_allocations_len, total);
+    qsort(kin_allocations, kin_allocations_len, sizeof(KinAllocation), kin_compare_allocation_sites);
+    for (size_t i = 0; i < kin_allocations_len;) {
+        size_t j = i, bytes = 0;
+        while (j < kin_allocations_len && strcmp(kin_allocations[j].site, kin_allocations[i].site) == 0) {
+            bytes += kin_allocations[j].size;
+            j++;
+        }
+        fprintf(stderr, "  %zu allocations (%zu bytes) at %s\n", j - i, bytes, kin_allocations[i].site);
+        i = j;
+    }
+}
+#endif
+
+// Allocate memory for the runtime
+//
+// In leak check mode, the call site of the allocation is recorded.
+void* kin_alloc(size_t size) {
+    void* ptr = malloc(size);
+    if (!ptr && size > 0) kin_panic_impl("Out of memory");
+#ifdef KIN_LEAK_CHECK
+    if (kin_allocations_len == kin_allocations_capacity) {
+        kin_allocations_capacity = kin_allocations_capacity == 0 ? 16 : kin_allocations_capacity * 2;
+        kin_allocations = (KinAllocation*)realloc(kin_allocations, kin_allocations_capacity * sizeof(KinAllocation));
+    }
+    char* site = kin_call_stack_len > 0 ? kin_call_stack[kin_call_stack_len - 1] : "main";
+    kin_allocations[kin_allocations_len++] = (KinAllocation) { .ptr = ptr, .size = size, .site = site };
+#endif
+    return ptr;
+}
+
+// Free memory allocated with kin_alloc
+void kin_free(void* ptr) {
+#ifdef KIN_LEAK_CHECK
+    for (size_t i = kin_allocations_len; i > 0; i--) {
+        if (kin_allocations[i - 1].ptr == ptr) {
+            kin_allocations[i - 1] = kin_allocations[--kin_allocations_len];
+            break;
+        }
+    }
+#endif
+    free(ptr);
+}
+
 void kin_panic_impl(char* message) {
+    if (kin_recover_point) {
+        strncpy(kin_panic_message, message, sizeof(kin_panic_message) - 1);
+        longjmp(kin_recover_point->env, 1);
+    }
     printf("%s\n", message);
-    for (int i = kin_call_stack_len - 1; i >= 0; i--)
+    for (int i = kin_call_stack_len - 1; i >= 0; i--) {
+        size_t from_top = kin_call_stack_len - 1 - i;
+        if (from_top == KIN_BACKTRACE_EDGE && i >= KIN_BACKTRACE_EDGE) {
+            printf("... %d more\n", i - KIN_BACKTRACE_EDGE + 1);
+            i = KIN_BACKTRACE_EDGE - 1;
+        }
         printf("at %s\n", kin_call_stack[i]);
+    }
     exit(EXIT_FAILURE);
 }
 
+// Log levels, from most to least verbose
+typedef enum KinLogLevel {
+    KinLogDebug,
+    KinLogInfo,
+    KinLogError,
+    KinLogOff,
+} KinLogLevel;
+
+static char* kin_log_level_names[] = { "debug", "info", "error", "off" };
+
+// The least severe level that is logged, set by the KIN_LOG env var
+static KinLogLevel kin_log_level = KinLogInfo;
+
+// Initialize the runtime at the start of main
+void kin_init() {
+    char* max_depth = getenv("KIN_MAX_CALL_DEPTH");
+    if (max_depth) kin_max_call_depth = strtoul(max_depth, NULL, 10);
+#ifdef KIN_LEAK_CHECK
+    atexit(kin_leak_report);
+#endif
+    char* log_level = getenv("KIN_LOG");
+    if (log_level) {
+        for (int i = KinLogDebug; i <= KinLogOff; i++) {
+            if (strcmp(log_level, kin_log_level_names[i]) == 0) kin_log_level = i;
+        }
+    }
+}
+
 // The type of a byte
 typedef unsigned char byte;
 
@@ -56,6 +205,7 @@ typedef enum KinType {
     Function,
     Closure,
     Error,
+    Thunk,
 } KinType;
 
 static char* kin_type_names[] = {
@@ -67,6 +217,7 @@ static char* kin_type_names[] = {
     "function",
     "function",
     "error",
+    "thunk",
 };
 
 // Foward declarations
@@ -84,6 +235,13 @@ typedef struct KinFunction {
     KinClosureFn f;
 } KinFunction;
 
+// A deferred call created by `recur`
+typedef struct KinThunk {
+    KinValue* f;
+    uint8_t count;
+    KinValue* args;
+} KinThunk;
+
 // The data of a Kin value
 typedef union KinData {
     bool Bool;
@@ -94,6 +252,7 @@ typedef union KinData {
     KinFn Function;
     KinFunction Closure;
     struct KinValue* Error;
+    KinThunk Thunk;
 } KinData;
 
 // A kin value with a type and data
@@ -148,6 +307,19 @@ void kin_unary_type_panic(char* message, KinType ty) {
     kin_panic_impl(str);
 }
 
+// Check that a value has one of the types it was annotated with
+//
+// `types` is a mask of `1 << KinType` bits
+KinValue kin_check_type(KinValue val, unsigned int types, char* type_name, char* call_site) {
+    if (!(types & (1 << val.type))) {
+        kin_push_call_stack(call_site);
+        char str[256];
+        sprintf(str, "Expected %s, but got %s", type_name, kin_type_names[val.type]);
+        kin_panic_impl(str);
+    }
+    return val;
+}
+
 // Create a new Kin error from a value
 KinValue kin_error(uint8_t count, KinValue* inner) {
     return new_val(Error, inner);
@@ -172,41 +344,154 @@ KinValue kin_call(KinValue val, int count, KinValue* args, char* call_site) {
     }
 }
 
-KinValue kin_print(uint8_t count, KinValue* args) {
-    KinValue val = count >= 1 ? args[0] : KIN_NIL;
+// Call a function with the items of a list as its arguments
+KinValue kin_apply(uint8_t count, KinValue* args) {
+    KinValue f = count >= 1 ? args[0] : KIN_NIL;
+    KinValue list = count >= 2 ? args[1] : KIN_NIL;
+    int len = 0;
+    if (list.type != Nil)
+        for (KinValue* node = &list; node; node = node->mom) len++;
+    if (len > UINT8_MAX) {
+        char str[256];
+        sprintf(str, "Attempted to apply %d arguments, but the maximum is %d", len, UINT8_MAX);
+        kin_panic_impl(str);
+    }
+    // Arguments may be referenced by the return value, so they are not freed
+    KinValue* applied = (KinValue*)kin_alloc((len ? len : 1) * sizeof(KinValue));
+    int i = 0;
+    if (list.type != Nil)
+        for (KinValue* node = &list; node; node = node->mom) applied[i++] = kin_head(*node);
+    return kin_call(f, len, applied, "apply");
+}
+
+KinValue kin_escape(KinValue val);
+
+// Create a thunk that calls a function with the given arguments when run by a trampoline
+KinValue kin_recur(uint8_t count, KinValue* args) {
+    if (count == 0) return KIN_NIL;
+    KinValue* f = (KinValue*)kin_alloc(sizeof(KinValue));
+    *f = args[0];
+    // Arguments may be referenced by the eventual result, so they are not freed
+    KinValue* thunk_args = (KinValue*)kin_alloc(count * sizeof(KinValue));
+    // The caller returns before the thunk runs, so nothing can stay on its stack
+    for (int i = 1; i < count; i++) thunk_args[i - 1] = kin_escape(args[i]);
+    return new_val(Thunk, { .f = f, .count = count - 1, .args = thunk_args });
+}
+
+// Call a function, then keep running the thunks it returns until it returns something else
+KinValue kin_trampoline(uint8_t count, KinValue* args) {
+    if (count == 0) return KIN_NIL;
+    KinValue res = kin_call(args[0], count - 1, args + 1, "trampoline");
+    while (res.type == Thunk) {
+        KinThunk thunk = res.data.Thunk;
+        KinValue f = *thunk.f;
+        kin_free(thunk.f);
+        res = kin_call(f, thunk.count, thunk.args, "trampoline");
+    }
+    return res;
+}
+
+// The function that is about to run as a step of a trampoline
+static KinFn kin_tail_step = NULL;
+
+// Get the C function of a function value, if it has one
+KinFn kin_function_ptr(KinValue f) {
+    switch (f.type) {
+    case Function: return f.data.Function;
+    case Closure: return (KinFn)f.data.Closure.f;
+    default: return NULL;
+    }
+}
+
+// Call a function whose calls in tail position return thunks, then keep
+// running the thunks until one returns something else
+KinValue kin_enter_trampoline(KinValue f, uint8_t count, KinValue* args) {
+    kin_tail_step = kin_function_ptr(f);
+    KinValue res = kin_call(f, count, args, "trampoline");
+    while (res.type == Thunk) {
+        KinThunk thunk = res.data.Thunk;
+        f = *thunk.f;
+        kin_free(thunk.f);
+        kin_tail_step = kin_function_ptr(f);
+        res = kin_call(f, thunk.count, thunk.args, "trampoline");
+    }
+    return res;
+}
+
+// Check whether a function is running as a step of a trampoline, in which
+// case it may return thunks
+//
+// The step is cleared so that the functions it calls do not return thunks.
+bool kin_is_tail_step(KinFn f) {
+    bool step = kin_tail_step == f;
+    kin_tail_step = NULL;
+    return step;
+}
+
+// Format a real with the fewest digits that read back as the same value
+//
+// Reals of a readable size are written without an exponent.
+void kin_format_real(char* str, size_t size, double d) {
+    if (isnan(d)) {
+        snprintf(str, size, "nan");
+        return;
+    }
+    if (isinf(d)) {
+        snprintf(str, size, d > 0 ? "inf" : "-inf");
+        return;
+    }
+    int digits = 1;
+    for (; digits < 17; digits++) {
+        snprintf(str, size, "%.*e", digits - 1, d);
+        if (strtod(str, NULL) == d) break;
+    }
+    snprintf(str, size, "%.*e", digits - 1, d);
+    int exp = atoi(strchr(str, 'e') + 1);
+    if (exp >= -5 && exp < 17) {
+        int decimals = digits - 1 - exp;
+        snprintf(str, size, "%.*f", decimals > 0 ? decimals : 0, d);
+    }
+}
+
+// Write a value to a file as it is printed
+void kin_write_value(FILE* file, KinValue val) {
     switch (val.type) {
     case Nil:
-        printf("nil");
+        fprintf(file, "nil");
         break;
     case Bool:
-        if (val.data.Bool) printf("true");
-        else printf("false");
+        if (val.data.Bool) fprintf(file, "true");
+        else fprintf(file, "false");
         break;
     case Int:
-        printf("%ld", val.data.Int);
+        fprintf(file, "%ld", val.data.Int);
         break;
     case Real:;
-        char str[50];
-        sprintf(str, "%f", val.data.Real);
-        int i = strlen(str);
-        if (i == 0) break;
-        i -= 1;
-        while (str[i] == '0' || str[i] == '.') i--;
-        printf("%*.*s", i + 1, i + 1, str);
+        char str[32];
+        kin_format_real(str, sizeof(str), val.data.Real);
+        fprintf(file, "%s", str);
         break;
     case String:;
         int len = val.data.String.len;
-        printf("%*.*s", len, len, val.data.String.s);
+        fprintf(file, "%*.*s", len, len, val.data.String.s);
         break;
     case Function:
     case Closure:
-        printf("function");
+        fprintf(file, "function");
         break;
     case Error:
-        printf("Error: ");
-        kin_print(1, val.data.Error);
+        fprintf(file, "Error: ");
+        kin_write_value(file, *val.data.Error);
+        break;
+    case Thunk:
+        fprintf(file, "thunk");
         break;
     }
+}
+
+KinValue kin_print(uint8_t count, KinValue* args) {
+    KinValue val = count >= 1 ? args[0] : KIN_NIL;
+    kin_write_value(stdout, val);
     return val;
 }
 
@@ -216,13 +501,127 @@ KinValue kin_println(uint8_t count, KinValue* args) {
     return res;
 }
 
+KinValue kin_eprint(uint8_t count, KinValue* args) {
+    KinValue val = count >= 1 ? args[0] : KIN_NIL;
+    kin_write_value(stderr, val);
+    return val;
+}
+
+KinValue kin_eprintln(uint8_t count, KinValue* args) {
+    KinValue res = kin_eprint(count, args);
+    fprintf(stderr, "\n");
+    return res;
+}
+
+// Write a value to stderr if its level is enabled
+KinValue kin_log(KinLogLevel level, uint8_t count, KinValue* args) {
+    KinValue val = count >= 1 ? args[0] : KIN_NIL;
+    if (level >= kin_log_level) {
+        fprintf(stderr, "[%s] ", kin_log_level_names[level]);
+        kin_write_value(stderr, val);
+        fprintf(stderr, "\n");
+    }
+    return val;
+}
+
+KinValue kin_log_debug(uint8_t count, KinValue* args) {
+    return kin_log(KinLogDebug, count, args);
+}
+
+KinValue kin_log_info(uint8_t count, KinValue* args) {
+    return kin_log(KinLogInfo, count, args);
+}
+
+KinValue kin_log_error(uint8_t count, KinValue* args) {
+    return kin_log(KinLogError, count, args);
+}
+
+// Exit the process with an int status code
+KinValue kin_exit(uint8_t count, KinValue* args) {
+    KinValue code = count >= 1 ? args[0] : new_int(0);
+    if (code.type != Int) kin_unary_type_panic("Exit code must be an int, but it is %s", code.type);
+    exit((int)code.data.Int);
+    return KIN_NIL;
+}
+
+// Get the process exit status from the value of the last expression in main
+int kin_exit_status(KinValue val) {
+    return val.type == Int ? (int)val.data.Int : 0;
+}
+
+// Copy the parts of a value that may be on the stack to the heap
+//
+// This lets a value outlive the frames that are unwound when it is recovered.
+KinValue kin_escape(KinValue val) {
+    KinValue* curr = &val;
+    while (curr) {
+        if (curr->dad) {
+            KinValue* dad = (KinValue*)kin_alloc(sizeof(KinValue));
+            *dad = kin_escape(*curr->dad);
+            curr->dad = dad;
+        }
+        if (curr->type == Error) {
+            KinValue* inner = (KinValue*)kin_alloc(sizeof(KinValue));
+            *inner = kin_escape(*curr->data.Error);
+            curr->data.Error = inner;
+        }
+        if (curr->mom) {
+            KinValue* mom = (KinValue*)kin_alloc(sizeof(KinValue));
+            *mom = *curr->mom;
+            curr->mom = mom;
+        }
+        curr = curr->mom;
+    }
+    return val;
+}
+
+// The value passed to `panic`, if it is being recovered
+static bool kin_has_panic_value = false;
+static KinValue kin_panic_value;
+
 KinValue kin_panic(uint8_t count, KinValue* args) {
-    printf("\nKin panicked:\n");
-    kin_println(count, args);
+    if (kin_recover_point) {
+        kin_panic_value = kin_escape(count >= 1 ? args[0] : KIN_NIL);
+        kin_has_panic_value = true;
+    } else {
+        printf("\nKin panicked:\n");
+        kin_println(count, args);
+    }
     kin_panic_impl("");
     return KIN_NIL;
 }
 
+// Call a function, and call a handler with the panic value if it panics
+//
+// Without a handler, the panic value is returned as an error. Panics that
+// do not come from `panic` have their message as their value.
+KinValue kin_recover(uint8_t count, KinValue* args) {
+    if (count == 0) return KIN_NIL;
+    KinRecoverPoint point;
+    point.call_stack_len = kin_call_stack_len;
+    point.prev = kin_recover_point;
+    kin_recover_point = &point;
+    if (setjmp(point.env) == 0) {
+        KinValue res = kin_call(args[0], 0, NULL, "recover");
+        kin_recover_point = point.prev;
+        return res;
+    }
+    kin_recover_point = point.prev;
+    kin_call_stack_len = point.call_stack_len;
+    KinValue* val = (KinValue*)kin_alloc(sizeof(KinValue));
+    if (kin_has_panic_value) {
+        *val = kin_panic_value;
+        kin_has_panic_value = false;
+    } else {
+        size_t len = strlen(kin_panic_message);
+        char* message = (char*)kin_alloc(len + 1);
+        memcpy(message, kin_panic_message, len + 1);
+        *val = new_string(message, len);
+    }
+    if (count < 2) return kin_error(1, val);
+    return kin_call(args[1], 1, val, "recover");
+}
+
 KinValue kin_call_bin_op(KinValue f(KinValue, KinValue), KinValue a, KinValue b, char* call_site) {
     kin_push_call_stack(call_site);
     KinValue res = f(a, b);
@@ -278,7 +677,52 @@ KinValue kin_sub(KinValue a, KinValue b) {
     return KIN_NIL;
 }
 
+// Repeat a string n times
+KinValue kin_repeat_string(KinString s, long n) {
+    if (n <= 0 || s.len == 0) return new_string("", 0);
+    if ((size_t)n > SIZE_MAX / s.len) kin_panic_impl("Attempted to repeat a string too many times");
+    size_t len = s.len * n;
+    char* buffer = (char*)kin_alloc(len);
+    for (long i = 0; i < n; i++) memcpy(buffer + i * s.len, s.s, s.len);
+    return new_string(buffer, len);
+}
+
+// Repeat a list n times
+KinValue kin_repeat_list(KinValue list, long n) {
+    size_t len = 0;
+    for (KinValue* node = &list; node; node = node->mom) len++;
+    if (n <= 0) return KIN_NIL;
+    if ((size_t)n > SIZE_MAX / sizeof(KinValue) / len) {
+        kin_panic_impl("Attempted to repeat a list too many times");
+    }
+    KinValue* nodes = (KinValue*)kin_alloc(len * n * sizeof(KinValue));
+    size_t i = 0;
+    for (long r = 0; r < n; r++) {
+        for (KinValue* node = &list; node; node = node->mom) {
+            nodes[i] = kin_head(*node);
+            if (i > 0) nodes[i - 1].mom = &nodes[i];
+            i++;
+        }
+    }
+    return nodes[0];
+}
+
+// Make a list of n copies of the items of a value
+//
+// Unlike multiplying, this also repeats a list with one item.
+KinValue kin_repeat(uint8_t count, KinValue* args) {
+    KinValue val = count >= 1 ? args[0] : KIN_NIL;
+    KinValue n = count >= 2 ? args[1] : KIN_NIL;
+    if (n.type != Int) kin_unary_type_panic("Repeat count must be an int, but it is %s", n.type);
+    if (val.type == Nil && !val.mom) return KIN_NIL;
+    return kin_repeat_list(val, n.data.Int);
+}
+
 KinValue kin_mul(KinValue a, KinValue b) {
+    if (a.type == String && b.type == Int) return kin_repeat_string(a.data.String, b.data.Int);
+    if (a.type == Int && b.type == String) return kin_repeat_string(b.data.String, a.data.Int);
+    if (a.mom && b.type == Int) return kin_repeat_list(a, b.data.Int);
+    if (a.type == Int && b.mom) return kin_repeat_list(b, a.data.Int);
     switch (a.type) {
     case Int:
         switch (b.type) {
@@ -298,15 +742,28 @@ KinValue kin_mul(KinValue a, KinValue b) {
         }
     default: break;
     }
-    kin_binary_type_panic("Attempted to subtract multiply types %s and %s", a.type, b.type);
+    kin_binary_type_panic("Attempted to multiply incompatible types %s and %s", a.type, b.type);
     return KIN_NIL;
 }
 
+void kin_check_int_divisor(KinValue b) {
+    if (b.type == Int && b.data.Int == 0) kin_panic_impl("Attempted to divide by zero");
+}
+
+// The minimum int divided by -1 is one more than the maximum int
+void kin_check_int_quotient(KinValue a, KinValue b) {
+    kin_check_int_divisor(b);
+    if (a.data.Int == LONG_MIN && b.data.Int == -1) {
+        kin_panic_impl("Attempted to divide the minimum int by -1, which overflows");
+    }
+}
+
 KinValue kin_div(KinValue a, KinValue b) {
     switch (a.type) {
     case Int:
         switch (b.type) {
         case Int:
+            kin_check_int_quotient(a, b);
             return new_int(a.data.Int / b.data.Int);
         case Real:
             return new_real(a.data.Int / b.data.Real);
@@ -326,11 +783,43 @@ KinValue kin_div(KinValue a, KinValue b) {
     return KIN_NIL;
 }
 
+// Division rounded toward negative infinity
+KinValue kin_floor_div(KinValue a, KinValue b) {
+    switch (a.type) {
+    case Int:
+        switch (b.type) {
+        case Int:;
+            kin_check_int_quotient(a, b);
+            long q = a.data.Int / b.data.Int;
+            if (a.data.Int % b.data.Int != 0 && (a.data.Int < 0) != (b.data.Int < 0)) q -= 1;
+            return new_int(q);
+        case Real:
+            return new_real(floor(a.data.Int / b.data.Real));
+        default: break;
+        }
+    case Real:
+        switch (b.type) {
+        case Int:
+            return new_real(floor(a.data.Real / b.data.Int));
+        case Real:
+            return new_real(floor(a.data.Real / b.data.Real));
+        default: break;
+        }
+    default: break;
+    }
+    kin_binary_type_panic("Attempted to divide incompatible types %s and %s", a.type, b.type);
+    return KIN_NIL;
+}
+
+// Remainder of truncating division, which has the sign of the dividend
 KinValue kin_rem(KinValue a, KinValue b) {
     switch (a.type) {
     case Int:
         switch (b.type) {
         case Int:
+            kin_check_int_divisor(b);
+            // The remainder is 0, but computing it traps for the minimum int
+            if (b.data.Int == -1) return new_int(0);
             return new_int(a.data.Int % b.data.Int);
         case Real:
             return new_real(fmod(a.data.Int, b.data.Real));
@@ -350,6 +839,139 @@ KinValue kin_rem(KinValue a, KinValue b) {
     return KIN_NIL;
 }
 
+// Whether multiplying two ints would overflow
+bool kin_mul_overflows(long a, long b) {
+    if (a > 0) return b > 0 ? a > LONG_MAX / b : b < LONG_MIN / a;
+    return b > 0 ? a < LONG_MIN / b : a != 0 && b < LONG_MAX / a;
+}
+
+KinValue kin_pow(KinValue a, KinValue b) {
+    switch (a.type) {
+    case Int:
+        switch (b.type) {
+        case Int:
+            if (b.data.Int >= 0) {
+                long base = a.data.Int;
+                long exp = b.data.Int;
+                long res = 1;
+                // A power too big for an int is a real instead
+                while (exp > 0) {
+                    if (exp & 1) {
+                        if (kin_mul_overflows(res, base)) break;
+                        res *= base;
+                    }
+                    exp >>= 1;
+                    if (exp == 0) return new_int(res);
+                    if (kin_mul_overflows(base, base)) break;
+                    base *= base;
+                }
+                if (exp == 0) return new_int(res);
+            }
+            return new_real(pow(a.data.Int, b.data.Int));
+        case Real:
+            return new_real(pow(a.data.Int, b.data.Real));
+        default: break;
+        }
+    case Real:
+        switch (b.type) {
+        case Int:
+            return new_real(pow(a.data.Real, b.data.Int));
+        case Real:
+            return new_real(pow(a.data.Real, b.data.Real));
+        default: break;
+        }
+    default: break;
+    }
+    kin_binary_type_panic("Attempted to exponentiate incompatible types %s and %s", a.type, b.type);
+    return KIN_NIL;
+}
+
+// Remainder of flooring division, which has the sign of the divisor
+KinValue kin_mod(KinValue a, KinValue b) {
+    double r;
+    switch (a.type) {
+    case Int:
+        switch (b.type) {
+        case Int:;
+            kin_check_int_divisor(b);
+            if (b.data.Int == -1) return new_int(0);
+            long i = a.data.Int % b.data.Int;
+            if (i != 0 && (i < 0) != (b.data.Int < 0)) i += b.data.Int;
+            return new_int(i);
+        case Real:
+            r = fmod(a.data.Int, b.data.Real);
+            if (r != 0 && (r < 0) != (b.data.Real < 0)) r += b.data.Real;
+            return new_real(r);
+        default: break;
+        }
+    case Real:
+        switch (b.type) {
+        case Int:
+            r = fmod(a.data.Real, b.data.Int);
+            if (r != 0 && (r < 0) != (b.data.Int < 0)) r += b.data.Int;
+            return new_real(r);
+        case Real:
+            r = fmod(a.data.Real, b.data.Real);
+            if (r != 0 && (r < 0) != (b.data.Real < 0)) r += b.data.Real;
+            return new_real(r);
+        default: break;
+        }
+    default: break;
+    }
+    kin_binary_type_panic("Attempted to divide incompatible types %s and %s", a.type, b.type);
+    return KIN_NIL;
+}
+
+void kin_check_ints(char* op, KinValue a, KinValue b) {
+    if (a.type != Int || b.type != Int) {
+        char str[256];
+        sprintf(str, "Attempted to %s %s and %s, but only ints are allowed", op, kin_type_names[a.type], kin_type_names[b.type]);
+        kin_panic_impl(str);
+    }
+}
+
+KinValue kin_band(KinValue a, KinValue b) {
+    kin_check_ints("bitwise and", a, b);
+    return new_int(a.data.Int & b.data.Int);
+}
+
+KinValue kin_bor(KinValue a, KinValue b) {
+    kin_check_ints("bitwise or", a, b);
+    return new_int(a.data.Int | b.data.Int);
+}
+
+KinValue kin_bxor(KinValue a, KinValue b) {
+    kin_check_ints("bitwise xor", a, b);
+    return new_int(a.data.Int ^ b.data.Int);
+}
+
+void kin_check_shift(KinValue b) {
+    if (b.data.Int < 0 || b.data.Int >= 64) {
+        char str[256];
+        sprintf(str, "Attempted to shift by %ld bits", b.data.Int);
+        kin_panic_impl(str);
+    }
+}
+
+KinValue kin_shl(KinValue a, KinValue b) {
+    kin_check_ints("shift", a, b);
+    kin_check_shift(b);
+    return new_int((long)((unsigned long)a.data.Int << b.data.Int));
+}
+
+// Arithmetic right shift, which preserves the sign
+KinValue kin_shr(KinValue a, KinValue b) {
+    kin_check_ints("shift", a, b);
+    kin_check_shift(b);
+    return new_int(a.data.Int >> b.data.Int);
+}
+
+KinValue kin_bnot(uint8_t count, KinValue* args) {
+    KinValue val = count >= 1 ? args[0] : KIN_NIL;
+    if (val.type != Int) kin_unary_type_panic("Attempted to bitwise negate %s", val.type);
+    return new_int(~val.data.Int);
+}
+
 #define bin_fn(f) KinValue f## _fn(uint8_t count, KinValue* args) {  \
     KinValue left = count >= 1 ? args[0] : KIN_NIL; \
     KinValue right = count >= 2 ? args[1] : KIN_NIL; \
@@ -386,6 +1008,7 @@ bool kin_eq_impl(KinValue a, KinValue b) {
     case Function: return b.type == Function && a.data.Function == b.data.Function;
     case Closure: return b.type == Closure && a.data.Closure.f == b.data.Closure.f;
     case Error: return b.type == Error && kin_eq_impl(*a.data.Error, *b.data.Error);
+    case Thunk: return false;
     default: return false;
     }
 }
@@ -486,7 +1109,15 @@ bin_fn(kin_add);
 bin_fn(kin_sub);
 bin_fn(kin_mul);
 bin_fn(kin_div);
+bin_fn(kin_floor_div);
 bin_fn(kin_rem);
+bin_fn(kin_mod);
+bin_fn(kin_pow);
+bin_fn(kin_band);
+bin_fn(kin_bor);
+bin_fn(kin_bxor);
+bin_fn(kin_shl);
+bin_fn(kin_shr);
 bin_fn(kin_eq);
 bin_fn(kin_neq);
 bin_fn(kin_lt);
@@ -504,16 +1135,23 @@ KinValue kin_neg(KinValue val) {
     }
 }
 
-KinValue kin_not(uint8_t count, KinValue* args) {
-    KinValue val = count >= 1 ? args[0] : KIN_NIL;
-    if (val.type == Bool) return new_bool(!val.data.Bool);
-    else return new_bool(val.type == Nil);
-}
-
 bool kin_is_true(KinValue val) {
     return (val.type == Bool) * val.data.Bool + (val.type != Bool) * (val.type != Nil && val.type != Error);
 }
 
+KinValue kin_not(KinValue val) {
+    return new_bool(!kin_is_true(val));
+}
+
+// Get the value of a condition in strict bool mode, where only bools are allowed
+bool kin_check_bool(KinValue val, char* call_site) {
+    if (val.type != Bool) {
+        kin_push_call_stack(call_site);
+        kin_unary_type_panic("Expected a bool condition, but got %s", val.type);
+    }
+    return val.data.Bool;
+}
+
 KinValue kin_assert(uint8_t count, KinValue* args) {
     KinValue val = count >= 1 ? args[0] : KIN_NIL;
     if (!kin_is_true(val)) {
@@ -523,4 +1161,937 @@ KinValue kin_assert(uint8_t count, KinValue* args) {
     return val;
 }
 
+// Trees
+//
+// A tree is a value whose dad is its left subtree and whose mom is its right
+// subtree. An empty subtree is nil, which is stored as a NULL pointer.
+
+bool kin_tree_is_empty(KinValue tree) {
+    return tree.type == Nil && !tree.mom && !tree.dad;
+}
+
+// Copy a subtree to the heap so the tree does not point into the stack
+KinValue* kin_tree_box(KinValue tree) {
+    if (kin_tree_is_empty(tree)) return NULL;
+    KinValue* boxed = (KinValue*)kin_alloc(sizeof(KinValue));
+    *boxed = tree;
+    return boxed;
+}
+
+// Make a tree from its left subtree, value, and right subtree
+KinValue new_tree(KinValue* left, KinValue* val, KinValue* right) {
+    KinValue tree = kin_escape(kin_head(*val));
+    tree.dad = kin_tree_box(kin_escape(*left));
+    tree.mom = kin_tree_box(kin_escape(*right));
+    return tree;
+}
+
+KinValue kin_tree_left(uint8_t count, KinValue* args) {
+    return kin_dad(count, args);
+}
+
+KinValue kin_tree_val(uint8_t count, KinValue* args) {
+    return count >= 1 ? kin_head(args[0]) : KIN_NIL;
+}
+
+KinValue kin_tree_right(uint8_t count, KinValue* args) {
+    return kin_mom(count, args);
+}
+
+// Insert a value into a binary search tree
+//
+// Only the nodes on the path to the new value are copied, so the original
+// tree is unchanged. A value that is already in the tree is not inserted.
+KinValue kin_tree_insert_impl(KinValue tree, KinValue val) {
+    if (kin_tree_is_empty(tree)) return kin_escape(kin_head(val));
+    if (kin_lt_impl(val, tree))
+        tree.dad = kin_tree_box(kin_tree_insert_impl(tree.dad ? *tree.dad : KIN_NIL, val));
+    else if (kin_gt_impl(val, tree))
+        tree.mom = kin_tree_box(kin_tree_insert_impl(tree.mom ? *tree.mom : KIN_NIL, val));
+    return tree;
+}
+
+KinValue kin_tree_insert(uint8_t count, KinValue* args) {
+    KinValue tree = count >= 1 ? args[0] : KIN_NIL;
+    KinValue val = count >= 2 ? args[1] : KIN_NIL;
+    return kin_tree_insert_impl(tree, val);
+}
+
+// Call a function with each value of a tree, from left to right
+void kin_tree_walk_impl(KinValue tree, KinValue f) {
+    if (kin_tree_is_empty(tree)) return;
+    if (tree.dad) kin_tree_walk_impl(*tree.dad, f);
+    KinValue val = kin_head(tree);
+    kin_call(f, 1, &val, "tree_walk");
+    if (tree.mom) kin_tree_walk_impl(*tree.mom, f);
+}
+
+KinValue kin_tree_walk(uint8_t count, KinValue* args) {
+    KinValue tree = count >= 1 ? args[0] : KIN_NIL;
+    KinValue f = count >= 2 ? args[1] : KIN_NIL;
+    kin_tree_walk_impl(tree, f);
+    return KIN_NIL;
+}
+
+// Serialization
+//
+// Values are serialized as text that looks like Kin literals. Lists are
+// written as `[a b c]`, a value with a dad is written as `(dad :: value)`,
+// and errors are written as `error value`. Functions cannot be serialized.
+
+// A growable buffer for building strings
+typedef struct KinBuffer {
+    char* s;
+    size_t len;
+    size_t capacity;
+} KinBuffer;
+
+void kin_buffer_push(KinBuffer* buffer, char* s, size_t len) {
+    if (buffer->len + len > buffer->capacity) {
+        size_t capacity = buffer->capacity == 0 ? 32 : buffer->capacity;
+        while (capacity < buffer->len + len) capacity *= 2;
+        char* data = (char*)kin_alloc(capacity);
+        if (buffer->s) memcpy(data, buffer->s, buffer->len);
+        kin_free(buffer->s);
+        buffer->s = data;
+        buffer->capacity = capacity;
+    }
+    memcpy(buffer->s + buffer->len, s, len);
+    buffer->len += len;
+}
+
+void kin_buffer_push_str(KinBuffer* buffer, char* s) {
+    kin_buffer_push(buffer, s, strlen(s));
+}
+
+// Create an error value with a message
+KinValue kin_error_message(char* message) {
+    KinValue* inner = (KinValue*)kin_alloc(sizeof(KinValue));
+    *inner = new_string(message, strlen(message));
+    return kin_error(1, inner);
+}
+
+bool kin_serialize_value(KinBuffer* buffer, KinValue val);
+
+// Serialize a value and its dad, but not its mom
+bool kin_serialize_node(KinBuffer* buffer, KinValue val) {
+    if (val.dad) {
+        kin_buffer_push_str(buffer, "(");
+        if (!kin_serialize_value(buffer, *val.dad)) return false;
+        kin_buffer_push_str(buffer, " :: ");
+    }
+    char str[50];
+    switch (val.type) {
+    case Nil: kin_buffer_push_str(buffer, "nil"); break;
+    case Bool: kin_buffer_push_str(buffer, val.data.Bool ? "true" : "false"); break;
+    case Int:
+        sprintf(str, "%ld", val.data.Int);
+        kin_buffer_push_str(buffer, str);
+        break;
+    case Real:
+        sprintf(str, "%.17g", val.data.Real);
+        kin_buffer_push_str(buffer, str);
+        if (!strpbrk(str, ".eni")) kin_buffer_push_str(buffer, ".0");
+        break;
+    case String:
+        kin_buffer_push_str(buffer, "\"");
+        for (size_t i = 0; i < val.data.String.len; i++) {
+            byte c = val.data.String.s[i];
+            switch (c) {
+            case '"': kin_buffer_push_str(buffer, "\\\""); break;
+            case '\\': kin_buffer_push_str(buffer, "\\\\"); break;
+            case '\n': kin_buffer_push_str(buffer, "\\n"); break;
+            case '\r': kin_buffer_push_str(buffer, "\\r"); break;
+            case '\t': kin_buffer_push_str(buffer, "\\t"); break;
+            default:
+                if (c < 0x20 || c == 0x7f) {
+                    sprintf(str, "\\x%02x", c);
+                    kin_buffer_push_str(buffer, str);
+                } else {
+                    kin_buffer_push(buffer, (char*)&c, 1);
+                }
+            }
+        }
+        kin_buffer_push_str(buffer, "\"");
+        break;
+    case Error:
+        kin_buffer_push_str(buffer, "error ");
+        if (!kin_serialize_value(buffer, *val.data.Error)) return false;
+        break;
+    case Function:
+    case Closure:
+    case Thunk:
+        return false;
+    }
+    if (val.dad) kin_buffer_push_str(buffer, ")");
+    return true;
+}
+
+bool kin_serialize_value(KinBuffer* buffer, KinValue val) {
+    if (!val.mom) return kin_serialize_node(buffer, val);
+    kin_buffer_push_str(buffer, "[");
+    for (KinValue* node = &val; node; node = node->mom) {
+        if (node != &val) kin_buffer_push_str(buffer, " ");
+        if (!kin_serialize_node(buffer, *node)) return false;
+    }
+    kin_buffer_push_str(buffer, "]");
+    return true;
+}
+
+KinValue kin_serialize(uint8_t count, KinValue* args) {
+    KinValue val = count >= 1 ? args[0] : KIN_NIL;
+    KinBuffer buffer = { .s = NULL, .len = 0, .capacity = 0 };
+    if (!kin_serialize_value(&buffer, val)) {
+        kin_free(buffer.s);
+        return kin_error_message("Functions cannot be serialized");
+    }
+    return new_string(buffer.s, buffer.len);
+}
+
+// The state of deserialization
+typedef struct KinDeserializer {
+    char* s;
+    size_t len;
+    size_t pos;
+    char* error;
+} KinDeserializer;
+
+void kin_deserialize_skip_whitespace(KinDeserializer* de) {
+    while (de->pos < de->len && strchr(" \t\r\n", de->s[de->pos])) de->pos++;
+}
+
+bool kin_deserialize_match(KinDeserializer* de, char* token) {
+    kin_deserialize_skip_whitespace(de);
+    size_t len = strlen(token);
+    if (de->pos + len <= de->len && strncmp(de->s + de->pos, token, len) == 0) {
+        de->pos += len;
+        return true;
+    }
+    return false;
+}
+
+KinValue kin_deserialize_value(KinDeserializer* de);
+
+// Copy a value to the heap so that it can be linked to
+KinValue* kin_deserialize_box(KinValue val) {
+    KinValue* boxed = (KinValue*)kin_alloc(sizeof(KinValue));
+    *boxed = val;
+    return boxed;
+}
+
+KinValue kin_deserialize_string(KinDeserializer* de) {
+    KinBuffer buffer = { .s = NULL, .len = 0, .capacity = 0 };
+    while (de->pos < de->len && de->s[de->pos] != '"') {
+        char c = de->s[de->pos++];
+        if (c == '\\' && de->pos < de->len) {
+            switch (de->s[de->pos++]) {
+            case 'n': c = '\n'; break;
+            case 'r': c = '\r'; break;
+            case 't': c = '\t'; break;
+            case 'x':
+                if (de->pos + 2 > de->len) {
+                    de->error = "Invalid escape in serialized string";
+                    return KIN_NIL;
+                }
+                char hex[3] = { de->s[de->pos], de->s[de->pos + 1], 0 };
+                c = (char)strtol(hex, NULL, 16);
+                de->pos += 2;
+                break;
+            default: c = de->s[de->pos - 1]; break;
+            }
+        }
+        kin_buffer_push(&buffer, &c, 1);
+    }
+    if (!kin_deserialize_match(de, "\"")) {
+        de->error = "Unterminated serialized string";
+        return KIN_NIL;
+    }
+    return new_string(buffer.s ? buffer.s : "", buffer.len);
+}
+
+KinValue kin_deserialize_number(KinDeserializer* de) {
+    size_t start = de->pos;
+    while (de->pos < de->len && strchr("+-0123456789.eEinfa", de->s[de->pos])) de->pos++;
+    size_t len = de->pos - start;
+    char token[64];
+    if (len == 0 || len >= sizeof(token)) {
+        de->error = "Invalid serialized value";
+        return KIN_NIL;
+    }
+    memcpy(token, de->s + start, len);
+    token[len] = 0;
+    char* end;
+    KinValue val = strpbrk(token, ".eEni") ? new_real(strtod(token, &end)) : new_int(strtol(token, &end, 10));
+    if (*end) de->error = "Invalid serialized number";
+    return val;
+}
+
+// Deserialize a value and its dad, but not its mom
+KinValue kin_deserialize_node(KinDeserializer* de) {
+    kin_deserialize_skip_whitespace(de);
+    if (kin_deserialize_match(de, "(")) {
+        KinValue dad = kin_deserialize_value(de);
+        if (!de->error && !kin_deserialize_match(de, "::")) de->error = "Expected :: in serialized value";
+        if (de->error) return KIN_NIL;
+        KinValue val = kin_deserialize_node(de);
+        if (!de->error && !kin_deserialize_match(de, ")")) de->error = "Expected ) in serialized value";
+        val.dad = kin_deserialize_box(dad);
+        return val;
+    }
+    if (kin_deserialize_match(de, "nil")) return KIN_NIL;
+    if (kin_deserialize_match(de, "true")) return KIN_TRUE;
+    if (kin_deserialize_match(de, "false")) return KIN_FALSE;
+    if (kin_deserialize_match(de, "error")) {
+        KinValue inner = kin_deserialize_value(de);
+        return kin_error(1, kin_deserialize_box(inner));
+    }
+    if (kin_deserialize_match(de, "\"")) return kin_deserialize_string(de);
+    return kin_deserialize_number(de);
+}
+
+KinValue kin_deserialize_value(KinDeserializer* de) {
+    if (!kin_deserialize_match(de, "[")) return kin_deserialize_node(de);
+    KinValue head = kin_deserialize_node(de);
+    KinValue* tail = &head;
+    while (!de->error && !kin_deserialize_match(de, "]")) {
+        if (de->pos >= de->len) {
+            de->error = "Unterminated serialized list";
+            break;
+        }
+        tail->mom = kin_deserialize_box(kin_deserialize_node(de));
+        tail = tail->mom;
+    }
+    return head;
+}
+
+KinValue kin_deserialize(uint8_t count, KinValue* args) {
+    KinValue val = count >= 1 ? args[0] : KIN_NIL;
+    if (val.type != String) kin_unary_type_panic("Attempted to deserialize %s", val.type);
+    KinDeserializer de = { .s = val.data.String.s, .len = val.data.String.len, .pos = 0, .error = NULL };
+    KinValue res = kin_deserialize_value(&de);
+    kin_deserialize_skip_whitespace(&de);
+    if (!de.error && de.pos < de.len) de.error = "Unexpected trailing characters in serialized value";
+    return de.error ? kin_error_message(de.error) : res;
+}
+
+// Config parsing
+//
+// Configs are made of `key = value` lines grouped under `[section]` headers.
+// Section names may be dotted to nest them. A config is parsed into an
+// association list where each node is a key and its dad is the value. The
+// value of a section is another association list.
+
+// Create a string value from a slice of a larger string
+KinValue kin_string_slice(char* s, size_t len) {
+    char* copy = (char*)kin_alloc(len + 1);
+    memcpy(copy, s, len);
+    copy[len] = 0;
+    return new_string(copy, len);
+}
+
+void kin_trim(char** s, size_t* len) {
+    while (*len > 0 && strchr(" \t\r", **s)) {
+        (*s)++;
+        (*len)--;
+    }
+    while (*len > 0 && strchr(" \t\r", (*s)[*len - 1])) (*len)--;
+}
+
+// Find the entry for a key in an association list
+KinValue* kin_config_find(KinValue* table, char* key, size_t len) {
+    if (table->type == Nil && !table->mom) return NULL;
+    for (KinValue* node = table; node; node = node->mom) {
+        if (node->type == String && node->data.String.len == len && memcmp(node->data.String.s, key, len) == 0) {
+            return node;
+        }
+    }
+    return NULL;
+}
+
+// Whether a value is an association list, possibly empty
+bool kin_config_is_table(KinValue val) {
+    return (val.type == Nil && !val.mom && !val.dad) || (val.type == String && val.dad);
+}
+
+// Add an entry to the end of an association list
+KinValue* kin_config_insert(KinValue* table, char* key, size_t len, KinValue val) {
+    KinValue entry = kin_string_slice(key, len);
+    entry.dad = kin_deserialize_box(val);
+    if (table->type == Nil && !table->mom) {
+        *table = entry;
+        return table;
+    }
+    KinValue* last = table;
+    while (last->mom) last = last->mom;
+    last->mom = kin_deserialize_box(entry);
+    return last->mom;
+}
+
+KinValue kin_config_error(size_t line, char* message) {
+    char* full = (char*)kin_alloc(strlen(message) + 32);
+    sprintf(full, "line %zu: %s", line, message);
+    return kin_error_message(full);
+}
+
+// Parse a single config value, falling back to a bare string
+KinValue kin_config_value(char* s, size_t len) {
+    KinDeserializer de = { .s = s, .len = len, .pos = 0, .error = NULL };
+    KinValue val = kin_deserialize_value(&de);
+    kin_deserialize_skip_whitespace(&de);
+    if (de.error || de.pos < de.len) return kin_string_slice(s, len);
+    return val;
+}
+
+KinValue kin_config_parse(uint8_t count, KinValue* args) {
+    KinValue text = count >= 1 ? args[0] : KIN_NIL;
+    if (text.type != String) kin_unary_type_panic("Attempted to parse config from %s", text.type);
+    KinValue* root = kin_deserialize_box(KIN_NIL);
+    KinValue* section = root;
+    char* s = text.data.String.s;
+    size_t remaining = text.data.String.len;
+    for (size_t line = 1; remaining > 0; line++) {
+        char* end = memchr(s, '\n', remaining);
+        size_t len = end ? (size_t)(end - s) : remaining;
+        char* next = s + len + (end ? 1 : 0);
+        remaining -= next - s;
+        kin_trim(&s, &len);
+        if (len == 0 || s[0] == '#') {
+            s = next;
+            continue;
+        }
+        if (s[0] == '[') {
+            if (s[len - 1] != ']') return kin_config_error(line, "Expected ] after section name");
+            char* name = s + 1;
+            size_t name_len = len - 2;
+            section = root;
+            while (true) {
+                char* dot = memchr(name, '.', name_len);
+                size_t part_len = dot ? (size_t)(dot - name) : name_len;
+                char* part = name;
+                kin_trim(&part, &part_len);
+                if (part_len == 0) return kin_config_error(line, "Empty section name");
+                KinValue* entry = kin_config_find(section, part, part_len);
+                if (!entry) entry = kin_config_insert(section, part, part_len, KIN_NIL);
+                else if (!kin_config_is_table(*entry->dad)) return kin_config_error(line, "Section name is already a key");
+                section = entry->dad;
+                if (!dot) break;
+                name_len -= dot - name + 1;
+                name = dot + 1;
+            }
+        } else {
+            char* equals = memchr(s, '=', len);
+            if (!equals) return kin_config_error(line, "Expected key = value");
+            char* key = s;
+            size_t key_len = equals - s;
+            char* value = equals + 1;
+            size_t value_len = len - key_len - 1;
+            kin_trim(&key, &key_len);
+            kin_trim(&value, &value_len);
+            if (key_len == 0) return kin_config_error(line, "Empty key");
+            if (kin_config_find(section, key, key_len)) return kin_config_error(line, "Duplicate key");
+            kin_config_insert(section, key, key_len, kin_config_value(value, value_len));
+        }
+        s = next;
+    }
+    return *root;
+}
+
+// Filesystem and paths
+
+#ifdef _WIN32
+#define KIN_PATH_SEPARATORS "/\\"
+#define KIN_PATH_SEPARATOR '\\'
+#else
+#define KIN_PATH_SEPARATORS "/"
+#define KIN_PATH_SEPARATOR '/'
+#endif
+
+// Copy a string value into a null-terminated C string
+char* kin_c_string(KinValue val, char* message) {
+    if (val.type != String) kin_unary_type_panic(message, val.type);
+    return kin_string_slice(val.data.String.s, val.data.String.len).data.String.s;
+}
+
+// Create an error value describing the current errno
+KinValue kin_errno_error(char* action, char* path) {
+    char* err = strerror(errno);
+    char* message = (char*)kin_alloc(strlen(action) + strlen(path) + strlen(err) + 8);
+    sprintf(message, "%s %s: %s", action, path, err);
+    return kin_error_message(message);
+}
+
+// Create a list from an array of values
+KinValue kin_list_from(KinValue* items, size_t len) {
+    if (len == 0) return KIN_NIL;
+    KinValue head = items[0];
+    KinValue* tail = &head;
+    for (size_t i = 1; i < len; i++) {
+        tail->mom = kin_deserialize_box(items[i]);
+        tail = tail->mom;
+    }
+    return head;
+}
+
+int kin_compare_strings(const void* a, const void* b) {
+    return strcmp(*(char**)a, *(char**)b);
+}
+
+// Get the sorted names of the entries of a directory, not including `.` and
+// `..`. Returns NULL if the directory could not be read.
+char** kin_read_dir(char* path, size_t* len) {
+    char** names = NULL;
+    size_t capacity = 0;
+    *len = 0;
+#ifdef _WIN32
+    char* pattern = (char*)kin_alloc(strlen(path) + 3);
+    sprintf(pattern, "%s\\*", path);
+    WIN32_FIND_DATAA data;
+    HANDLE handle = FindFirstFileA(pattern, &data);
+    kin_free(pattern);
+    if (handle == INVALID_HANDLE_VALUE) {
+        errno = ENOENT;
+        return NULL;
+    }
+    do {
+        char* name = data.cFileName;
+#else
+    DIR* dir = opendir(path);
+    if (!dir) return NULL;
+    struct dirent* entry;
+    while ((entry = readdir(dir))) {
+        char* name = entry->d_name;
+#endif
+        if (strcmp(name, ".") == 0 || strcmp(name, "..") == 0) continue;
+        // Leave room for a terminating NULL so that empty directories
+        // are not mistaken for errors
+        if (*len + 1 >= capacity) {
+            capacity = capacity == 0 ? 16 : capacity * 2;
+            char** new_names = (char**)kin_alloc(capacity * sizeof(char*));
+            if (names) memcpy(new_names, names, *len * sizeof(char*));
+            kin_free(names);
+            names = new_names;
+        }
+        names[(*len)++] = kin_string_slice(name, strlen(name)).data.String.s;
+#ifdef _WIN32
+    } while (FindNextFileA(handle, &data));
+    FindClose(handle);
+#else
+    }
+    closedir(dir);
+#endif
+    if (!names) names = (char**)kin_alloc(sizeof(char*));
+    names[*len] = NULL;
+    qsort(names, *len, sizeof(char*), kin_compare_strings);
+    return names;
+}
+
+// Create a list of strings from an array of C strings
+KinValue kin_string_list(char** strings, size_t len) {
+    KinValue* items = (KinValue*)kin_alloc((len + 1) * sizeof(KinValue));
+    for (size_t i = 0; i < len; i++) items[i] = new_string(strings[i], strlen(strings[i]));
+    KinValue list = kin_list_from(items, len);
+    kin_free(items);
+    return list;
+}
+
+KinValue kin_list_dir(uint8_t count, KinValue* args) {
+    char* path = count >= 1 ? kin_c_string(args[0], "Attempted to list directory of %s") : ".";
+    size_t len;
+    char** names = kin_read_dir(path, &len);
+    if (!names) return kin_errno_error("Unable to list directory", path);
+    KinValue list = kin_string_list(names, len);
+    kin_free(names);
+    return list;
+}
+
+KinValue kin_mkdir(uint8_t count, KinValue* args) {
+    char* path = kin_c_string(count >= 1 ? args[0] : KIN_NIL, "Attempted to make directory of %s");
+#ifdef _WIN32
+    int res = _mkdir(path);
+#else
+    int res = mkdir(path, 0777);
+#endif
+    return res == 0 ? KIN_NIL : kin_errno_error("Unable to make directory", path);
+}
+
+KinValue kin_remove_file(uint8_t count, KinValue* args) {
+    char* path = kin_c_string(count >= 1 ? args[0] : KIN_NIL, "Attempted to remove file of %s");
+    return remove(path) == 0 ? KIN_NIL : kin_errno_error("Unable to remove", path);
+}
+
+bool kin_is_separator(char c) {
+    return c && strchr(KIN_PATH_SEPARATORS, c);
+}
+
+// Join any number of path components. A component that is an absolute
+// path replaces everything before it.
+KinValue kin_path_join(uint8_t count, KinValue* args) {
+    KinBuffer buffer = { .s = NULL, .len = 0, .capacity = 0 };
+    for (uint8_t i = 0; i < count; i++) {
+        KinValue part = args[i];
+        if (part.type != String) kin_unary_type_panic("Attempted to join path with %s", part.type);
+        KinString s = part.data.String;
+        if (s.len > 0 && kin_is_separator(s.s[0])) buffer.len = 0;
+        if (buffer.len > 0 && !kin_is_separator(buffer.s[buffer.len - 1]) && s.len > 0) {
+            char separator = KIN_PATH_SEPARATOR;
+            kin_buffer_push(&buffer, &separator, 1);
+        }
+        kin_buffer_push(&buffer, s.s, s.len);
+    }
+    return kin_string_slice(buffer.s ? buffer.s : "", buffer.len);
+}
+
+// Get the last component of a path, ignoring trailing separators
+KinString kin_basename_impl(KinString path) {
+    size_t end = path.len;
+    while (end > 0 && kin_is_separator(path.s[end - 1])) end--;
+    size_t start = end;
+    while (start > 0 && !kin_is_separator(path.s[start - 1])) start--;
+    return new_kin_string(path.s + start, end - start);
+}
+
+KinValue kin_basename(uint8_t count, KinValue* args) {
+    KinValue path = count >= 1 ? args[0] : KIN_NIL;
+    if (path.type != String) kin_unary_type_panic("Attempted to get basename of %s", path.type);
+    KinString name = kin_basename_impl(path.data.String);
+    return kin_string_slice(name.s, name.len);
+}
+
+// Get the extension of a path without the dot, or nil if it has none
+KinValue kin_extension(uint8_t count, KinValue* args) {
+    KinValue path = count >= 1 ? args[0] : KIN_NIL;
+    if (path.type != String) kin_unary_type_panic("Attempted to get extension of %s", path.type);
+    KinString name = kin_basename_impl(path.data.String);
+    for (size_t i = name.len; i > 1; i--) {
+        if (name.s[i - 1] == '.') return kin_string_slice(name.s + i, name.len - i);
+    }
+    return KIN_NIL;
+}
+
+// Globbing
+
+// A growable array of C strings
+typedef struct KinStrings {
+    char** s;
+    size_t len;
+    size_t capacity;
+} KinStrings;
+
+void kin_strings_push(KinStrings* strings, char* s) {
+    if (strings->len == strings->capacity) {
+        strings->capacity = strings->capacity == 0 ? 16 : strings->capacity * 2;
+        char** data = (char**)kin_alloc(strings->capacity * sizeof(char*));
+        if (strings->s) memcpy(data, strings->s, strings->len * sizeof(char*));
+        kin_free(strings->s);
+        strings->s = data;
+    }
+    strings->s[strings->len++] = s;
+}
+
+bool kin_path_exists(char* path) {
+#ifdef _WIN32
+    return GetFileAttributesA(path) != INVALID_FILE_ATTRIBUTES;
+#else
+    struct stat info;
+    return stat(path, &info) == 0;
+#endif
+}
+
+// Match a single path component against a pattern with `*`, `?`, and `[...]`.
+// Wildcards do not match a leading `.`.
+bool kin_glob_match(char* pattern, char* name) {
+    if (name[0] == '.' && pattern[0] != '.') return false;
+    while (*pattern) {
+        switch (*pattern) {
+        case '*':
+            while (*pattern == '*') pattern++;
+            if (!*pattern) return true;
+            for (char* rest = name; *rest; rest++) {
+                if (kin_glob_match(pattern, rest)) return true;
+            }
+            return false;
+        case '?':
+            if (!*name) return false;
+            break;
+        case '[': {
+            char* end = strchr(pattern + 1, ']');
+            if (!end) {
+                if (*name != '[') return false;
+                break;
+            }
+            bool negate = pattern[1] == '!';
+            bool matched = false;
+            for (char* c = pattern + 1 + negate; c < end; c++) {
+                if (c + 2 < end && c[1] == '-') {
+                    matched |= *name >= c[0] && *name <= c[2];
+                    c += 2;
+                } else {
+                    matched |= *name == *c;
+                }
+            }
+            if (!*name || matched == negate) return false;
+            pattern = end;
+            break;
+        }
+        default:
+            if (*pattern != *name) return false;
+        }
+        pattern++;
+        name++;
+    }
+    return !*name;
+}
+
+char* kin_glob_join(char* base, char* name) {
+    size_t base_len = strlen(base);
+    char* path = (char*)kin_alloc(base_len + strlen(name) + 2);
+    if (base_len == 0) strcpy(path, name);
+    else if (kin_is_separator(base[base_len - 1])) sprintf(path, "%s%s", base, name);
+    else sprintf(path, "%s%c%s", base, KIN_PATH_SEPARATOR, name);
+    return path;
+}
+
+void kin_glob_walk(char* base, char** components, size_t count, KinStrings* results) {
+    if (count == 0) {
+        if (*base) kin_strings_push(results, kin_string_slice(base, strlen(base)).data.String.s);
+        return;
+    }
+    char* component = components[0];
+    if (strcmp(component, "**") == 0) {
+        kin_glob_walk(base, components + 1, count - 1, results);
+    } else if (!strpbrk(component, "*?[")) {
+        char* path = kin_glob_join(base, component);
+        if (kin_path_exists(path)) kin_glob_walk(path, components + 1, count - 1, results);
+        kin_free(path);
+        return;
+    }
+    size_t len;
+    char** names = kin_read_dir(*base ? base : ".", &len);
+    if (!names) return;
+    for (size_t i = 0; i < len; i++) {
+        char* path = kin_glob_join(base, names[i]);
+        if (strcmp(component, "**") == 0) {
+            if (names[i][0] != '.') kin_glob_walk(path, components, count, results);
+        } else if (kin_glob_match(component, names[i])) {
+            kin_glob_walk(path, components + 1, count - 1, results);
+        }
+        kin_free(path);
+        kin_free(names[i]);
+    }
+    kin_free(names);
+}
+
+// Get a sorted list of the paths that match a pattern. `**` matches
+// any number of directories.
+KinValue kin_glob(uint8_t count, KinValue* args) {
+    char* pattern = kin_c_string(count >= 1 ? args[0] : KIN_NIL, "Attempted to glob with %s");
+    KinStrings components = { .s = NULL, .len = 0, .capacity = 0 };
+    char* base = kin_is_separator(pattern[0]) ? "/" : "";
+    for (char* part = strtok(pattern, KIN_PATH_SEPARATORS); part; part = strtok(NULL, KIN_PATH_SEPARATORS)) {
+        kin_strings_push(&components, part);
+    }
+    KinStrings results = { .s = NULL, .len = 0, .capacity = 0 };
+    kin_glob_walk(base, components.s, components.len, &results);
+    qsort(results.s, results.len, sizeof(char*), kin_compare_strings);
+    KinValue list = kin_string_list(results.s, results.len);
+    kin_free(results.s);
+    kin_free(components.s);
+    kin_free(pattern);
+    return list;
+}
+
+// Hashing
+
+#define KIN_FNV_OFFSET 14695981039346656037ULL
+#define KIN_FNV_PRIME 1099511628211ULL
+
+uint64_t kin_hash_bytes(uint64_t hash, void* data, size_t len) {
+    for (size_t i = 0; i < len; i++) {
+        hash ^= ((byte*)data)[i];
+        hash *= KIN_FNV_PRIME;
+    }
+    return hash;
+}
+
+uint64_t kin_hash_value(uint64_t hash, KinValue val);
+
+// Hash a value and its dad, but not its mom
+uint64_t kin_hash_node(uint64_t hash, KinValue val) {
+    KinType type = val.type;
+    // Reals that equal an int hash the same as that int
+    // Casting a real outside the range of a long is undefined, so only
+    // whole reals in range hash like ints
+    if (type == Real) {
+        double r = val.data.Real;
+        if (isfinite(r) && r >= -9.2e18 && r < 9.2e18 && r == (long)r) {
+            type = Int;
+            val.data.Int = (long)r;
+        }
+    }
+    hash = kin_hash_bytes(hash, &type, sizeof(type));
+    switch (type) {
+    case Nil: break;
+    case Bool: hash = kin_hash_bytes(hash, &val.data.Bool, sizeof(bool)); break;
+    case Int: hash = kin_hash_bytes(hash, &val.data.Int, sizeof(long)); break;
+    case Real: hash = kin_hash_bytes(hash, &val.data.Real, sizeof(double)); break;
+    case String: hash = kin_hash_bytes(hash, val.data.String.s, val.data.String.len); break;
+    case Function: hash = kin_hash_bytes(hash, &val.data.Function, sizeof(KinFn)); break;
+    case Closure: hash = kin_hash_bytes(hash, &val.data.Closure.f, sizeof(KinClosureFn)); break;
+    case Error: hash = kin_hash_value(hash, *val.data.Error); break;
+    case Thunk: hash = kin_hash_bytes(hash, &val.data.Thunk.f, sizeof(KinValue*)); break;
+    }
+    if (val.dad) hash = kin_hash_value(kin_hash_bytes(hash, "::", 2), *val.dad);
+    return hash;
+}
+
+uint64_t kin_hash_value(uint64_t hash, KinValue val) {
+    for (KinValue* node = &val; node; node = node->mom) {
+        hash = kin_hash_node(kin_hash_bytes(hash, ":", 1), *node);
+    }
+    return hash;
+}
+
+// Hash a value structurally, including its mom and dad
+KinValue kin_hash(uint8_t count, KinValue* args) {
+    KinValue val = count >= 1 ? args[0] : KIN_NIL;
+    return new_int((long)kin_hash_value(KIN_FNV_OFFSET, val));
+}
+
+KinString kin_hash_input(uint8_t count, KinValue* args, char* message) {
+    KinValue val = count >= 1 ? args[0] : KIN_NIL;
+    if (val.type != String) kin_unary_type_panic(message, val.type);
+    return val.data.String;
+}
+
+KinValue kin_hex_digest(byte* digest, size_t len) {
+    char* hex = (char*)kin_alloc(len * 2 + 1);
+    for (size_t i = 0; i < len; i++) sprintf(hex + i * 2, "%02x", digest[i]);
+    return new_string(hex, len * 2);
+}
+
+KinValue kin_crc32(uint8_t count, KinValue* args) {
+    KinString s = kin_hash_input(count, args, "Attempted to get crc32 of %s");
+    uint32_t crc = 0xffffffff;
+    for (size_t i = 0; i < s.len; i++) {
+        crc ^= (byte)s.s[i];
+        for (int j = 0; j < 8; j++) crc = (crc >> 1) ^ (0xedb88320 & -(crc & 1));
+    }
+    return new_int((long)(crc ^ 0xffffffff));
+}
+
+// Pad a message the way MD5 and SHA-256 do, with the bit length
+// stored in the last 8 bytes
+byte* kin_digest_pad(KinString s, size_t* padded_len, bool big_endian) {
+    *padded_len = (s.len + 8) / 64 * 64 + 64;
+    byte* padded = (byte*)kin_alloc(*padded_len);
+    memset(padded, 0, *padded_len);
+    memcpy(padded, s.s, s.len);
+    padded[s.len] = 0x80;
+    uint64_t bits = (uint64_t)s.len * 8;
+    for (int i = 0; i < 8; i++) {
+        padded[*padded_len - (big_endian ? 1 + i : 8 - i)] = (byte)(bits >> (8 * i));
+    }
+    return padded;
+}
+
+#define KIN_ROTL(x, n) (((x) << (n)) | ((x) >> (32 - (n))))
+#define KIN_ROTR(x, n) (((x) >> (n)) | ((x) << (32 - (n))))
+
+KinValue kin_md5(uint8_t count, KinValue* args) {
+    static const uint32_t k[64] = {
+        0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+        0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+        0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+        0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+        0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+        0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+        0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+        0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+    };
+    static const int r[64] = {
+        7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22,
+        5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20,
+        4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23,
+        6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+    };
+    KinString s = kin_hash_input(count, args, "Attempted to get md5 of %s");
+    size_t len;
+    byte* padded = kin_digest_pad(s, &len, false);
+    uint32_t h[4] = { 0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476 };
+    for (size_t chunk = 0; chunk < len; chunk += 64) {
+        uint32_t w[16];
+        for (int i = 0; i < 16; i++) {
+            byte* p = padded + chunk + i * 4;
+            w[i] = (uint32_t)p[0] | (uint32_t)p[1] << 8 | (uint32_t)p[2] << 16 | (uint32_t)p[3] << 24;
+        }
+        uint32_t a = h[0], b = h[1], c = h[2], d = h[3];
+        for (int i = 0; i < 64; i++) {
+            uint32_t f;
+            int g;
+            if (i < 16) { f = (b & c) | (~b & d); g = i; }
+            else if (i < 32) { f = (d & b) | (~d & c); g = (5 * i + 1) % 16; }
+            else if (i < 48) { f = b ^ c ^ d; g = (3 * i + 5) % 16; }
+            else { f = c ^ (b | ~d); g = (7 * i) % 16; }
+            uint32_t temp = d;
+            d = c;
+            c = b;
+            b = b + KIN_ROTL(a + f + k[i] + w[g], r[i]);
+            a = temp;
+        }
+        h[0] += a; h[1] += b; h[2] += c; h[3] += d;
+    }
+    kin_free(padded);
+    byte digest[16];
+    for (int i = 0; i < 16; i++) digest[i] = (byte)(h[i / 4] >> (8 * (i % 4)));
+    return kin_hex_digest(digest, 16);
+}
+
+KinValue kin_sha256(uint8_t count, KinValue* args) {
+    static const uint32_t k[64] = {
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+        0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+        0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+        0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+        0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+        0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+        0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+    };
+    KinString s = kin_hash_input(count, args, "Attempted to get sha256 of %s");
+    size_t len;
+    byte* padded = kin_digest_pad(s, &len, true);
+    uint32_t h[8] = {
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+    };
+    for (size_t chunk = 0; chunk < len; chunk += 64) {
+        uint32_t w[64];
+        for (int i = 0; i < 16; i++) {
+            byte* p = padded + chunk + i * 4;
+            w[i] = (uint32_t)p[0] << 24 | (uint32_t)p[1] << 16 | (uint32_t)p[2] << 8 | (uint32_t)p[3];
+        }
+        for (int i = 16; i < 64; i++) {
+            uint32_t s0 = KIN_ROTR(w[i - 15], 7) ^ KIN_ROTR(w[i - 15], 18) ^ (w[i - 15] >> 3);
+            uint32_t s1 = KIN_ROTR(w[i - 2], 17) ^ KIN_ROTR(w[i - 2], 19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16] + s0 + w[i - 7] + s1;
+        }
+        uint32_t v[8];
+        memcpy(v, h, sizeof(v));
+        for (int i = 0; i < 64; i++) {
+            uint32_t s1 = KIN_ROTR(v[4], 6) ^ KIN_ROTR(v[4], 11) ^ KIN_ROTR(v[4], 25);
+            uint32_t ch = (v[4] & v[5]) ^ (~v[4] & v[6]);
+            uint32_t temp1 = v[7] + s1 + ch + k[i] + w[i];
+            uint32_t s0 = KIN_ROTR(v[0], 2) ^ KIN_ROTR(v[0], 13) ^ KIN_ROTR(v[0], 22);
+            uint32_t maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            memmove(v + 1, v, 7 * sizeof(uint32_t));
+            v[4] += temp1;
+            v[0] = temp1 + s0 + maj;
+        }
+        for (int i = 0; i < 8; i++) h[i] += v[i];
+    }
+    kin_free(padded);
+    byte digest[32];
+    for (int i = 0; i < 32; i++) digest[i] = (byte)(h[i / 4] >> (24 - 8 * (i % 4)));
+    return kin_hex_digest(digest, 32);
+}
+
 #endif
@@ -1,20 +1,8 @@
 #![allow(clippy::upper_case_acronyms)]
 
+use itertools::Itertools;
 use pest::Span;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct Lifetime {
-    pub depth: u8,
-    pub refs: u8,
-}
-
-impl Lifetime {
-    pub const STATIC: Self = Lifetime::new(0, 0);
-    pub const fn new(depth: u8, refs: u8) -> Self {
-        Lifetime { depth, refs }
-    }
-}
-
 #[derive(Debug, Clone)]
 pub struct Ident<'a> {
     pub name: &'a str,
@@ -38,24 +26,27 @@ impl<'a> Eq for Ident<'a> {}
 pub enum Item<'a> {
     Node(Node<'a>),
     Def(Def<'a>),
+    /// An expression that runs when the enclosing function returns
+    Defer(Node<'a>),
+    /// An expression whose value is thrown away on purpose
+    Discard(Node<'a>),
+    /// An expression whose value the enclosing function returns early
+    Return(Node<'a>),
 }
 
 impl<'a> Item<'a> {
     pub fn is_const(&self) -> bool {
         match self {
-            Item::Node(node) => node.kind.is_const(),
+            Item::Node(node) | Item::Discard(node) => node.kind.is_const(),
             Item::Def(_) => true,
-        }
-    }
-    pub fn lifetime(&self) -> Lifetime {
-        match self {
-            Item::Node(node) => node.lifetime,
-            Item::Def(_) => Lifetime::STATIC,
+            Item::Defer(_) | Item::Return(_) => false,
         }
     }
     pub fn span(&self) -> &Span<'a> {
         match self {
-            Item::Node(node) => node.kind.span(),
+            Item::Node(node) | Item::Defer(node) | Item::Discard(node) | Item::Return(node) => {
+                node.kind.span()
+            }
             Item::Def(def) => &def.ident.span,
         }
     }
@@ -63,9 +54,122 @@ impl<'a> Item<'a> {
 
 pub type Items<'a> = Vec<Item<'a>>;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Nil,
+    Bool,
+    Int,
+    Real,
+    String,
+    Function,
+    Error,
+}
+
+impl Type {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "nil" => Type::Nil,
+            "bool" => Type::Bool,
+            "int" => Type::Int,
+            "real" => Type::Real,
+            "string" => Type::String,
+            "function" => Type::Function,
+            "error" => Type::Error,
+            _ => return None,
+        })
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Type::Nil => "nil",
+            Type::Bool => "bool",
+            Type::Int => "int",
+            Type::Real => "real",
+            Type::String => "string",
+            Type::Function => "function",
+            Type::Error => "error",
+        }
+    }
+    pub const ALL: [Type; 7] = [
+        Type::Nil,
+        Type::Bool,
+        Type::Int,
+        Type::Real,
+        Type::String,
+        Type::Function,
+        Type::Error,
+    ];
+    /// The name of the matching `KinType` in the runtime
+    pub fn c_name(&self) -> &'static str {
+        match self {
+            Type::Nil => "Nil",
+            Type::Bool => "Bool",
+            Type::Int => "Int",
+            Type::Real => "Real",
+            Type::String => "String",
+            Type::Function => "Function",
+            Type::Error => "Error",
+        }
+    }
+}
+
+/// Named sets of types that can be used in annotations
+pub static BUILTIN_TYPE_SETS: &[(&str, &[Type])] = &[
+    ("any", &Type::ALL),
+    ("number", &[Type::Int, Type::Real]),
+    ("comparable", &[Type::Bool, Type::Int, Type::Real, Type::String]),
+];
+
+/// A set of types that a value is allowed to have
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TypeSet(u8);
+
+impl TypeSet {
+    pub fn of(types: &[Type]) -> Self {
+        types
+            .iter()
+            .fold(TypeSet(0), |set, &ty| set.union(ty.into()))
+    }
+    pub fn union(self, other: Self) -> Self {
+        TypeSet(self.0 | other.0)
+    }
+    pub fn contains(self, ty: Type) -> bool {
+        self.0 & (1 << ty as u8) != 0
+    }
+    /// The C expression for the mask of `KinType`s in the set
+    pub fn c_mask(self) -> String {
+        Type::ALL
+            .iter()
+            .filter(|&&ty| self.contains(ty))
+            .flat_map(|ty| {
+                let closure = if let Type::Function = ty {
+                    Some("1 << Closure".to_string())
+                } else {
+                    None
+                };
+                Some(format!("1 << {}", ty.c_name())).into_iter().chain(closure)
+            })
+            .intersperse(" | ".into())
+            .collect()
+    }
+}
+
+impl From<Type> for TypeSet {
+    fn from(ty: Type) -> Self {
+        TypeSet(1 << ty as u8)
+    }
+}
+
+/// A type annotation on a param or return value
+#[derive(Debug, Clone)]
+pub struct TypeAnn<'a> {
+    pub types: TypeSet,
+    pub span: Span<'a>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Param<'a> {
     pub ident: Ident<'a>,
+    pub ty: Option<TypeAnn<'a>>,
 }
 
 pub type Params<'a> = Vec<Param<'a>>;
@@ -74,7 +178,10 @@ pub type Params<'a> = Vec<Param<'a>>;
 pub struct Def<'a> {
     pub ident: Ident<'a>,
     pub params: Params<'a>,
+    pub ret: Option<TypeAnn<'a>>,
     pub items: Items<'a>,
+    /// The note of a `#[deprecated]` attribute, which may be empty
+    pub deprecated: Option<String>,
 }
 
 impl<'a> Def<'a> {
@@ -89,21 +196,17 @@ pub enum NodeKind<'a> {
     BinExpr(BinExpr<'a>),
     UnExpr(UnExpr<'a>),
     Call(CallExpr<'a>),
+    CmpChain(CmpChain<'a>),
 }
 
 impl<'a> NodeKind<'a> {
-    pub fn life(self, depth: u8, refs: u8) -> Node<'a> {
-        Node {
-            kind: self,
-            lifetime: Lifetime::new(depth, refs),
-        }
-    }
     pub fn span(&self) -> &Span<'a> {
         match self {
             NodeKind::Term(_, span) => span,
             NodeKind::BinExpr(expr) => &expr.span,
             NodeKind::UnExpr(expr) => &expr.span,
             NodeKind::Call(expr) => &expr.span,
+            NodeKind::CmpChain(chain) => &chain.span,
         }
     }
     pub fn is_const(&self) -> bool {
@@ -112,18 +215,91 @@ impl<'a> NodeKind<'a> {
             _ => false,
         }
     }
+    /// Get the type a node is known to evaluate to without running it
+    pub fn static_type(&self) -> Option<Type> {
+        match self {
+            NodeKind::Term(term, _) => match term {
+                Term::Expr(items) => match items.last() {
+                    Some(Item::Node(node)) => node.kind.static_type(),
+                    _ => None,
+                },
+                Term::Int(_) => Some(Type::Int),
+                Term::Real(_) => Some(Type::Real),
+                Term::String(_) => Some(Type::String),
+                Term::Closure(_) => Some(Type::Function),
+                Term::Ident(ident) => match ident.name {
+                    "nil" => Some(Type::Nil),
+                    "true" | "false" => Some(Type::Bool),
+                    _ => None,
+                },
+                Term::Tree(_) => None,
+            },
+            NodeKind::BinExpr(expr) if expr.op.is_comparison() => Some(Type::Bool),
+            NodeKind::UnExpr(UnExpr { op: UnOp::Not, .. }) | NodeKind::CmpChain(_) => {
+                Some(Type::Bool)
+            }
+            _ => None,
+        }
+    }
+    /// Check if a node can be known to not evaluate to a bool without running it
+    pub fn is_never_bool(&self) -> bool {
+        match self {
+            NodeKind::Term(Term::Expr(items), _) => match items.last() {
+                Some(Item::Node(node)) => node.kind.is_never_bool(),
+                _ => false,
+            },
+            NodeKind::Term(Term::Ident(_), _) => false,
+            NodeKind::Term(..) => true,
+            NodeKind::BinExpr(expr) => !matches!(
+                expr.op,
+                BinOp::Or | BinOp::Coalesce | BinOp::And | BinOp::Mom | BinOp::Dad
+            ) && !expr.op.is_comparison(),
+            NodeKind::UnExpr(expr) => matches!(expr.op, UnOp::Neg),
+            NodeKind::Call(_) | NodeKind::CmpChain(_) => false,
+        }
+    }
 }
 
+/// An identifier for a node that is unique within a file
+///
+/// Ids are assigned by the parser as it builds nodes, so side tables can be
+/// keyed by id instead of by span.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NodeId(pub u32);
+
 #[derive(Debug, Clone)]
 pub struct Node<'a> {
     pub kind: NodeKind<'a>,
-    pub lifetime: Lifetime,
+    pub id: NodeId,
+}
+
+/// The storage that the children of nodes are allocated in
+///
+/// Nodes are allocated in chunks rather than boxed one at a time, and they
+/// all live as long as the source they are parsed from.
+#[derive(Default)]
+pub struct Arena<'a> {
+    nodes: typed_arena::Arena<Node<'a>>,
+    trees: typed_arena::Arena<[Node<'a>; 3]>,
+    closures: typed_arena::Arena<Closure<'a>>,
+}
+
+impl<'a> Arena<'a> {
+    pub fn node(&'a self, node: Node<'a>) -> &'a Node<'a> {
+        self.nodes.alloc(node)
+    }
+    pub fn tree(&'a self, nodes: [Node<'a>; 3]) -> &'a [Node<'a>; 3] {
+        self.trees.alloc(nodes)
+    }
+    pub fn closure(&'a self, closure: Closure<'a>) -> &'a Closure<'a> {
+        self.closures.alloc(closure)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct BinExpr<'a> {
-    pub left: Box<Node<'a>>,
-    pub right: Box<Node<'a>>,
+    pub left: &'a Node<'a>,
+    pub right: &'a Node<'a>,
     pub op: BinOp,
     pub span: Span<'a>,
     pub op_span: Span<'a>,
@@ -131,15 +307,15 @@ pub struct BinExpr<'a> {
 
 impl<'a> BinExpr<'a> {
     pub fn new(
-        left: Node<'a>,
-        right: Node<'a>,
+        left: &'a Node<'a>,
+        right: &'a Node<'a>,
         op: BinOp,
         span: Span<'a>,
         op_span: Span<'a>,
     ) -> Self {
         BinExpr {
-            left: left.into(),
-            right: right.into(),
+            left,
+            right,
             op,
             span,
             op_span,
@@ -150,6 +326,7 @@ impl<'a> BinExpr<'a> {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BinOp {
     Or,
+    Coalesce,
     And,
     Equals,
     NotEquals,
@@ -161,22 +338,110 @@ pub enum BinOp {
     Sub,
     Mul,
     Div,
+    FloorDiv,
     Rem,
+    Mod,
+    Pow,
     Mom,
     Dad,
 }
 
+/// The associativity of a binary operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assoc {
+    Left,
+    Right,
+}
+
+/// The infix operators that can appear between operands in an expression
+///
+/// `:` and `::` bind tighter than function calls and are handled by the
+/// grammar, so they do not appear here.
+pub static INFIX_OPERATORS: &[(&str, BinOp)] = &[
+    ("or", BinOp::Or),
+    ("??", BinOp::Coalesce),
+    ("and", BinOp::And),
+    ("==", BinOp::Equals),
+    ("!=", BinOp::NotEquals),
+    ("<=", BinOp::LessOrEqual),
+    (">=", BinOp::GreaterOrEqual),
+    ("<", BinOp::Less),
+    (">", BinOp::Greater),
+    ("+", BinOp::Add),
+    ("-", BinOp::Sub),
+    ("*", BinOp::Mul),
+    ("//", BinOp::FloorDiv),
+    ("/", BinOp::Div),
+    ("%", BinOp::Rem),
+    ("mod", BinOp::Mod),
+    ("^", BinOp::Pow),
+];
+
+impl BinOp {
+    /// Get the operator for an infix operator token
+    pub fn from_infix(s: &str) -> Option<Self> {
+        INFIX_OPERATORS
+            .iter()
+            .find(|(op, _)| *op == s)
+            .map(|&(_, op)| op)
+    }
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            BinOp::Equals
+                | BinOp::NotEquals
+                | BinOp::Less
+                | BinOp::LessOrEqual
+                | BinOp::Greater
+                | BinOp::GreaterOrEqual
+        )
+    }
+    /// The binding power of the operator. Higher binds tighter.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            BinOp::Or | BinOp::Coalesce => 1,
+            BinOp::And => 2,
+            BinOp::Equals
+            | BinOp::NotEquals
+            | BinOp::Less
+            | BinOp::LessOrEqual
+            | BinOp::Greater
+            | BinOp::GreaterOrEqual => 3,
+            BinOp::Add | BinOp::Sub => 4,
+            BinOp::Mul | BinOp::Div | BinOp::FloorDiv | BinOp::Rem | BinOp::Mod => 5,
+            BinOp::Pow => 6,
+            BinOp::Mom | BinOp::Dad => 8,
+        }
+    }
+    pub fn assoc(&self) -> Assoc {
+        match self {
+            BinOp::Pow => Assoc::Right,
+            _ => Assoc::Left,
+        }
+    }
+}
+
+/// A chain of comparisons like `a < b <= c`
+///
+/// This means `a < b and b <= c`, but `b` is only evaluated once.
+#[derive(Debug, Clone)]
+pub struct CmpChain<'a> {
+    pub operands: Vec<Node<'a>>,
+    pub ops: Vec<(BinOp, Span<'a>)>,
+    pub span: Span<'a>,
+}
+
 #[derive(Debug, Clone)]
 pub struct UnExpr<'a> {
-    pub inner: Box<Node<'a>>,
+    pub inner: &'a Node<'a>,
     pub op: UnOp,
     pub span: Span<'a>,
 }
 
 impl<'a> UnExpr<'a> {
-    pub fn new(inner: Node<'a>, op: UnOp, span: Span<'a>) -> Self {
+    pub fn new(inner: &'a Node<'a>, op: UnOp, span: Span<'a>) -> Self {
         UnExpr {
-            inner: inner.into(),
+            inner,
             op,
             span,
         }
@@ -186,20 +451,20 @@ impl<'a> UnExpr<'a> {
 #[derive(Debug, Clone)]
 pub enum UnOp {
     Neg,
+    Not,
     Head,
 }
 
-#[derive(Debug, Clone)]
-pub struct CallExpr<'a> {
-    pub caller: Box<Node<'a>>,
-    pub args: Vec<Node<'a>>,
-    pub span: Span<'a>,
+impl UnOp {
+    /// The precedence of `not`, which applies to everything that binds
+    /// tighter than `and`
+    pub const NOT_PRECEDENCE: u8 = 3;
 }
 
 #[derive(Debug, Clone)]
-pub struct PushExpr<'a> {
-    pub head: Box<Node<'a>>,
-    pub tail: Box<Node<'a>>,
+pub struct CallExpr<'a> {
+    pub caller: &'a Node<'a>,
+    pub args: Vec<Node<'a>>,
     pub span: Span<'a>,
 }
 
@@ -210,8 +475,8 @@ pub enum Term<'a> {
     Real(f64),
     Ident(Ident<'a>),
     String(String),
-    Tree(Box<[Node<'a>; 3]>),
-    Closure(Box<Closure<'a>>),
+    Tree(&'a [Node<'a>; 3]),
+    Closure(&'a Closure<'a>),
 }
 
 impl<'a> Term<'a> {
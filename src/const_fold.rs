@@ -0,0 +1,55 @@
+//! Evaluates integer arithmetic on literals before it is transpiled
+//!
+//! Only operations whose result is the same at compile time as in the
+//! runtime are folded. Anything that would overflow is left for the runtime.
+
+use crate::{ast::*, visit::*};
+
+/// Fold the constant integer arithmetic in a file
+pub fn fold<'a>(items: Items<'a>, arena: &'a Arena<'a>) -> Items<'a> {
+    ConstFold { arena }.fold_items(items)
+}
+
+struct ConstFold<'a> {
+    arena: &'a Arena<'a>,
+}
+
+impl<'a> Fold<'a> for ConstFold<'a> {
+    fn arena(&self) -> &'a Arena<'a> {
+        self.arena
+    }
+    fn fold_node(&mut self, node: Node<'a>) -> Node<'a> {
+        // Fold the operands first so that whole chains collapse
+        let node = fold_node(self, node);
+        let value = match &node.kind {
+            NodeKind::BinExpr(expr) => match (int_literal(expr.left), int_literal(expr.right)) {
+                (Some(a), Some(b)) => match expr.op {
+                    BinOp::Add => a.checked_add(b),
+                    BinOp::Sub => a.checked_sub(b),
+                    BinOp::Mul => a.checked_mul(b),
+                    _ => None,
+                },
+                _ => None,
+            },
+            NodeKind::UnExpr(expr) if matches!(expr.op, UnOp::Neg) => {
+                int_literal(expr.inner).and_then(i64::checked_neg)
+            }
+            _ => None,
+        };
+        // The minimum int has no C literal, since the minus is not part of it
+        match value.filter(|&i| i != i64::MIN) {
+            Some(i) => Node {
+                kind: NodeKind::Term(Term::Int(i), node.kind.span().clone()),
+                ..node
+            },
+            None => node,
+        }
+    }
+}
+
+fn int_literal(node: &Node) -> Option<i64> {
+    match node.kind {
+        NodeKind::Term(Term::Int(i), _) => Some(i),
+        _ => None,
+    }
+}
@@ -0,0 +1,278 @@
+//! A lossless token list that keeps whitespace and comments
+//!
+//! The AST drops formatting, so tools that rewrite source, like a
+//! formatter, work on this instead. Every byte of the input belongs to
+//! exactly one token or piece of trivia, so printing a `Cst` reproduces its
+//! input.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Ident,
+    Number,
+    String,
+    /// An operator or other punctuation
+    Punct,
+    /// A character that cannot start any token
+    Unknown,
+    /// The end of the input, which holds any trivia after the last token
+    Eof,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriviaKind {
+    Whitespace,
+    Newline,
+    LineComment,
+    BlockComment,
+    Shebang,
+}
+
+/// Text between tokens that does not affect the meaning of the program
+#[derive(Debug, Clone)]
+pub struct Trivia<'a> {
+    pub kind: TriviaKind,
+    pub text: &'a str,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    /// The byte offset of the token in the input
+    pub start: usize,
+    /// Trivia before the token, starting after the previous token's line
+    pub leading: Vec<Trivia<'a>>,
+    /// Trivia after the token on the same line, not including the newline
+    pub trailing: Vec<Trivia<'a>>,
+}
+
+/// The tokens of a file, ending with an `Eof` token
+#[derive(Debug, Clone)]
+pub struct Cst<'a> {
+    pub tokens: Vec<Token<'a>>,
+}
+
+impl<'a> fmt::Display for Cst<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for token in &self.tokens {
+            for trivia in &token.leading {
+                write!(f, "{}", trivia.text)?;
+            }
+            write!(f, "{}", token.text)?;
+            for trivia in &token.trailing {
+                write!(f, "{}", trivia.text)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Trivia<'a> {
+    pub fn is_comment(&self) -> bool {
+        matches!(
+            self.kind,
+            TriviaKind::LineComment | TriviaKind::BlockComment
+        )
+    }
+}
+
+impl<'a> Cst<'a> {
+    /// Get all the trivia of the file in order
+    pub fn trivia(&self) -> impl Iterator<Item = &Trivia<'a>> {
+        self.tokens
+            .iter()
+            .flat_map(|token| token.leading.iter().chain(&token.trailing))
+    }
+    /// Get all the comments of the file in order
+    pub fn comments(&self) -> impl Iterator<Item = &Trivia<'a>> {
+        self.trivia().filter(|trivia| trivia.is_comment())
+    }
+    /// Print the file with each comment replaced by spaces, keeping
+    /// newlines so that every token stays at the same line and column
+    pub fn blank_comments(&self) -> String {
+        let mut blanked = String::new();
+        for token in &self.tokens {
+            for trivia in &token.leading {
+                blanked.push_str(&blank(trivia));
+            }
+            blanked.push_str(token.text);
+            for trivia in &token.trailing {
+                blanked.push_str(&blank(trivia));
+            }
+        }
+        blanked
+    }
+}
+
+fn blank(trivia: &Trivia) -> String {
+    if trivia.is_comment() {
+        trivia
+            .text
+            .chars()
+            .map(|c| if c == '\n' { c } else { ' ' })
+            .collect()
+    } else {
+        trivia.text.into()
+    }
+}
+
+const OP_CHARS: &str = "+-*/%<>=!&^~?@$";
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_ident_tail(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+struct Lexer<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+    /// Advance past a prefix of the rest of the input and return it
+    fn take(&mut self, len: usize) -> &'a str {
+        let text = &self.input[self.pos..self.pos + len];
+        self.pos += len;
+        text
+    }
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let len = self
+            .rest()
+            .find(|c| !f(c))
+            .unwrap_or_else(|| self.rest().len());
+        self.take(len)
+    }
+    fn trivia(&mut self, allow_newlines: bool) -> Option<Trivia<'a>> {
+        let rest = self.rest();
+        let (kind, len) = if let Some(body) = rest.strip_prefix("'''") {
+            let len = body.find("'''").map_or(rest.len(), |end| end + 6);
+            (TriviaKind::BlockComment, len)
+        } else if rest.starts_with("--") {
+            (
+                TriviaKind::LineComment,
+                rest.find('\n').unwrap_or(rest.len()),
+            )
+        } else if rest.starts_with("#!") && self.pos == 0 {
+            (TriviaKind::Shebang, rest.find('\n').unwrap_or(rest.len()))
+        } else if rest.starts_with('\n') && allow_newlines {
+            (TriviaKind::Newline, 1)
+        } else if whitespace_len(rest) > 0 {
+            (TriviaKind::Whitespace, whitespace_len(rest))
+        } else {
+            return None;
+        };
+        let text = self.take(len);
+        Some(Trivia { kind, text })
+    }
+    fn token_kind(&mut self) -> Option<TokenKind> {
+        let rest = self.rest();
+        let c = self.peek()?;
+        Some(if is_ident_start(c) {
+            self.take_while(is_ident_tail);
+            TokenKind::Ident
+        } else if c.is_ascii_digit() {
+            self.take_while(|c| c.is_ascii_digit());
+            let rest = self.rest();
+            if rest.starts_with('.') && rest[1..].starts_with(|c: char| c.is_ascii_digit()) {
+                self.take(1);
+                self.take_while(|c| c.is_ascii_digit());
+                let rest = self.rest();
+                if rest.starts_with(['e', 'E']) {
+                    let sign = rest[1..].starts_with(['+', '-']) as usize;
+                    if rest[1 + sign..].starts_with(|c: char| c.is_ascii_digit()) {
+                        self.take(1 + sign);
+                        self.take_while(|c| c.is_ascii_digit());
+                    }
+                }
+            }
+            TokenKind::Number
+        } else if c == '"' {
+            let mut escaped = false;
+            let len = rest[1..]
+                .find(|c| {
+                    let end = c == '"' && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    end
+                })
+                .map_or(rest.len(), |end| end + 2);
+            self.take(len);
+            TokenKind::String
+        } else if rest.starts_with("::") {
+            self.take(2);
+            TokenKind::Punct
+        } else if OP_CHARS.contains(c) {
+            let len = rest
+                .char_indices()
+                .find(|&(i, c)| !OP_CHARS.contains(c) || rest[i..].starts_with("--"))
+                .map_or(rest.len(), |(i, _)| i);
+            self.take(len);
+            TokenKind::Punct
+        } else if "()[]{}|,:#".contains(c) {
+            self.take(1);
+            TokenKind::Punct
+        } else {
+            self.take(c.len_utf8());
+            TokenKind::Unknown
+        })
+    }
+}
+
+/// The length of the spaces, tabs, and escaped newlines at the start of a
+/// string
+fn whitespace_len(s: &str) -> usize {
+    let mut len = 0;
+    loop {
+        let rest = &s[len..];
+        len += if rest.starts_with([' ', '\t', '\r']) {
+            1
+        } else if rest.starts_with("\\\n") {
+            2
+        } else if rest.starts_with("\\\r\n") {
+            3
+        } else {
+            return len;
+        };
+    }
+}
+
+/// Split a file into tokens and trivia
+pub fn tokenize(input: &str) -> Cst<'_> {
+    let mut lexer = Lexer { input, pos: 0 };
+    let mut tokens: Vec<Token> = Vec::new();
+    loop {
+        let mut leading = Vec::new();
+        while let Some(trivia) = lexer.trivia(true) {
+            leading.push(trivia);
+        }
+        let start = lexer.pos;
+        let kind = lexer.token_kind().unwrap_or(TokenKind::Eof);
+        let text = &input[start..lexer.pos];
+        let mut trailing = Vec::new();
+        if kind != TokenKind::Eof {
+            while let Some(trivia) = lexer.trivia(false) {
+                trailing.push(trivia);
+            }
+        }
+        tokens.push(Token {
+            kind,
+            text,
+            start,
+            leading,
+            trailing,
+        });
+        if kind == TokenKind::Eof {
+            break Cst { tokens };
+        }
+    }
+}
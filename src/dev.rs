@@ -0,0 +1,363 @@
+//! Commands for developing the compiler itself
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Instant,
+};
+
+use crate::{
+    ast::{Arena, INFIX_OPERATORS},
+    cst, parse,
+    transpile::*,
+};
+
+/// The directory of programs whose generated C is snapshotted
+fn codegen_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("codegen")
+}
+
+/// Get the paths of all the Kin files in a directory, sorted by name
+fn corpus(dir: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("Unable to read {}: {}", dir.display(), e))
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "kin"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Transpile a Kin file to C source
+fn generate_c(path: &Path) -> Result<String, String> {
+    let input = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    generate_c_from(&input, path)
+}
+
+/// Transpile Kin source to C source as if it were read from a path
+fn generate_c_from(input: &str, path: &Path) -> Result<String, String> {
+    let options = CompileOptions {
+        debug_codegen: true,
+        ..CompileOptions::default()
+    };
+    let arena = Arena::default();
+    let items = parse::parse(input, &arena, &options).map_err(|errors| {
+        errors
+            .into_iter()
+            .map(|error| error.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    })?;
+    let mut source = Vec::new();
+    transpile(items, input, &arena, &options)
+        .write_source(&mut source, path)
+        .map_err(|e| e.to_string())?;
+    Ok(String::from_utf8(source).unwrap())
+}
+
+/// Overwrite the snapshot of each program with its current generated C
+pub fn bless_codegen() -> bool {
+    let mut success = true;
+    for path in corpus(&codegen_dir()) {
+        match generate_c(&path) {
+            Ok(source) => {
+                fs::write(path.with_extension("c"), source).unwrap();
+                println!("blessed {}", path.display());
+            }
+            Err(error) => {
+                println!("failed {}:\n{}", path.display(), error);
+                success = false;
+            }
+        }
+    }
+    success
+}
+
+/// Compare the generated C of each program to its snapshot
+pub fn check_codegen() -> bool {
+    let mut failures = 0;
+    let paths = corpus(&codegen_dir());
+    for path in &paths {
+        let snapshot_path = path.with_extension("c");
+        let snapshot = fs::read_to_string(&snapshot_path).unwrap_or_default();
+        match generate_c(path) {
+            Ok(source) if source == snapshot => {}
+            Ok(source) => {
+                failures += 1;
+                let end = Some("<end of file>");
+                let difference = snapshot
+                    .lines()
+                    .chain(end)
+                    .zip(source.lines().chain(end))
+                    .enumerate()
+                    .find(|(_, (expected, found))| expected != found);
+                if let Some((line, (expected, found))) = difference {
+                    println!(
+                        "mismatch {}:{}\n  expected: {}\n  found:    {}",
+                        snapshot_path.display(),
+                        line + 1,
+                        expected,
+                        found
+                    );
+                } else {
+                    println!("mismatch {}: line endings differ", snapshot_path.display());
+                }
+            }
+            Err(error) => {
+                failures += 1;
+                println!("failed {}:\n{}", path.display(), error);
+            }
+        }
+    }
+    println!(
+        "{} of {} codegen snapshots match",
+        paths.len() - failures,
+        paths.len()
+    );
+    failures == 0
+}
+
+/// The directory of programs that are run and checked against their expected output
+fn run_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("run")
+}
+
+/// Compile and run each program, comparing its stdout to its `.expected`
+/// file and its exit code to its `.status` file, which defaults to 0
+///
+/// Programs run in parallel. Only programs whose names contain the filter
+/// are run.
+pub fn run_tests(filter: Option<&str>) -> bool {
+    let paths: Vec<PathBuf> = corpus(&run_dir())
+        .into_iter()
+        .filter(|path| filter.is_none_or(|filter| test_name(path).contains(filter)))
+        .collect();
+    let exe = std::env::current_exe().unwrap();
+    let results = parallel_map(&paths, |path| run_test(&exe, path));
+    let mut failures = 0;
+    for (path, result) in paths.iter().zip(results) {
+        let name = test_name(path);
+        match result {
+            Ok(()) => println!("ok     {}", name),
+            Err(reason) => {
+                failures += 1;
+                println!("FAILED {}\n{}", name, reason);
+            }
+        }
+    }
+    println!("{} of {} tests passed", paths.len() - failures, paths.len());
+    failures == 0
+}
+
+/// Apply a function to each item on as many threads as there are cores,
+/// keeping the results in order
+pub fn parallel_map<T, R>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+{
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::new());
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= items.len() {
+                    break;
+                }
+                let result = f(&items[i]);
+                results.lock().unwrap().push((i, result));
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+fn test_name(path: &Path) -> String {
+    path.file_stem().unwrap().to_string_lossy().into_owned()
+}
+
+fn run_test(exe: &Path, path: &Path) -> Result<(), String> {
+    let expected_path = path.with_extension("expected");
+    let expected = fs::read_to_string(&expected_path)
+        .map_err(|e| format!("  unable to read {}: {}", expected_path.display(), e))?;
+    let expected_status = match fs::read_to_string(path.with_extension("status")) {
+        Ok(status) => status
+            .trim()
+            .parse::<i32>()
+            .map_err(|e| format!("  invalid status file: {}", e))?,
+        Err(_) => 0,
+    };
+    let output = Command::new(exe)
+        .arg(path)
+        .output()
+        .map_err(|e| format!("  unable to run: {}", e))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut reasons = Vec::new();
+    if stdout != expected {
+        reasons.push(format!(
+            "  expected stdout:\n{}\n  found stdout:\n{}",
+            expected, stdout
+        ));
+    }
+    let status = output.status.code();
+    if status != Some(expected_status) {
+        reasons.push(format!(
+            "  expected exit code {}, found {:?}",
+            expected_status, status
+        ));
+    }
+    if reasons.is_empty() {
+        Ok(())
+    } else {
+        Err(reasons.join("\n"))
+    }
+}
+
+/// Check that every test program survives a round trip through the
+/// lossless token list and contains no unknown tokens
+///
+/// Also check that blanking out the comments the token list finds does not
+/// change the generated C, which would mean it found a comment that the
+/// parser does not see, like one inside a string.
+pub fn check_cst() -> bool {
+    let paths: Vec<PathBuf> = corpus(&codegen_dir())
+        .into_iter()
+        .chain(corpus(&run_dir()))
+        .collect();
+    let mut failures = 0;
+    for path in &paths {
+        let input = fs::read_to_string(path).unwrap();
+        let cst = cst::tokenize(&input);
+        let unknown = cst.tokens.iter().find(|token| token.kind == cst::TokenKind::Unknown);
+        if cst.to_string() != input {
+            failures += 1;
+            println!("mismatch {}: printed tokens differ from input", path.display());
+        } else if let Some(token) = unknown {
+            failures += 1;
+            println!(
+                "unknown token in {} at byte {}: {:?}",
+                path.display(),
+                token.start,
+                token.text
+            );
+        } else if generate_c_from(&cst.blank_comments(), path) != generate_c_from(&input, path) {
+            failures += 1;
+            println!(
+                "mismatch {}: blanking {} comments changes the generated C",
+                path.display(),
+                cst.comments().count()
+            );
+        }
+    }
+    println!(
+        "{} of {} files round-trip",
+        paths.len() - failures,
+        paths.len()
+    );
+    failures == 0
+}
+
+/// Check that every runtime function the transpiler can call is defined in
+/// the runtime header
+///
+/// This covers the builtin functions and the functions that operators are
+/// lowered to, whose names are written by hand on both sides.
+pub fn check_runtime() -> bool {
+    let builtins = BUILTIN_FUNCTIONS.iter().copied();
+    let operators = INFIX_OPERATORS
+        .iter()
+        .filter_map(|&(token, op)| bin_op_fn(op).map(|(c_name, _)| (token, c_name)));
+    let functions: Vec<(&str, &str)> = builtins.chain(operators).collect();
+    let mut failures = 0;
+    for (kin_name, c_name) in &functions {
+        let defined = KIN_HEADER.contains(&format!("KinValue {}(", c_name))
+            || c_name
+                .strip_suffix("_fn")
+                .is_some_and(|base| KIN_HEADER.contains(&format!("bin_fn({});", base)));
+        if !defined {
+            failures += 1;
+            println!("undefined {}: {} is not in kin.h", kin_name, c_name);
+        }
+    }
+    println!(
+        "{} of {} runtime functions are defined",
+        functions.len() - failures,
+        functions.len()
+    );
+    failures == 0
+}
+
+/// Generates a program whose single expression has a given number of terms
+type StressProgram = fn(usize) -> String;
+
+/// Programs for checking that the compiler neither overflows its stack nor
+/// slows down quadratically on huge expressions
+static STRESS_PROGRAMS: &[(&str, StressProgram)] = &[
+    // Starting with a real keeps constant folding from collapsing the chain
+    ("sum chain", |n| {
+        format!("x = 1.0{}\nprintln x", " + 1".repeat(n - 1))
+    }),
+    ("nested parens", |n| {
+        format!("x = {}1.0{}\nprintln x", "(".repeat(n), " + 1)".repeat(n))
+    }),
+    ("power chain", |n| {
+        format!("x = 1{}\nprintln x", " ^ 1".repeat(n - 1))
+    }),
+    ("not chain", |n| {
+        format!("x = {}true\nprintln x", "not ".repeat(n))
+    }),
+];
+
+/// The number of terms in the smaller version of each stress program
+const STRESS_SIZE: usize = 10_000;
+
+/// Transpile each stress program at two sizes and check that doubling the
+/// size does not much more than double the time
+pub fn stress() -> bool {
+    let path = Path::new("stress.kin");
+    let mut failures = 0;
+    for (name, program) in STRESS_PROGRAMS {
+        let mut times = Vec::new();
+        for size in [STRESS_SIZE, 2 * STRESS_SIZE] {
+            let start = Instant::now();
+            if let Err(e) = generate_c_from(&program(size), path) {
+                println!("FAILED {} of {} terms\n{}", name, size, e);
+                failures += 1;
+                break;
+            }
+            times.push(start.elapsed());
+        }
+        if let [small, large] = times[..] {
+            let ratio = large.as_secs_f64() / small.as_secs_f64().max(0.05);
+            if ratio > 3.0 {
+                failures += 1;
+                println!(
+                    "FAILED {}: {:?} for {} terms but {:?} for {}",
+                    name,
+                    small,
+                    STRESS_SIZE,
+                    large,
+                    2 * STRESS_SIZE
+                );
+            } else {
+                println!("ok     {} ({:?} for {} terms)", name, large, 2 * STRESS_SIZE);
+            }
+        }
+    }
+    println!(
+        "{} of {} stress programs passed",
+        STRESS_PROGRAMS.len() - failures,
+        STRESS_PROGRAMS.len()
+    );
+    failures == 0
+}
@@ -0,0 +1,226 @@
+//! Comparing the structure of two files while ignoring formatting
+
+use std::path::Path;
+
+use crate::{ast::*, visit::*};
+
+/// Renders the structure of some AST as a string, ignoring spans, so that
+/// two ASTs are equal exactly when their fingerprints are
+///
+/// The bodies of nested defs are left out, since defs are compared
+/// separately.
+#[derive(Default)]
+struct Fingerprint {
+    text: String,
+}
+
+impl Fingerprint {
+    fn push(&mut self, s: impl AsRef<str>) {
+        self.text.push_str(s.as_ref());
+        self.text.push(' ');
+    }
+    fn params(&mut self, params: &Params) {
+        for param in params {
+            self.push(param.ident.name);
+            if let Some(ty) = &param.ty {
+                self.push(format!("{:?}", ty.types));
+            }
+        }
+    }
+    fn of_def(def: &Def) -> String {
+        let mut fingerprint = Fingerprint::default();
+        fingerprint.params(&def.params);
+        if let Some(ret) = &def.ret {
+            fingerprint.push(format!("-> {:?}", ret.types));
+        }
+        walk_items(&mut fingerprint, &def.items);
+        fingerprint.text
+    }
+    fn of_item(item: &Item) -> String {
+        let mut fingerprint = Fingerprint::default();
+        fingerprint.visit_item(item);
+        fingerprint.text
+    }
+}
+
+impl<'a> Visit<'a> for Fingerprint {
+    fn visit_item(&mut self, item: &Item<'a>) {
+        match item {
+            Item::Defer(_) => self.push("defer"),
+            Item::Discard(_) => self.push("discard"),
+            Item::Return(_) => self.push("return"),
+            Item::Node(_) | Item::Def(_) => {}
+        }
+        walk_item(self, item);
+    }
+    fn visit_def(&mut self, def: &Def<'a>) {
+        self.push("def");
+        self.push(def.ident.name);
+        self.params(&def.params);
+    }
+    fn visit_node(&mut self, node: &Node<'a>) {
+        match &node.kind {
+            NodeKind::Term(..) => {}
+            NodeKind::BinExpr(expr) => self.push(format!("({:?}", expr.op)),
+            NodeKind::UnExpr(expr) => self.push(format!("({:?}", expr.op)),
+            NodeKind::Call(expr) => self.push(format!("(call{}", expr.args.len())),
+            NodeKind::CmpChain(chain) => {
+                let ops: Vec<_> = chain
+                    .ops
+                    .iter()
+                    .map(|(op, _)| format!("{:?}", op))
+                    .collect();
+                self.push(format!("({}", ops.join(" ")));
+            }
+        }
+        walk_node(self, node);
+        if !matches!(node.kind, NodeKind::Term(..)) {
+            self.push(")");
+        }
+    }
+    fn visit_term(&mut self, term: &Term<'a>, _span: &pest::Span<'a>) {
+        match term {
+            Term::Int(i) => self.push(i.to_string()),
+            Term::Real(r) => self.push(format!("{:?}", r)),
+            Term::String(s) => self.push(format!("{:?}", s)),
+            Term::Expr(_) => self.push("(paren"),
+            Term::Tree(_) => self.push("(tree"),
+            Term::Ident(_) | Term::Closure(_) => {}
+        }
+        walk_term(self, term);
+        if let Term::Expr(_) | Term::Tree(_) = term {
+            self.push(")");
+        }
+    }
+    fn visit_closure(&mut self, closure: &Closure<'a>) {
+        self.push("(|");
+        self.params(&closure.params);
+        self.push("|");
+        walk_items(self, &closure.body);
+        self.push(")");
+    }
+    fn visit_ident(&mut self, ident: &Ident<'a>) {
+        self.push(ident.name);
+    }
+}
+
+/// A def found anywhere in a file, named by the defs it is nested in
+struct DefEntry {
+    path: String,
+    line: usize,
+    fingerprint: String,
+}
+
+#[derive(Default)]
+struct DefCollector {
+    scope: Vec<String>,
+    defs: Vec<DefEntry>,
+}
+
+impl<'a> Visit<'a> for DefCollector {
+    fn visit_def(&mut self, def: &Def<'a>) {
+        self.scope.push(def.ident.name.into());
+        self.defs.push(DefEntry {
+            path: self.scope.join("."),
+            line: def.ident.span.start_pos().line_col().0,
+            fingerprint: Fingerprint::of_def(def),
+        });
+        walk_def(self, def);
+        self.scope.pop();
+    }
+}
+
+/// A top-level expression
+struct ExprEntry<'a> {
+    line: usize,
+    text: &'a str,
+    fingerprint: String,
+}
+
+fn exprs<'a>(items: &[Item<'a>]) -> Vec<ExprEntry<'a>> {
+    items
+        .iter()
+        .filter(|item| !matches!(item, Item::Def(_)))
+        .map(|item| {
+            let span = item.span();
+            ExprEntry {
+                line: span.start_pos().line_col().0,
+                text: span.as_str().lines().next().unwrap_or_default(),
+                fingerprint: Fingerprint::of_item(item),
+            }
+        })
+        .collect()
+}
+
+/// Print the structural differences between two files
+///
+/// Returns whether the files are structurally the same.
+pub fn diff(old_path: &Path, old_items: &[Item], new_path: &Path, new_items: &[Item]) -> bool {
+    let (old, new) = (old_path.display(), new_path.display());
+    let mut same = true;
+
+    // Defs
+    let mut old_defs = DefCollector::default();
+    walk_items(&mut old_defs, old_items);
+    let mut new_defs = DefCollector::default();
+    walk_items(&mut new_defs, new_items);
+    for old_def in &old_defs.defs {
+        match new_defs.defs.iter().find(|def| def.path == old_def.path) {
+            None => {
+                same = false;
+                println!("- def {} ({}:{})", old_def.path, old, old_def.line);
+            }
+            Some(new_def) if new_def.fingerprint != old_def.fingerprint => {
+                same = false;
+                println!(
+                    "~ def {} ({}:{}, {}:{})",
+                    old_def.path, old, old_def.line, new, new_def.line
+                );
+            }
+            Some(_) => {}
+        }
+    }
+    for new_def in &new_defs.defs {
+        if !old_defs.defs.iter().any(|def| def.path == new_def.path) {
+            same = false;
+            println!("+ def {} ({}:{})", new_def.path, new, new_def.line);
+        }
+    }
+
+    // Top-level expressions, matched by their longest common subsequence
+    let old_exprs = exprs(old_items);
+    let new_exprs = exprs(new_items);
+    let mut lengths = vec![vec![0usize; new_exprs.len() + 1]; old_exprs.len() + 1];
+    for i in (0..old_exprs.len()).rev() {
+        for j in (0..new_exprs.len()).rev() {
+            lengths[i][j] = if old_exprs[i].fingerprint == new_exprs[j].fingerprint {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < old_exprs.len() || j < new_exprs.len() {
+        if i < old_exprs.len()
+            && j < new_exprs.len()
+            && old_exprs[i].fingerprint == new_exprs[j].fingerprint
+        {
+            i += 1;
+            j += 1;
+        } else if j == new_exprs.len()
+            || i < old_exprs.len() && lengths[i + 1][j] >= lengths[i][j + 1]
+        {
+            same = false;
+            let expr = &old_exprs[i];
+            println!("- expr {}:{}: {}", old, expr.line, expr.text);
+            i += 1;
+        } else {
+            same = false;
+            let expr = &new_exprs[j];
+            println!("+ expr {}:{}: {}", new, expr.line, expr.text);
+            j += 1;
+        }
+    }
+    same
+}
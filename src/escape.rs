@@ -0,0 +1,413 @@
+//! Checking that functions do not return values that point into their own frames
+//!
+//! Lists and closures point at the values they are made from, and most of
+//! those values live on the stack. Each value is given the set of
+//! stack regions it may point into, and a function may not return a value
+//! that points into its own frame or into the frame of a function nested in
+//! it.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    rc::Rc,
+};
+
+use pest::Span;
+
+use crate::{ast::*, parse::TranspileError};
+
+/// A part of the stack that a value can point into
+///
+/// Functions are numbered by how deeply they are nested, starting with the
+/// top level of the file at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Region {
+    /// Whatever the args of the function at a depth point into
+    Through(usize),
+    /// The args of a call to the function at a depth, which are made in the
+    /// frame of the caller
+    Args(usize),
+    /// The locals of the function at a depth
+    Frame(usize),
+}
+
+/// The value that a region is pointed into at, for diagnostics
+#[derive(Debug, Clone)]
+struct Local<'a> {
+    span: Span<'a>,
+    what: String,
+}
+
+impl<'a> Local<'a> {
+    fn new(span: &Span<'a>, what: impl Into<String>) -> Self {
+        Local {
+            span: span.clone(),
+            what: what.into(),
+        }
+    }
+}
+
+type Refs<'a> = BTreeMap<Region, Local<'a>>;
+
+/// What a value may point into
+#[derive(Debug, Clone, Default)]
+struct Value<'a> {
+    refs: Refs<'a>,
+    /// What calling the value returns, if it is a known function
+    returns: Option<Rc<Returns<'a>>>,
+}
+
+impl<'a> Value<'a> {
+    fn pointing(refs: Refs<'a>) -> Self {
+        Value {
+            refs,
+            returns: None,
+        }
+    }
+}
+
+/// What the return value of a function may point into
+#[derive(Debug)]
+struct Returns<'a> {
+    /// The depth of the function's body
+    depth: usize,
+    refs: Refs<'a>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BindingKind {
+    Param,
+    Value,
+    Function { captures: bool },
+}
+
+#[derive(Debug, Clone)]
+enum Binding<'a> {
+    Local {
+        depth: usize,
+        value: Value<'a>,
+        kind: BindingKind,
+    },
+    /// A function whose body is being checked
+    Unfinished,
+}
+
+#[derive(Default)]
+struct Frame<'a> {
+    /// Whether the function captures anything from an enclosing function
+    captures: bool,
+    /// The values of the function's `return` items and what they point into
+    returns: Vec<(Span<'a>, Refs<'a>)>,
+}
+
+struct EscapeCheck<'a> {
+    scopes: Vec<HashMap<&'a str, Binding<'a>>>,
+    frames: Vec<Frame<'a>>,
+    errors: Vec<TranspileError<'a>>,
+}
+
+/// Check that no function returns a value that points into its own frame
+pub fn check<'a>(items: &[Item<'a>]) -> Vec<TranspileError<'a>> {
+    let mut check = EscapeCheck {
+        scopes: vec![HashMap::new()],
+        frames: vec![Frame::default()],
+        errors: Vec::new(),
+    };
+    check.items(items);
+    check.errors
+}
+
+fn extend<'a>(refs: &mut Refs<'a>, other: Refs<'a>) {
+    for (region, local) in other {
+        refs.entry(region).or_insert(local);
+    }
+}
+
+impl<'a> EscapeCheck<'a> {
+    fn depth(&self) -> usize {
+        self.frames.len()
+    }
+    fn bind(&mut self, name: &'a str, binding: Binding<'a>) {
+        self.scopes.last_mut().unwrap().insert(name, binding);
+    }
+    fn binding(&self, name: &str) -> Option<&Binding<'a>> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+    /// Check some items, returning the value of the last one and its span
+    fn items(&mut self, items: &[Item<'a>]) -> (Value<'a>, Option<Span<'a>>) {
+        let mut last = (Value::default(), None);
+        for item in items {
+            match item {
+                Item::Def(def) if def.is_function() => {
+                    self.bind(def.ident.name, Binding::Unfinished);
+                    let (captures, returns) = self.function(&def.params, &def.items);
+                    let mut refs = Refs::new();
+                    if captures {
+                        let what = format!("the capture list of `{}`", def.ident.name);
+                        refs.insert(
+                            Region::Frame(self.depth()),
+                            Local::new(&def.ident.span, what),
+                        );
+                    }
+                    let binding = Binding::Local {
+                        depth: self.depth(),
+                        value: Value {
+                            refs,
+                            returns: Some(returns),
+                        },
+                        kind: BindingKind::Function { captures },
+                    };
+                    self.bind(def.ident.name, binding);
+                }
+                Item::Def(def) => {
+                    let (value, _) = self.block(&def.items);
+                    let binding = Binding::Local {
+                        depth: self.depth(),
+                        value,
+                        kind: BindingKind::Value,
+                    };
+                    self.bind(def.ident.name, binding);
+                }
+                Item::Node(node) => last = (self.node(node), Some(node.kind.span().clone())),
+                Item::Defer(node) | Item::Discard(node) => {
+                    self.node(node);
+                }
+                Item::Return(node) => {
+                    let refs = self.node(node).refs;
+                    let span = node.kind.span().clone();
+                    self.frames.last_mut().unwrap().returns.push((span, refs));
+                }
+            }
+        }
+        last
+    }
+    /// Check some items in their own scope
+    fn block(&mut self, items: &[Item<'a>]) -> (Value<'a>, Option<Span<'a>>) {
+        self.scopes.push(HashMap::new());
+        let value = self.items(items);
+        self.scopes.pop();
+        value
+    }
+    /// Check the body of a function, returning whether it captures anything
+    /// and what its return value may point into
+    fn function(&mut self, params: &[Param<'a>], items: &[Item<'a>]) -> (bool, Rc<Returns<'a>>) {
+        self.frames.push(Frame::default());
+        self.scopes.push(HashMap::new());
+        let depth = self.depth();
+        for param in params {
+            let mut refs = Refs::new();
+            let what = format!("what `{}` points at", param.ident.name);
+            refs.insert(Region::Through(depth), Local::new(&param.ident.span, what));
+            let binding = Binding::Local {
+                depth,
+                value: Value::pointing(refs),
+                kind: BindingKind::Param,
+            };
+            self.bind(param.ident.name, binding);
+        }
+        let (value, span) = self.items(items);
+        self.scopes.pop();
+        let frame = self.frames.pop().unwrap();
+        // The function returns either a `return` item or its last item
+        let mut refs = Refs::new();
+        let last = span.map(|span| (span, value.refs));
+        for (span, returned) in frame.returns.into_iter().chain(last) {
+            let escaping = returned
+                .iter()
+                .find(|(region, _)| matches!(region, Region::Frame(d) if *d >= depth));
+            if let Some((_, local)) = escaping {
+                self.errors.push(TranspileError::ReturnReferencesLocal(
+                    span,
+                    local.span.clone(),
+                    local.what.clone(),
+                ));
+            }
+            extend(&mut refs, returned);
+        }
+        refs.retain(|region, _| !matches!(region, Region::Frame(d) if *d >= depth));
+        (frame.captures, Rc::new(Returns { depth, refs }))
+    }
+    fn node(&mut self, node: &Node<'a>) -> Value<'a> {
+        match &node.kind {
+            NodeKind::Term(term, _) => self.term(term),
+            NodeKind::BinExpr(expr) => match expr.op {
+                BinOp::Mom => {
+                    let head = self.node(expr.left);
+                    let tail = self.operand(expr.right);
+                    let mut refs = head.refs;
+                    extend(&mut refs, tail);
+                    Value::pointing(refs)
+                }
+                BinOp::Dad => {
+                    let left = self.operand(expr.left);
+                    let head = self.node(expr.right);
+                    let mut refs = head.refs;
+                    extend(&mut refs, left);
+                    Value::pointing(refs)
+                }
+                // These evaluate to one of their operands
+                BinOp::Or | BinOp::And | BinOp::Coalesce => {
+                    let mut refs = self.node(expr.left).refs;
+                    extend(&mut refs, self.node(expr.right).refs);
+                    Value::pointing(refs)
+                }
+                _ => {
+                    self.node(expr.left);
+                    self.node(expr.right);
+                    Value::default()
+                }
+            },
+            NodeKind::UnExpr(expr) => {
+                let inner = self.node(expr.inner);
+                match expr.op {
+                    UnOp::Head => Value::pointing(inner.refs),
+                    UnOp::Neg | UnOp::Not => Value::default(),
+                }
+            }
+            NodeKind::CmpChain(chain) => {
+                for operand in &chain.operands {
+                    self.node(operand);
+                }
+                Value::default()
+            }
+            NodeKind::Call(call) => self.call(call),
+        }
+    }
+    /// Check a node whose address is taken, returning what both it and its
+    /// address may point into
+    fn operand(&mut self, node: &Node<'a>) -> Refs<'a> {
+        let (value, storage) = match &node.kind {
+            NodeKind::Term(Term::Ident(ident), _) => self.ident(ident),
+            kind => {
+                let value = self.node(node);
+                let storage = Local::new(kind.span(), "this temporary value");
+                (value, Some((Region::Frame(self.depth()), storage)))
+            }
+        };
+        let mut refs = value.refs;
+        if let Some((region, local)) = storage {
+            refs.entry(region).or_insert(local);
+        }
+        refs
+    }
+    /// Look up the value an ident refers to and where that value is stored
+    fn ident(&mut self, ident: &Ident<'a>) -> (Value<'a>, Option<(Region, Local<'a>)>) {
+        let depth = self.depth();
+        let (bound_depth, value, kind) = match self.binding(ident.name) {
+            Some(Binding::Local { depth, value, kind }) => (*depth, value.clone(), *kind),
+            // Builtins are static and unfinished functions are never captured
+            Some(Binding::Unfinished) | None => return (Value::default(), None),
+        };
+        let captured = bound_depth < depth
+            && match kind {
+                BindingKind::Param | BindingKind::Value => true,
+                BindingKind::Function { captures } => captures,
+            };
+        let storage = if captured {
+            for frame in &mut self.frames[bound_depth..] {
+                frame.captures = true;
+            }
+            let what = format!("the captured copy of `{}`", ident.name);
+            (Region::Frame(depth - 1), Local::new(&ident.span, what))
+        } else {
+            match kind {
+                BindingKind::Param => {
+                    let what = format!("the argument list holding `{}`", ident.name);
+                    (Region::Args(depth), Local::new(&ident.span, what))
+                }
+                BindingKind::Value => {
+                    let what = format!("`{}`", ident.name);
+                    (Region::Frame(depth), Local::new(&ident.span, what))
+                }
+                BindingKind::Function { .. } => (
+                    Region::Frame(depth),
+                    Local::new(&ident.span, "this temporary value"),
+                ),
+            }
+        };
+        (value, Some(storage))
+    }
+    fn term(&mut self, term: &Term<'a>) -> Value<'a> {
+        match term {
+            Term::Expr(items) => self.block(items).0,
+            Term::Int(_) | Term::Real(_) | Term::String(_) => Value::default(),
+            Term::Ident(ident) => self.ident(ident).0,
+            // The runtime copies the nodes of a tree to the heap
+            Term::Tree(nodes) => {
+                let mut refs = Refs::new();
+                for node in nodes.iter() {
+                    extend(&mut refs, self.node(node).refs);
+                }
+                Value::pointing(refs)
+            }
+            Term::Closure(closure) => {
+                let (captures, returns) = self.function(&closure.params, &closure.body);
+                let mut refs = Refs::new();
+                if captures {
+                    refs.insert(
+                        Region::Frame(self.depth()),
+                        Local::new(&closure.span, "the capture list of this closure"),
+                    );
+                }
+                Value {
+                    refs,
+                    returns: Some(returns),
+                }
+            }
+        }
+    }
+    fn call(&mut self, call: &CallExpr<'a>) -> Value<'a> {
+        let callee = self.node(call.caller);
+        let mut args: Vec<Value> = call.args.iter().map(|arg| self.node(arg)).collect();
+        let builtin = match &call.caller.kind {
+            NodeKind::Term(Term::Ident(ident), _) if self.binding(ident.name).is_none() => {
+                Some(ident.name)
+            }
+            _ => None,
+        };
+        let returns = match builtin {
+            // These never return
+            Some("panic") | Some("exit") => return Value::default(),
+            // `recover` returns what its function returns
+            Some("recover") if args.len() == 1 && args[0].returns.is_some() => {
+                args.pop().unwrap().returns
+            }
+            // An error points at the argument list it was made from
+            Some("error") => {
+                let mut refs = Refs::new();
+                let local = Local::new(&call.span, "the argument list of this call");
+                refs.insert(Region::Frame(self.depth()), local);
+                for arg in args {
+                    extend(&mut refs, arg.refs);
+                }
+                return Value::pointing(refs);
+            }
+            _ => callee.returns.clone(),
+        };
+        let mut refs = Refs::new();
+        if let Some(returns) = returns {
+            for (region, local) in &returns.refs {
+                match *region {
+                    Region::Through(depth) if depth == returns.depth => {
+                        for arg in &args {
+                            extend(&mut refs, arg.refs.clone());
+                        }
+                    }
+                    Region::Args(depth) if depth == returns.depth => {
+                        let local = Local::new(&call.span, "the argument list of this call");
+                        refs.entry(Region::Frame(self.depth())).or_insert(local);
+                    }
+                    region => {
+                        refs.entry(region).or_insert_with(|| local.clone());
+                    }
+                }
+            }
+        } else {
+            // Anything the callee can reach may be returned
+            refs = callee.refs;
+            for arg in args {
+                extend(&mut refs, arg.refs);
+            }
+        }
+        Value::pointing(refs)
+    }
+}
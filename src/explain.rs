@@ -0,0 +1,57 @@
+//! Extended descriptions of compile errors and lints
+
+use crate::lint::LINTS;
+
+/// The explanation of each error code
+static EXPLANATIONS: &[(&str, &str)] = &[
+    ("E0001", include_str!("explanations/E0001.md")),
+    ("E0002", include_str!("explanations/E0002.md")),
+    ("E0003", include_str!("explanations/E0003.md")),
+    ("E0004", include_str!("explanations/E0004.md")),
+    ("E0005", include_str!("explanations/E0005.md")),
+    ("E0006", include_str!("explanations/E0006.md")),
+    ("E0007", include_str!("explanations/E0007.md")),
+    ("E0008", include_str!("explanations/E0008.md")),
+    ("E0009", include_str!("explanations/E0009.md")),
+    ("E0010", include_str!("explanations/E0010.md")),
+    ("E0011", include_str!("explanations/E0011.md")),
+    ("E0012", include_str!("explanations/E0012.md")),
+    ("E0013", include_str!("explanations/E0013.md")),
+    ("E0014", include_str!("explanations/E0014.md")),
+    ("E0015", include_str!("explanations/E0015.md")),
+    ("E0016", include_str!("explanations/E0016.md")),
+];
+
+/// Print the explanation of an error code or lint name, or a summary of
+/// every one if none is given
+///
+/// Returns false if the code is unknown.
+pub fn explain(code: Option<&str>) -> bool {
+    let lints = LINTS.iter().map(|lint| (lint.name, lint.explanation));
+    match code {
+        Some(code) => {
+            let upper = code.to_uppercase();
+            let explanation = EXPLANATIONS
+                .iter()
+                .copied()
+                .find(|(c, _)| *c == upper)
+                .or_else(|| lints.clone().find(|(name, _)| *name == code));
+            match explanation {
+                Some((_, text)) => {
+                    print!("{}", text);
+                    true
+                }
+                None => {
+                    eprintln!("Unknown error code: {}", code);
+                    false
+                }
+            }
+        }
+        None => {
+            for (code, text) in EXPLANATIONS.iter().copied().chain(lints) {
+                println!("{}: {}", code, text.lines().next().unwrap_or_default());
+            }
+            true
+        }
+    }
+}
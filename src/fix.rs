@@ -0,0 +1,60 @@
+//! Applying the suggested fixes of compile errors
+
+use std::{fs, path::Path};
+
+use crate::{ast::Arena, parse, transpile::CompileOptions};
+
+/// Apply every machine-applicable fix to a file in place
+///
+/// Fixes are applied until none remain, in case fixing one error reveals
+/// another. Guesses, like similar names, are left in the printed errors as
+/// hints. Returns whether the file compiles afterwards.
+pub fn fix_file(path: &Path, options: &CompileOptions) -> bool {
+    let mut input = fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Unable to read {}: {}", path.display(), e);
+        std::process::exit(1);
+    });
+    let mut applied = 0;
+    let remaining = loop {
+        let arena = Arena::default();
+        let errors = match parse::parse(&input, &arena, options) {
+            Ok(_) => break Vec::new(),
+            Err(errors) => errors,
+        };
+        let mut suggestions: Vec<_> = errors
+            .iter()
+            .filter_map(|e| e.suggestion())
+            .filter(|suggestion| suggestion.machine_applicable)
+            .collect();
+        if suggestions.is_empty() {
+            break errors.iter().map(ToString::to_string).collect();
+        }
+        // Apply from the end so earlier ranges stay valid, skipping overlaps
+        suggestions.sort_by_key(|suggestion| suggestion.start);
+        let mut fixed = input.clone();
+        let mut end = input.len() + 1;
+        for suggestion in suggestions.into_iter().rev() {
+            if suggestion.end <= end {
+                fixed.replace_range(suggestion.start..suggestion.end, &suggestion.replacement);
+                end = suggestion.start;
+                applied += 1;
+            }
+        }
+        input = fixed;
+    };
+    if applied > 0 {
+        fs::write(path, &input).unwrap_or_else(|e| {
+            eprintln!("Unable to write {}: {}", path.display(), e);
+            std::process::exit(1);
+        });
+    }
+    println!(
+        "Applied {} fix{}",
+        applied,
+        if applied == 1 { "" } else { "es" }
+    );
+    for error in &remaining {
+        eprintln!("{}", error);
+    }
+    remaining.is_empty()
+}
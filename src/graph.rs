@@ -0,0 +1,213 @@
+//! Finding which defs and closures refer to which
+
+use std::{collections::HashMap, str::FromStr};
+
+use pest::Span;
+
+use crate::{ast::*, metrics::json_string, visit::*};
+
+/// The format a graph is printed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    Dot,
+    Json,
+}
+
+impl FromStr for GraphFormat {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(GraphFormat::Dot),
+            "json" => Ok(GraphFormat::Json),
+            _ => Err(anyhow::anyhow!(
+                "Unknown graph format {:?}. Expected dot or json",
+                s
+            )),
+        }
+    }
+}
+
+/// A def, a closure, or the top level of the file
+pub struct GraphNode {
+    /// The name of the node, prefixed by the defs it is nested in
+    pub path: String,
+    pub kind: &'static str,
+    pub line: usize,
+}
+
+/// The defs and closures of a file and the references between them
+///
+/// There is an edge from a node to every def it mentions by name, and to
+/// every closure it creates.
+pub struct Graph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<(usize, usize)>,
+}
+
+/// Build the graph of some items
+pub fn graph(items: &[Item]) -> Graph {
+    let mut builder = Builder {
+        graph: Graph {
+            nodes: vec![GraphNode {
+                path: "<main>".into(),
+                kind: "main",
+                line: 1,
+            }],
+            edges: Vec::new(),
+        },
+        frames: Vec::new(),
+        current: 0,
+    };
+    builder.scope(items, &[]);
+    builder.graph
+}
+
+struct Builder<'a> {
+    graph: Graph,
+    /// The names in scope, innermost last. Defs map to their nodes, and
+    /// params map to `None` because they hide defs.
+    frames: Vec<HashMap<&'a str, Option<usize>>>,
+    /// The node whose body is being walked
+    current: usize,
+}
+
+impl<'a> Builder<'a> {
+    fn add_node(&mut self, name: &str, kind: &'static str, span: &Span<'a>) -> usize {
+        let path = if self.current == 0 {
+            name.into()
+        } else {
+            format!("{}.{}", self.graph.nodes[self.current].path, name)
+        };
+        self.graph.nodes.push(GraphNode {
+            path,
+            kind,
+            line: span.start_pos().line_col().0,
+        });
+        self.graph.nodes.len() - 1
+    }
+    fn add_edge(&mut self, to: usize) {
+        let edge = (self.current, to);
+        if !self.graph.edges.contains(&edge) {
+            self.graph.edges.push(edge);
+        }
+    }
+    /// Walk the items of a body with its params in scope
+    ///
+    /// Defs come into scope as they are walked, like in the parser.
+    fn scope(&mut self, items: &[Item<'a>], params: &[Param<'a>]) {
+        let frame = params
+            .iter()
+            .map(|param| (param.ident.name, None))
+            .collect();
+        self.frames.push(frame);
+        walk_items(self, items);
+        self.frames.pop();
+    }
+    fn bind(&mut self, name: &'a str, node: usize) {
+        self.frames.last_mut().unwrap().insert(name, Some(node));
+    }
+    /// Walk a body as the current node
+    fn enter(&mut self, node: usize, items: &[Item<'a>], params: &[Param<'a>]) {
+        let outer = self.current;
+        self.current = node;
+        self.scope(items, params);
+        self.current = outer;
+    }
+}
+
+impl<'a> Visit<'a> for Builder<'a> {
+    fn visit_def(&mut self, def: &Def<'a>) {
+        let ident = &def.ident;
+        // A def of a name that is already in this scope is a separate node,
+        // named by its line
+        let redefined = self.frames.last().unwrap().contains_key(ident.name);
+        let node = if redefined {
+            let line = ident.span.start_pos().line_col().0;
+            self.add_node(&format!("{}@{}", ident.name, line), "def", &ident.span)
+        } else {
+            self.add_node(ident.name, "def", &ident.span)
+        };
+        // Functions can refer to themselves, but values refer to whatever
+        // the name meant before them
+        if def.params.is_empty() {
+            self.enter(node, &def.items, &def.params);
+            self.bind(ident.name, node);
+        } else {
+            self.bind(ident.name, node);
+            self.enter(node, &def.items, &def.params);
+        }
+    }
+    fn visit_term(&mut self, term: &Term<'a>, _span: &Span<'a>) {
+        match term {
+            Term::Expr(items) => self.scope(items, &[]),
+            _ => walk_term(self, term),
+        }
+    }
+    fn visit_closure(&mut self, closure: &Closure<'a>) {
+        let (line, col) = closure.span.start_pos().line_col();
+        let name = format!("<closure {}:{}>", line, col);
+        let node = self.add_node(&name, "closure", &closure.span);
+        self.add_edge(node);
+        self.enter(node, &closure.body, &closure.params);
+    }
+    fn visit_ident(&mut self, ident: &Ident<'a>) {
+        let binding = self
+            .frames
+            .iter()
+            .rev()
+            .find_map(|frame| frame.get(ident.name));
+        if let Some(&Some(node)) = binding {
+            self.add_edge(node);
+        }
+    }
+}
+
+impl Graph {
+    /// Print the graph in the Graphviz DOT language
+    pub fn print_dot(&self) {
+        println!("digraph kin {{");
+        for (i, node) in self.nodes.iter().enumerate() {
+            let style = if node.kind == "closure" {
+                ", style=dashed"
+            } else {
+                ""
+            };
+            println!("    n{} [label={}{}];", i, json_string(&node.path), style);
+        }
+        for (from, to) in &self.edges {
+            println!("    n{} -> n{};", from, to);
+        }
+        println!("}}");
+    }
+    /// Print the graph as a JSON object with a list of nodes and a list of
+    /// edges between their names
+    pub fn print_json(&self) {
+        let nodes: Vec<String> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                format!(
+                    r#"    {{"name": {}, "kind": "{}", "line": {}}}"#,
+                    json_string(&node.path),
+                    node.kind,
+                    node.line
+                )
+            })
+            .collect();
+        let edges: Vec<String> = self
+            .edges
+            .iter()
+            .map(|&(from, to)| {
+                format!(
+                    r#"    {{"from": {}, "to": {}}}"#,
+                    json_string(&self.nodes[from].path),
+                    json_string(&self.nodes[to].path)
+                )
+            })
+            .collect();
+        println!("{{");
+        println!("  \"nodes\": [\n{}\n  ],", nodes.join(",\n"));
+        println!("  \"edges\": [\n{}\n  ]", edges.join(",\n"));
+        println!("}}");
+    }
+}
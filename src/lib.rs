@@ -0,0 +1,19 @@
+//! The pipeline that checks Kin source and transpiles it to C
+//!
+//! The `kin` binary drives it from the command line. Without the `native`
+//! feature, nothing here reads files or runs processes, so it can be built for
+//! `wasm32-unknown-unknown`.
+
+#![allow(unstable_name_collisions)]
+
+pub mod ast;
+pub mod const_fold;
+pub mod escape;
+pub mod lint;
+#[cfg(feature = "native")]
+pub mod manifest;
+pub mod parse;
+pub mod transpile;
+pub mod visit;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,485 @@
+//! Checks for suspicious code that still compiles
+//!
+//! Each lint has a name and a default level. Levels can be changed in the
+//! `[lints]` section of the closest `kin.toml` to the source file, and then
+//! by the `--allow`, `--warn`, and `--deny` flags.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    mem::take,
+    str::FromStr,
+};
+
+#[cfg(feature = "native")]
+use std::path::Path;
+
+use pest::Span;
+
+use crate::{
+    ast::*,
+    parse::format_span,
+    transpile::{CompileOptions, BUILTIN_FUNCTIONS, BUILTIN_VALUES},
+    visit::*,
+};
+
+#[cfg(feature = "native")]
+use crate::manifest::Manifest;
+
+/// How a lint is reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl FromStr for Level {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(Level::Allow),
+            "warn" => Ok(Level::Warn),
+            "deny" => Ok(Level::Deny),
+            _ => Err(format!(
+                "Unknown lint level {:?}. Expected allow, warn, or deny",
+                s
+            )),
+        }
+    }
+}
+
+/// A problem found by a lint
+pub struct Finding<'a> {
+    pub span: Span<'a>,
+    pub message: String,
+}
+
+/// A lint that can be run on a file
+pub struct Lint {
+    pub name: &'static str,
+    pub default: Level,
+    /// A longer description with examples, shown by `kin explain`
+    pub explanation: &'static str,
+    check: for<'a> fn(&[Item<'a>]) -> Vec<Finding<'a>>,
+}
+
+/// Every lint
+pub static LINTS: &[Lint] = &[
+    Lint {
+        name: "shadowed_builtin",
+        default: Level::Warn,
+        explanation: include_str!("explanations/shadowed_builtin.md"),
+        check: shadowed_builtin,
+    },
+    Lint {
+        name: "shadowed_def",
+        default: Level::Warn,
+        explanation: include_str!("explanations/shadowed_def.md"),
+        check: shadowed_def,
+    },
+    Lint {
+        name: "deprecated",
+        default: Level::Warn,
+        explanation: include_str!("explanations/deprecated.md"),
+        check: deprecated,
+    },
+    Lint {
+        name: "closure_depth",
+        default: Level::Warn,
+        explanation: include_str!("explanations/closure_depth.md"),
+        check: closure_depth,
+    },
+    Lint {
+        name: "unused_result",
+        default: Level::Warn,
+        explanation: include_str!("explanations/unused_result.md"),
+        check: unused_result,
+    },
+];
+
+/// A finding at the level its lint is set to
+pub struct Report<'a> {
+    pub lint: &'static Lint,
+    pub level: Level,
+    pub finding: Finding<'a>,
+}
+
+impl<'a> fmt::Display for Report<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let level = if self.level == Level::Deny {
+            "error"
+        } else {
+            "warning"
+        };
+        writeln!(f, "{}[{}]", level, self.lint.name)?;
+        format_span(self.finding.message.clone(), self.finding.span.clone(), f)
+    }
+}
+
+/// The level of each lint, in the same order as `LINTS`
+pub struct Levels(Vec<Level>);
+
+impl Default for Levels {
+    fn default() -> Self {
+        Levels(LINTS.iter().map(|lint| lint.default).collect())
+    }
+}
+
+impl Levels {
+    /// Get the levels of the lints for a source file
+    ///
+    /// Problems with the configuration are returned as messages. They are not
+    /// fatal.
+    #[cfg(feature = "native")]
+    pub fn load(source_path: &Path, options: &CompileOptions) -> (Self, Vec<String>) {
+        let mut levels = Levels::default();
+        let mut problems = Vec::new();
+        let dir = source_path.parent().unwrap_or_else(|| Path::new(""));
+        match Manifest::find(dir) {
+            Ok(Some(manifest)) => {
+                for entry in manifest
+                    .sections("lints")
+                    .flat_map(|section| &section.entries)
+                {
+                    if let Err(e) = entry
+                        .value
+                        .parse()
+                        .and_then(|level| levels.set(&entry.key, level))
+                    {
+                        problems.push(format!("{}:{}: {}", manifest.path.display(), entry.line, e));
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(e) => problems.push(e),
+        }
+        levels.set_flags(options, &mut problems);
+        (levels, problems)
+    }
+    /// Get the levels of the lints from the defaults and the flags alone
+    ///
+    /// This is for source that has no file, and so no `kin.toml`.
+    pub fn from_options(options: &CompileOptions) -> (Self, Vec<String>) {
+        let mut levels = Levels::default();
+        let mut problems = Vec::new();
+        levels.set_flags(options, &mut problems);
+        (levels, problems)
+    }
+    fn set_flags(&mut self, options: &CompileOptions, problems: &mut Vec<String>) {
+        let flags = [
+            (&options.allow, Level::Allow),
+            (&options.warn, Level::Warn),
+            (&options.deny, Level::Deny),
+        ];
+        for (names, level) in flags.iter() {
+            for name in names.iter() {
+                if let Err(e) = self.set(name, *level) {
+                    problems.push(e);
+                }
+            }
+        }
+    }
+    fn set(&mut self, name: &str, level: Level) -> Result<(), String> {
+        let i = LINTS
+            .iter()
+            .position(|lint| lint.name == name)
+            .ok_or_else(|| format!("Unknown lint {:?}", name))?;
+        self.0[i] = level;
+        Ok(())
+    }
+}
+
+/// Run every lint that is not allowed
+pub fn lint<'a>(items: &[Item<'a>], levels: &Levels) -> Vec<Report<'a>> {
+    let mut reports = Vec::new();
+    for (lint, &level) in LINTS.iter().zip(&levels.0) {
+        if level == Level::Allow {
+            continue;
+        }
+        for finding in (lint.check)(items) {
+            reports.push(Report {
+                lint,
+                level,
+                finding,
+            });
+        }
+    }
+    reports.sort_by_key(|report| report.finding.span.start());
+    reports
+}
+
+/// Defs with the same name as a builtin
+struct ShadowedBuiltin<'a> {
+    findings: Vec<Finding<'a>>,
+}
+
+fn shadowed_builtin<'a>(items: &[Item<'a>]) -> Vec<Finding<'a>> {
+    let mut lint = ShadowedBuiltin {
+        findings: Vec::new(),
+    };
+    walk_items(&mut lint, items);
+    lint.findings
+}
+
+impl<'a> Visit<'a> for ShadowedBuiltin<'a> {
+    fn visit_def(&mut self, def: &Def<'a>) {
+        let is_builtin = BUILTIN_FUNCTIONS
+            .iter()
+            .chain(BUILTIN_VALUES)
+            .any(|&(name, _)| name == def.ident.name);
+        if is_builtin {
+            self.findings.push(Finding {
+                span: def.ident.span.clone(),
+                message: format!("{} shadows a builtin", def.ident.name),
+            });
+        }
+        walk_def(self, def)
+    }
+}
+
+/// Defs that hide a def or param from an enclosing scope
+///
+/// Params may hide outer names without being reported, since giving a param
+/// the name of an outer def is common and the param's scope is clear. Binding
+/// a name again in the same scope is not reported, because the old binding can
+/// no longer be used there anyway.
+struct ShadowedDef<'a> {
+    /// The names bound in each scope, innermost last
+    scopes: Vec<HashSet<&'a str>>,
+    findings: Vec<Finding<'a>>,
+}
+
+fn shadowed_def<'a>(items: &[Item<'a>]) -> Vec<Finding<'a>> {
+    let mut lint = ShadowedDef {
+        scopes: vec![HashSet::new()],
+        findings: Vec::new(),
+    };
+    walk_items(&mut lint, items);
+    lint.findings
+}
+
+impl<'a> ShadowedDef<'a> {
+    fn bind(&mut self, ident: &Ident<'a>) {
+        let (inner, outer) = self.scopes.split_last_mut().unwrap();
+        if !inner.contains(ident.name) && outer.iter().any(|scope| scope.contains(ident.name)) {
+            self.findings.push(Finding {
+                span: ident.span.clone(),
+                message: format!("{} shadows a name from an enclosing scope", ident.name),
+            });
+        }
+        inner.insert(ident.name);
+    }
+    fn scope(&mut self, params: &[Param<'a>], items: &[Item<'a>]) {
+        self.scopes
+            .push(params.iter().map(|param| param.ident.name).collect());
+        walk_items(self, items);
+        self.scopes.pop();
+    }
+}
+
+impl<'a> Visit<'a> for ShadowedDef<'a> {
+    fn visit_def(&mut self, def: &Def<'a>) {
+        if def.is_function() {
+            self.bind(&def.ident);
+            self.scope(&def.params, &def.items);
+        } else {
+            self.scope(&[], &def.items);
+            self.bind(&def.ident);
+        }
+    }
+    fn visit_term(&mut self, term: &Term<'a>, _span: &Span<'a>) {
+        match term {
+            Term::Expr(items) => self.scope(&[], items),
+            _ => walk_term(self, term),
+        }
+    }
+    fn visit_closure(&mut self, closure: &Closure<'a>) {
+        self.scope(&closure.params, &closure.body)
+    }
+}
+
+/// Uses of defs marked `#[deprecated]`
+///
+/// Uses inside a deprecated def itself are not reported.
+struct Deprecated<'a> {
+    /// The deprecation note of each name bound in each scope, innermost last
+    scopes: Vec<HashMap<&'a str, Option<String>>>,
+    findings: Vec<Finding<'a>>,
+}
+
+fn deprecated<'a>(items: &[Item<'a>]) -> Vec<Finding<'a>> {
+    let mut lint = Deprecated {
+        scopes: vec![HashMap::new()],
+        findings: Vec::new(),
+    };
+    walk_items(&mut lint, items);
+    lint.findings
+}
+
+impl<'a> Deprecated<'a> {
+    fn scope(&mut self, params: &[Param<'a>], items: &[Item<'a>]) {
+        self.scopes
+            .push(params.iter().map(|param| (param.ident.name, None)).collect());
+        walk_items(self, items);
+        self.scopes.pop();
+    }
+}
+
+impl<'a> Visit<'a> for Deprecated<'a> {
+    fn visit_def(&mut self, def: &Def<'a>) {
+        self.scope(&def.params, &def.items);
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(def.ident.name, def.deprecated.clone());
+    }
+    fn visit_term(&mut self, term: &Term<'a>, _span: &Span<'a>) {
+        match term {
+            Term::Expr(items) => self.scope(&[], items),
+            _ => walk_term(self, term),
+        }
+    }
+    fn visit_closure(&mut self, closure: &Closure<'a>) {
+        self.scope(&closure.params, &closure.body)
+    }
+    fn visit_ident(&mut self, ident: &Ident<'a>) {
+        let note = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(ident.name));
+        if let Some(Some(note)) = note {
+            let message = if note.is_empty() {
+                format!("{} is deprecated", ident.name)
+            } else {
+                format!("{} is deprecated: {}", ident.name, note)
+            };
+            self.findings.push(Finding {
+                span: ident.span.clone(),
+                message,
+            });
+        }
+    }
+}
+
+/// The deepest closures can be nested before they are reported
+const MAX_CLOSURE_DEPTH: usize = 3;
+
+/// Closures nested too deeply to follow
+struct ClosureDepth<'a> {
+    depth: usize,
+    findings: Vec<Finding<'a>>,
+}
+
+fn closure_depth<'a>(items: &[Item<'a>]) -> Vec<Finding<'a>> {
+    let mut lint = ClosureDepth {
+        depth: 0,
+        findings: Vec::new(),
+    };
+    walk_items(&mut lint, items);
+    lint.findings
+}
+
+impl<'a> Visit<'a> for ClosureDepth<'a> {
+    fn visit_closure(&mut self, closure: &Closure<'a>) {
+        self.depth += 1;
+        if self.depth == MAX_CLOSURE_DEPTH + 1 {
+            self.findings.push(Finding {
+                span: closure.span.clone(),
+                message: format!(
+                    "Closures are nested {} deep, more than the limit of {}",
+                    self.depth, MAX_CLOSURE_DEPTH
+                ),
+            });
+        }
+        walk_closure(self, closure);
+        self.depth -= 1;
+    }
+}
+
+/// Expressions without calls whose values are thrown away
+struct UnusedResult<'a> {
+    findings: Vec<Finding<'a>>,
+    /// Whether each node has a call
+    calls: HashMap<NodeId, bool>,
+}
+
+fn unused_result<'a>(items: &[Item<'a>]) -> Vec<Finding<'a>> {
+    let mut calls = HasCall::default();
+    walk_items(&mut calls, items);
+    let mut lint = UnusedResult {
+        findings: Vec::new(),
+        calls: calls.nodes,
+    };
+    lint.check_items(items);
+    lint.findings
+}
+
+impl<'a> UnusedResult<'a> {
+    fn check_items(&mut self, items: &[Item<'a>]) {
+        let discarded = items.len().saturating_sub(1);
+        for item in &items[..discarded] {
+            if let Item::Node(node) = item {
+                if !self.calls[&node.id] {
+                    self.findings.push(Finding {
+                        span: node.kind.span().clone(),
+                        message: "The result of this expression is unused".into(),
+                    });
+                }
+            }
+        }
+        walk_items(self, items);
+    }
+}
+
+impl<'a> Visit<'a> for UnusedResult<'a> {
+    fn visit_def(&mut self, def: &Def<'a>) {
+        self.check_items(&def.items)
+    }
+    fn visit_term(&mut self, term: &Term<'a>, _span: &Span<'a>) {
+        match term {
+            Term::Expr(items) => self.check_items(items),
+            _ => walk_term(self, term),
+        }
+    }
+    fn visit_closure(&mut self, closure: &Closure<'a>) {
+        self.check_items(&closure.body)
+    }
+}
+
+/// Finds whether each node calls anything or returns, which might have side
+/// effects
+///
+/// Every node is walked once, so nested blocks are not walked again for each
+/// block around them.
+#[derive(Default)]
+struct HasCall {
+    nodes: HashMap<NodeId, bool>,
+    /// Whether the node being walked has a call so far
+    found: bool,
+}
+
+impl<'a> Visit<'a> for HasCall {
+    fn visit_item(&mut self, item: &Item<'a>) {
+        if let Item::Return(_) = item {
+            self.found = true;
+        }
+        walk_item(self, item)
+    }
+    fn visit_node(&mut self, node: &Node<'a>) {
+        let outer = take(&mut self.found);
+        if let NodeKind::Call(_) = node.kind {
+            self.found = true;
+        }
+        walk_node(self, node);
+        self.nodes.insert(node.id, self.found);
+        self.found |= outer;
+    }
+    /// Calls in a closure do not happen when it is made
+    fn visit_closure(&mut self, closure: &Closure<'a>) {
+        let outer = take(&mut self.found);
+        walk_closure(self, closure);
+        self.found = outer;
+    }
+}
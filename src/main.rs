@@ -1,119 +1,847 @@
 #![allow(unstable_name_collisions)]
 
-mod ast;
-mod parse;
-mod transpile;
+mod cst;
+mod dev;
+mod diff;
+mod explain;
+mod fix;
+mod graph;
+mod metrics;
+mod outline;
+mod spec;
+mod stats;
 
-use std::str::FromStr;
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use clap::Clap;
 
-fn main() {
-    use std::process::*;
+use kin::{ast, lint, manifest, parse, transpile, visit};
+use transpile::CompileOptions;
 
-    use transpile::*;
+/// The stack size of the compiler thread
+///
+/// Pest and the AST passes recurse into nested expressions, so give them
+/// plenty of room. The memory is only committed as it is touched.
+const COMPILER_STACK_SIZE: usize = 1024 * 1024 * 1024;
 
+fn main() {
     color_backtrace::install();
 
     let app = App::parse();
 
-    // Parse and check
-    let input = std::fs::read_to_string("test.kin").unwrap();
-    let items = match parse::parse(&input) {
+    let compiler = std::thread::Builder::new()
+        .name("compiler".into())
+        .stack_size(COMPILER_STACK_SIZE)
+        .spawn(move || compile(app))
+        .unwrap();
+    if compiler.join().is_err() {
+        std::process::exit(1);
+    }
+}
+
+fn compile(app: App) {
+    use std::process::*;
+
+    if let Some(script) = &app.script {
+        run_script(script);
+        return;
+    }
+    let sub = if let Some(sub) = &app.sub {
+        sub
+    } else {
+        eprintln!("Expected a subcommand or a file to run. Use --help for usage.");
+        exit(1);
+    };
+    match sub {
+        Sub::Cache(CacheArgs {
+            command: CacheCommand::Clean,
+        }) => {
+            clean_cache();
+            return;
+        }
+        Sub::Stats(StatsArgs { command }) => {
+            let result = match command {
+                None => {
+                    stats::print_summary();
+                    Ok(())
+                }
+                Some(StatsCommand::Enable) => stats::enable(),
+                Some(StatsCommand::Disable) => stats::disable(),
+            };
+            if let Err(e) = result {
+                eprintln!("Unable to change {}: {}", stats::path().display(), e);
+                exit(1);
+            }
+            return;
+        }
+        Sub::Fix(FixArgs { path, options }) => {
+            exit(if fix::fix_file(path, options) { 0 } else { 1 });
+        }
+        Sub::Outline(OutlineArgs { path, options }) => {
+            let input = read_source(path);
+            let arena = ast::Arena::default();
+            let items = parse_or_exit(&input, &arena, options);
+            outline::print_outline(&outline::outline(&items), 0);
+            return;
+        }
+        Sub::Metrics(MetricsArgs {
+            path,
+            json,
+            options,
+        }) => {
+            let input = read_source(path);
+            let arena = ast::Arena::default();
+            let items = parse_or_exit(&input, &arena, options);
+            let functions = metrics::metrics(&items);
+            if *json {
+                metrics::print_json(&functions);
+            } else {
+                metrics::print_table(&functions);
+            }
+            return;
+        }
+        Sub::Graph(GraphArgs {
+            path,
+            format,
+            options,
+        }) => {
+            let input = read_source(path);
+            let arena = ast::Arena::default();
+            let items = parse_or_exit(&input, &arena, options);
+            let graph = graph::graph(&items);
+            match format {
+                graph::GraphFormat::Dot => graph.print_dot(),
+                graph::GraphFormat::Json => graph.print_json(),
+            }
+            return;
+        }
+        Sub::AstDiff(AstDiffArgs { old, new, options }) => {
+            let (old_input, new_input) = (read_source(old), read_source(new));
+            let (old_arena, new_arena) = (ast::Arena::default(), ast::Arena::default());
+            let old_items = parse_or_exit(&old_input, &old_arena, options);
+            let new_items = parse_or_exit(&new_input, &new_arena, options);
+            exit(if diff::diff(old, &old_items, new, &new_items) { 0 } else { 1 });
+        }
+        Sub::Explain(ExplainArgs { code }) => {
+            exit(if explain::explain(code.as_deref()) { 0 } else { 1 });
+        }
+        Sub::Dev(DevArgs { command }) => {
+            let success = match command {
+                DevCommand::BlessCodegen => dev::bless_codegen(),
+                DevCommand::CheckCodegen => dev::check_codegen(),
+                DevCommand::CheckCst => dev::check_cst(),
+                DevCommand::CheckRuntime => dev::check_runtime(),
+                DevCommand::Spec => spec::run(),
+                DevCommand::Stress => dev::stress(),
+                DevCommand::RunTests { filter } => dev::run_tests(filter.as_deref()),
+            };
+            exit(if success { 0 } else { 1 });
+        }
+        _ => {}
+    }
+
+    let manifest = match manifest::Manifest::load(Path::new("")) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+    let targets = targets(sub, manifest.as_ref());
+    if let Some(manifest) = &manifest {
+        run_build_step(manifest);
+    }
+    for target in &targets {
+        if targets.len() > 1 {
+            println!("{}", target.name);
+        }
+        compile_file(&target.input, &target.build_dir, &target.exe, sub, true);
+    }
+
+    // Run
+    if !matches!(sub, Sub::Run(_)) {
+        return;
+    }
+    let exe_path = match targets.as_slice() {
+        [target] => &target.exe,
+        _ => {
+            eprintln!("There are multiple bins. Use --bin to choose one to run.");
+            exit(1);
+        }
+    };
+    println!();
+    let run_status = Command::new(Path::new(".").join(exe_path))
+        .spawn()
+        .unwrap()
+        .wait()
+        .unwrap();
+    if !run_status.success() {
+        exit(run_status.code().unwrap_or(1));
+    }
+}
+
+/// A file to compile and where to put its outputs
+struct Target {
+    name: String,
+    input: PathBuf,
+    build_dir: PathBuf,
+    exe: PathBuf,
+}
+
+/// Get the targets that a subcommand compiles
+///
+/// A path given on the command line is compiled on its own. Otherwise these
+/// are the `[[bin]]`s of the `kin.toml` in the current directory, or only the
+/// one selected with `--bin`. Without any bins, `test.kin` is compiled.
+///
+/// `--output` and `--build-dir` replace where the outputs of the one target
+/// go.
+fn targets(sub: &Sub, manifest: Option<&manifest::Manifest>) -> Vec<Target> {
+    use std::process::exit;
+
+    let mut targets = default_targets(sub, manifest);
+    let args = if let Some(args) = sub.build_args() {
+        args
+    } else {
+        return targets;
+    };
+    if args.output.is_none() && args.build_dir.is_none() {
+        return targets;
+    }
+    let target = match targets.as_mut_slice() {
+        [target] => target,
+        _ => {
+            eprintln!("--output and --build-dir need a single bin. Use --bin to choose one.");
+            exit(1);
+        }
+    };
+    if let Some(output) = &args.output {
+        target.exe = output.clone();
+    }
+    if let Some(build_dir) = &args.build_dir {
+        target.build_dir = build_dir.clone();
+    }
+    targets
+}
+
+/// Get the targets that a subcommand compiles before any outputs are replaced
+fn default_targets(sub: &Sub, manifest: Option<&manifest::Manifest>) -> Vec<Target> {
+    use std::process::exit;
+
+    let selected = sub.build_args().and_then(|args| args.bin.as_deref());
+    if let Some(path) = sub.path() {
+        if selected.is_some() {
+            eprintln!("--bin cannot be used with a file path");
+            exit(1);
+        }
+        if !path.is_file() {
+            eprintln!("There is no file at {}", path.display());
+            exit(1);
+        }
+        let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(name) => name.to_string(),
+            None => {
+                eprintln!("Unable to name a binary after {}", path.display());
+                exit(1);
+            }
+        };
+        return vec![Target {
+            build_dir: Path::new("build").join(&name),
+            exe: exe_file(&name),
+            input: path.into(),
+            name,
+        }];
+    }
+    let bins = match manifest.map_or(Ok(Vec::new()), |manifest| manifest.bins()) {
+        Ok(bins) => bins,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+    if bins.is_empty() {
+        if let Some(name) = selected {
+            eprintln!("There is no bin named {:?} because {} declares no bins", name, manifest::FILE_NAME);
+            exit(1);
+        }
+        return vec![Target {
+            name: "test".into(),
+            input: "test.kin".into(),
+            build_dir: "build".into(),
+            exe: exe_file("test"),
+        }];
+    }
+    let names: Vec<&str> = bins.iter().map(|bin| bin.name.as_str()).collect();
+    if let Some(name) = selected.filter(|name| !names.contains(name)) {
+        eprintln!("There is no bin named {:?}. The bins are: {}", name, names.join(", "));
+        exit(1);
+    }
+    bins.into_iter()
+        .filter(|bin| selected.is_none_or(|name| bin.name == name))
+        .map(|bin| Target {
+            build_dir: Path::new("build").join(&bin.name),
+            exe: exe_file(&bin.name),
+            input: bin.path,
+            name: bin.name,
+        })
+        .collect()
+}
+
+/// Run the `[build]` step of a manifest, if it has one
+///
+/// The step is either a shell `command` or a Kin `script`, and it runs before
+/// any source is read, so files it generates are compiled and cached like
+/// any others. Exits the process if the step fails.
+fn run_build_step(manifest: &manifest::Manifest) {
+    use std::process::*;
+
+    let section = if let Some(section) = manifest.sections("build").next() {
+        section
+    } else {
+        return;
+    };
+    let mut command = match (section.get("command"), section.get("script")) {
+        (Some(command), None) => {
+            let mut shell = if cfg!(windows) {
+                Command::new("cmd")
+            } else {
+                Command::new("sh")
+            };
+            shell.arg(if cfg!(windows) { "/C" } else { "-c" }).arg(command);
+            shell
+        }
+        (None, Some(script)) => {
+            let mut kin = Command::new(std::env::current_exe().unwrap());
+            kin.arg(script);
+            kin
+        }
+        _ => {
+            eprintln!(
+                "{}:{}: [build] needs either a `command` or a `script`",
+                manifest.path.display(),
+                section.line
+            );
+            exit(1);
+        }
+    };
+    let status = command.status().unwrap_or_else(|e| {
+        eprintln!("Unable to run the build step: {}", e);
+        exit(1);
+    });
+    if !status.success() {
+        eprintln!("The build step failed");
+        exit(status.code().unwrap_or(1));
+    }
+}
+
+/// Read a source file, exiting the process if it cannot be read
+fn read_source(path: &Path) -> String {
+    std::fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Unable to read {}: {}", path.display(), e);
+        std::process::exit(1);
+    })
+}
+
+/// Parse a source file for a tool, exiting the process if it has errors
+fn parse_or_exit<'a>(
+    input: &'a str,
+    arena: &'a ast::Arena<'a>,
+    options: &CompileOptions,
+) -> ast::Items<'a> {
+    match parse::parse(input, arena, options) {
         Ok(items) => items,
         Err(errors) => {
             for error in errors {
-                println!("{}", error)
+                eprintln!("{}", error)
             }
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Check, transpile, and build a Kin file as far as the subcommand requires
+///
+/// Exits the process if any step fails.
+fn compile_file(input_path: &Path, build_dir: &Path, exe_path: &Path, sub: &Sub, verbose: bool) {
+    use std::process::*;
+
+    use transpile::*;
+
+    // Parse and check
+    let input = read_source(input_path);
+    if let Some(dir) = exe_path.parent().filter(|_| sub.build_args().is_some()) {
+        std::fs::create_dir_all(dir).unwrap_or_else(|e| {
+            eprintln!("Unable to create {}: {}", dir.display(), e);
             exit(1);
+        });
+    }
+
+    let mut options = sub.options().clone();
+    if sub.build_args().is_some_and(|args| args.sanitize.is_some()) {
+        options.line_directives = true;
+    }
+    let options = &options;
+    let session = stats::Session::start(&input);
+    let fail = |codes: &[&str]| -> ! {
+        session.finish(codes);
+        if options.attach_stats {
+            stats::print_report();
         }
+        exit(1)
     };
-    println!("Check succeeded");
+    let arena = ast::Arena::default();
+    let items = match parse::parse(&input, &arena, options) {
+        Ok(items) => items,
+        Err(errors) => {
+            for error in &errors {
+                eprintln!("{}", error)
+            }
+            eprintln!(
+                "For more information about an error, try `kin explain {}`",
+                errors[0].code()
+            );
+            fail(&errors.iter().map(|error| error.code()).collect::<Vec<_>>());
+        }
+    };
+    let (levels, problems) = lint::Levels::load(input_path, options);
+    for problem in problems {
+        eprintln!("{}", problem)
+    }
+    let reports = lint::lint(&items, &levels);
+    for report in &reports {
+        eprintln!("{}", report)
+    }
+    let denied: Vec<&str> = reports
+        .iter()
+        .filter(|report| report.level == lint::Level::Deny)
+        .map(|report| report.lint.name)
+        .collect();
+    if !denied.is_empty() {
+        fail(&denied);
+    }
+    if verbose {
+        println!("Check succeeded");
+    }
+
+    // Reuse a cached binary if nothing has changed. This comes after the
+    // check so that lint levels, which are not part of the key, still apply.
+    let cached_exe = sub
+        .build_args()
+        .filter(|args| !args.no_cache && !args.assembly && !args.emit_build_files)
+        .map(|args| cached_exe_path(&input, args));
+    if let Some(cached_exe) = &cached_exe {
+        if cached_exe != exe_path && cached_exe.exists() {
+            std::fs::copy(cached_exe, exe_path).unwrap();
+            if verbose {
+                println!("Using cached build");
+            }
+            session.finish(&[]);
+            return;
+        }
+    }
 
     // Transpile
-    if !app.sub.transpiles() {
+    if !sub.transpiles() {
+        session.finish(&[]);
         return;
     }
-    let transpilation = transpile(items);
-    transpilation.write().unwrap();
-    println!("Transpilation succeeded");
+    let transpilation = transpile(items, &input, &arena, options);
+    transpilation.write(build_dir, input_path).unwrap();
+    if verbose {
+        println!("Transpilation succeeded");
+    }
 
     // Compile
-    let build_args = if let Some(args) = app.sub.build_args() {
+    let build_args = if let Some(args) = sub.build_args() {
         args
     } else {
+        session.finish(&[]);
         return;
     };
 
-    let ccomp = build_args.compiler.unwrap_or_else(CCompiler::find);
-
-    let mut args: Vec<String> = vec!["build/main.c".into(), "-o".into()];
+    let ccomp = build_args
+        .chosen_compiler()
+        .unwrap_or_else(CCompiler::find);
+    if build_args.assembly && !ccomp.is_gcc_like() {
+        eprintln!("{} cannot write assembly", ccomp.name());
+        fail(&["cc"]);
+    }
+    for flag in unsupported_flags(build_args, &ccomp) {
+        eprintln!("warning: {} does not support {}, so it is ignored", ccomp.name(), flag);
+    }
 
-    let name = "test";
+    let mut args: Vec<String> = vec![
+        build_dir.join("main.c").to_string_lossy().into_owned(),
+        "-o".into(),
+    ];
 
     // Push target arg
     if build_args.assembly {
-        args.push(format!("{}.asm", name));
+        let exe_path = exe_path.to_string_lossy();
+        let stem = exe_path.strip_suffix(EXE_EXT).unwrap_or(&exe_path);
+        args.push(format!("{}.asm", stem));
         args.push("-S".into());
     } else {
-        args.push(format!("{}{}", name, EXE_EXT));
+        args.push(exe_path.to_string_lossy().into_owned());
+    }
+
+    args.extend(c_flags(build_args, &ccomp, build_dir));
+
+    // Write the build files
+    if build_args.emit_build_files {
+        write_build_files(build_dir, exe_path, build_args, &ccomp).unwrap();
+    }
+
+    // Link the math library
+    args.push("-lm".into());
+
+    let compile_status = match Command::new(ccomp.name()).args(args).status() {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("Unable to run {}: {}", ccomp.name(), e);
+            fail(&["cc"]);
+        }
+    };
+    if !compile_status.success() {
+        fail(&["cc"]);
+    }
+    session.finish(&[]);
+    if verbose {
+        println!("Compilation succeeded");
     }
 
+    // Cache the binary
+    if let Some(cached_exe) = cached_exe.filter(|path| path != exe_path) {
+        let _ = std::fs::create_dir_all(cached_exe.parent().unwrap())
+            .and_then(|_| std::fs::copy(exe_path, cached_exe));
+    }
+}
+
+/// The build args that a compiler has no flags for
+fn unsupported_flags(build_args: &BuildArgs, ccomp: &CCompiler) -> Vec<&'static str> {
+    let mut flags = Vec::new();
+    if build_args.stack_size.is_some() && ccomp.stack_size_arg(0).is_none() {
+        flags.push("--stack");
+    }
+    if !ccomp.is_gcc_like() {
+        let gcc_flags = [
+            (build_args.profile, "--profile"),
+            (build_args.sanitize.is_some(), "--sanitize"),
+            (build_args.reproducible, "--reproducible"),
+        ];
+        flags.extend(gcc_flags.iter().filter(|(used, _)| *used).map(|&(_, flag)| flag));
+    }
+    flags
+}
+
+/// The flags for compiling generated C, other than the input, output, and
+/// libraries
+///
+/// Build args that the compiler does not support are left out.
+fn c_flags(build_args: &BuildArgs, ccomp: &CCompiler, build_dir: &Path) -> Vec<String> {
+    let mut flags = Vec::new();
+
     // Push opt arg
-    args.push("-O3".into());
+    flags.push(if build_args.sanitize.is_some() { "-O1" } else { "-O3" }.into());
 
     // Push C standard arg
-    args.push("-std=c99".into());
+    flags.push("-std=c99".into());
 
     // Push stack size arg
     if let Some(size) = build_args.stack_size {
-        args.push(ccomp.stack_size_arg(size * 1024 * 1024));
+        flags.extend(ccomp.stack_size_arg(size * 1024 * 1024));
+    }
+
+    // Push call depth arg
+    if let Some(depth) = build_args.max_call_depth {
+        flags.push(format!("-DKIN_MAX_CALL_DEPTH={}", depth));
+    }
+
+    // Push leak check arg
+    if build_args.leak_check {
+        flags.push("-DKIN_LEAK_CHECK".into());
+    }
+
+    // The rest are only understood by GCC and Clang
+    if !ccomp.is_gcc_like() {
+        return flags;
     }
 
     // Push profile arg
     if build_args.profile {
-        args.push("-pg".into());
+        flags.push("-pg".into());
     }
 
-    let compile_status = Command::new(ccomp.name())
-        .args(args)
-        .spawn()
-        .unwrap()
-        .wait()
-        .unwrap();
-    if compile_status.success() {
-        println!("Compilation succeeded");
-    } else {
-        exit(1);
+    // Push sanitizer args
+    if let Some(sanitizers) = &build_args.sanitize {
+        flags.push(format!("-fsanitize={}", sanitizers));
+        flags.push("-fno-omit-frame-pointer".into());
+        flags.push("-g".into());
     }
 
-    // Run
-    if !matches!(app.sub, Sub::Run(_)) {
-        return;
+    // Push reproducibility args
+    if build_args.reproducible {
+        flags.push("-frandom-seed=kin".into());
+        flags.push(format!("-ffile-prefix-map={}=.", build_dir.display()));
     }
-    println!();
-    let run_status = Command::new("./test").spawn().unwrap().wait().unwrap();
-    if !run_status.success() {
+
+    flags
+}
+
+/// Write a Makefile and a CMakeLists.txt to the build directory that compile
+/// its C into an executable without Kin
+fn write_build_files(
+    build_dir: &Path,
+    exe_path: &Path,
+    build_args: &BuildArgs,
+    ccomp: &CCompiler,
+) -> std::io::Result<()> {
+    let name = exe_path.file_name().unwrap().to_string_lossy();
+    let flags = c_flags(build_args, ccomp, build_dir).join(" ");
+    std::fs::write(
+        build_dir.join("Makefile"),
+        format!(
+            "# Generated by kin\n\
+             CC = {cc}\n\
+             KIN_FLAGS = {flags}\n\
+             \n\
+             {name}: main.c kin.h\n\
+             \t$(CC) main.c -o $@ $(KIN_FLAGS) $(CFLAGS) -lm\n\
+             \n\
+             clean:\n\
+             \trm -f {name}\n\
+             \n\
+             .PHONY: clean\n",
+            cc = ccomp.name(),
+            flags = flags,
+            name = name
+        ),
+    )?;
+    std::fs::write(
+        build_dir.join("CMakeLists.txt"),
+        format!(
+            "# Generated by kin\n\
+             cmake_minimum_required(VERSION 3.13)\n\
+             project({name} C)\n\
+             \n\
+             separate_arguments(KIN_FLAGS UNIX_COMMAND \"{flags}\")\n\
+             add_executable({name} main.c kin.h)\n\
+             target_compile_options({name} PRIVATE ${{KIN_FLAGS}})\n\
+             target_link_options({name} PRIVATE ${{KIN_FLAGS}})\n\
+             if(NOT MSVC)\n\
+             \x20   target_link_libraries({name} m)\n\
+             endif()\n",
+            flags = flags,
+            name = name
+        ),
+    )
+}
+
+/// The directory where compiled binaries are cached
+fn cache_dir() -> PathBuf {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir);
+    base.join("kin")
+}
+
+/// Get the path that the binary for a source would be cached at
+///
+/// The path is keyed on the hash of the source, the runtime, the compiler
+/// version, and the build args, so changing any of them misses the cache.
+fn cached_exe_path(input: &str, build_args: &BuildArgs) -> PathBuf {
+    use std::{
+        collections::hash_map::DefaultHasher,
+        hash::{Hash, Hasher},
+    };
+
+    let mut hasher = DefaultHasher::new();
+    input.hash(&mut hasher);
+    transpile::KIN_HEADER.hash(&mut hasher);
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    build_args.options.hash(&mut hasher);
+    build_args.stack_size.hash(&mut hasher);
+    build_args.chosen_compiler().hash(&mut hasher);
+    build_args.profile.hash(&mut hasher);
+    build_args.max_call_depth.hash(&mut hasher);
+    build_args.reproducible.hash(&mut hasher);
+    build_args.sanitize.hash(&mut hasher);
+    build_args.leak_check.hash(&mut hasher);
+    cache_dir()
+        .join(format!("{:016x}", hasher.finish()))
+        .join(exe_file("main"))
+}
+
+fn clean_cache() {
+    let dir = cache_dir();
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir).unwrap_or_else(|e| {
+            eprintln!("Unable to remove {}: {}", dir.display(), e);
+            std::process::exit(1);
+        });
+    }
+    println!("Cleaned {}", dir.display());
+}
+
+/// Compile a script to a cached binary and run it
+fn run_script(path: &Path) {
+    use std::process::*;
+
+    let input = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Unable to read {}: {}", path.display(), e);
         exit(1);
+    });
+    let build_args = BuildArgs::default();
+    let exe_path = cached_exe_path(&input, &build_args);
+    if !exe_path.exists() {
+        let build_dir = exe_path.parent().unwrap();
+        compile_file(path, build_dir, &exe_path, &Sub::Build(build_args), false);
     }
+    let run_status = Command::new(&exe_path).spawn().unwrap().wait().unwrap();
+    exit(run_status.code().unwrap_or(1));
 }
 
 #[derive(Clap)]
 struct App {
     #[clap(subcommand)]
-    sub: Sub,
+    sub: Option<Sub>,
+    #[clap(about = "A Kin file to compile and run as a script")]
+    script: Option<PathBuf>,
 }
 
 #[derive(Clap)]
 enum Sub {
     #[clap(alias = "c")]
-    Check,
+    Check(CheckArgs),
     #[clap(alias = "t")]
-    Trans,
+    Trans(CheckArgs),
     #[clap(alias = "b")]
     Build(BuildArgs),
     #[clap(alias = "r")]
     Run(BuildArgs),
+    Cache(CacheArgs),
+    #[clap(about = "Show the stats recorded about local compiles")]
+    Stats(StatsArgs),
+    #[clap(about = "Apply the suggested fixes for errors in a file")]
+    Fix(FixArgs),
+    #[clap(about = "List the defs and closures in a file with their line and column ranges")]
+    Outline(OutlineArgs),
+    #[clap(about = "Print the line count, cyclomatic complexity, and nesting depth of each function in a file")]
+    Metrics(MetricsArgs),
+    #[clap(about = "Print the graph of which defs and closures refer to which")]
+    Graph(GraphArgs),
+    #[clap(about = "Print the defs and expressions that differ between two files, ignoring formatting")]
+    AstDiff(AstDiffArgs),
+    #[clap(about = "Print an extended description of an error code")]
+    Explain(ExplainArgs),
+    Dev(DevArgs),
+}
+
+#[derive(Clap)]
+struct CacheArgs {
+    #[clap(subcommand)]
+    command: CacheCommand,
+}
+
+#[derive(Clap)]
+enum CacheCommand {
+    #[clap(about = "Remove all cached binaries")]
+    Clean,
+}
+
+#[derive(Clap)]
+struct StatsArgs {
+    #[clap(subcommand)]
+    command: Option<StatsCommand>,
+}
+
+#[derive(Clap)]
+enum StatsCommand {
+    #[clap(about = "Start recording compile times, source sizes, and error codes on this machine")]
+    Enable,
+    #[clap(about = "Stop recording stats and delete them")]
+    Disable,
+}
+
+#[derive(Clap)]
+struct FixArgs {
+    path: PathBuf,
+    #[clap(flatten)]
+    options: CompileOptions,
+}
+
+#[derive(Clap)]
+struct OutlineArgs {
+    path: PathBuf,
+    #[clap(flatten)]
+    options: CompileOptions,
+}
+
+#[derive(Clap)]
+struct MetricsArgs {
+    path: PathBuf,
+    #[clap(long = "json", about = "Print the metrics as JSON")]
+    json: bool,
+    #[clap(flatten)]
+    options: CompileOptions,
+}
+
+#[derive(Clap)]
+struct GraphArgs {
+    path: PathBuf,
+    #[clap(
+        long = "format",
+        default_value = "dot",
+        about = "The output format, dot or json"
+    )]
+    format: graph::GraphFormat,
+    #[clap(flatten)]
+    options: CompileOptions,
+}
+
+#[derive(Clap)]
+struct AstDiffArgs {
+    old: PathBuf,
+    new: PathBuf,
+    #[clap(flatten)]
+    options: CompileOptions,
+}
+
+#[derive(Clap)]
+struct ExplainArgs {
+    #[clap(about = "The error code, like E0001. Lists every code if omitted.")]
+    code: Option<String>,
+}
+
+#[derive(Clap)]
+struct DevArgs {
+    #[clap(subcommand)]
+    command: DevCommand,
+}
+
+#[derive(Clap)]
+enum DevCommand {
+    #[clap(about = "Overwrite the codegen snapshots in tests/codegen")]
+    BlessCodegen,
+    #[clap(about = "Compare generated C to the codegen snapshots in tests/codegen")]
+    CheckCodegen,
+    #[clap(about = "Check that the test programs round-trip through the lossless token list")]
+    CheckCst,
+    #[clap(about = "Check that every builtin and operator function is defined by the runtime")]
+    CheckRuntime,
+    #[clap(about = "Run the grammar spec cases and print the grammar rules they cover")]
+    Spec,
+    #[clap(about = "Transpile huge generated expressions and check that the time scales linearly")]
+    Stress,
+    #[clap(about = "Run the programs in tests/run and check their output")]
+    RunTests {
+        #[clap(long = "filter", about = "Only run tests whose names contain this")]
+        filter: Option<String>,
+    },
 }
 
 impl Sub {
@@ -123,66 +851,193 @@ impl Sub {
             _ => None,
         }
     }
+    fn path(&self) -> Option<&Path> {
+        match self {
+            Sub::Check(args) | Sub::Trans(args) => args.path.as_deref(),
+            Sub::Build(args) | Sub::Run(args) => args.path.as_deref(),
+            _ => None,
+        }
+    }
+    fn options(&self) -> &CompileOptions {
+        match self {
+            Sub::Check(args) | Sub::Trans(args) => &args.options,
+            Sub::Build(args) | Sub::Run(args) => &args.options,
+            Sub::Cache(_)
+            | Sub::Stats(_)
+            | Sub::Fix(_)
+            | Sub::Outline(_)
+            | Sub::Metrics(_)
+            | Sub::Graph(_)
+            | Sub::AstDiff(_)
+            | Sub::Explain(_)
+            | Sub::Dev(_) => {
+                unreachable!("this subcommand does not compile")
+            }
+        }
+    }
     fn transpiles(&self) -> bool {
-        !matches!(self, Sub::Check)
+        matches!(self, Sub::Trans(_) | Sub::Build(_) | Sub::Run(_))
     }
 }
 
 #[derive(Clap)]
+struct CheckArgs {
+    #[clap(about = "The Kin file to compile instead of the bins in kin.toml or test.kin")]
+    path: Option<PathBuf>,
+    #[clap(flatten)]
+    options: CompileOptions,
+}
+
+#[derive(Clap, Default)]
 struct BuildArgs {
+    #[clap(about = "The Kin file to compile instead of the bins in kin.toml or test.kin")]
+    path: Option<PathBuf>,
+    #[clap(flatten)]
+    options: CompileOptions,
+    #[clap(long = "bin", about = "Only build the bin with this name from kin.toml")]
+    bin: Option<String>,
+    #[clap(short = 'o', long = "output", about = "The path to write the executable to")]
+    output: Option<PathBuf>,
+    #[clap(long = "build-dir", about = "The directory to write the generated C to")]
+    build_dir: Option<PathBuf>,
     #[clap(long = "stack", about = "The executable stack size in MB")]
     stack_size: Option<usize>,
-    #[clap(about = "The C compiler to use")]
+    #[clap(
+        long = "cc",
+        about = "The C compiler to use, like gcc, clang, cc, tcc, or a path. Defaults to $KIN_CC."
+    )]
     compiler: Option<CCompiler>,
     #[clap(long = "asm")]
     assembly: bool,
+    #[clap(
+        long = "emit-build-files",
+        about = "Also write a Makefile and a CMakeLists.txt that compile the generated C"
+    )]
+    emit_build_files: bool,
     #[clap(long = "profile")]
     profile: bool,
+    #[clap(
+        long = "max-call-depth",
+        about = "The maximum call depth before the program panics"
+    )]
+    max_call_depth: Option<usize>,
+    #[clap(
+        long = "reproducible",
+        about = "Keep build paths and random seeds out of the binary so identical input builds identically"
+    )]
+    reproducible: bool,
+    #[clap(
+        long = "sanitize",
+        about = "Build with C sanitizers, like address,undefined, which report Kin source lines"
+    )]
+    sanitize: Option<String>,
+    #[clap(
+        long = "leak-check",
+        about = "Report memory that was never freed and where it was allocated when the program exits"
+    )]
+    leak_check: bool,
+    #[clap(long = "no-cache", about = "Always recompile instead of using a cached binary")]
+    no_cache: bool,
+}
+
+impl BuildArgs {
+    /// The C compiler chosen with `--cc` or `KIN_CC`, if any
+    fn chosen_compiler(&self) -> Option<CCompiler> {
+        self.compiler.clone().or_else(|| {
+            let var = std::env::var("KIN_CC").ok().filter(|var| !var.is_empty())?;
+            match var.parse() {
+                Ok(compiler) => Some(compiler),
+                Err(e) => {
+                    eprintln!("KIN_CC: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        })
+    }
 }
 
 const EXE_EXT: &str = if cfg!(windows) { ".exe" } else { "" };
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum CCompiler {
+/// The file name of the binary with some name
+///
+/// The extension is appended rather than set, so that dots in the name,
+/// like in `v1.2`, are kept.
+fn exe_file(name: &str) -> PathBuf {
+    format!("{}{}", name, EXE_EXT).into()
+}
+
+/// The families of C compilers, which differ in the arguments they take
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum CCompilerKind {
+    /// GCC and compilers that take the same arguments, like most `cc`s
     Gcc,
     Clang,
+    Tcc,
+}
+
+/// A C compiler command and the family of arguments it takes
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CCompiler {
+    command: String,
+    kind: CCompilerKind,
 }
 
 impl CCompiler {
-    const ALL: &'static [Self] = &[CCompiler::Gcc, CCompiler::Clang];
+    /// The compilers that are looked for when none is chosen, in order
+    const SEARCHED: &'static [&'static str] = &["gcc", "clang", "cc", "tcc"];
     fn find() -> Self {
         use std::process::*;
-        for &comp in Self::ALL {
-            if Command::new(comp.name())
+        for &command in Self::SEARCHED {
+            if Command::new(command)
                 .arg("-v")
                 .output()
-                .map_or(false, |output| output.status.success())
+                .is_ok_and(|output| output.status.success())
             {
-                return comp;
+                return command.parse().unwrap();
             }
         }
-        println!("No compatible C compiler detected.");
+        eprintln!("No compatible C compiler detected.");
         exit(1)
     }
-    pub fn name(&self) -> &'static str {
-        ["gcc", "clang"][*self as usize]
+    pub fn name(&self) -> &str {
+        &self.command
     }
-    pub fn stack_size_arg(&self, size: usize) -> String {
-        match self {
-            CCompiler::Gcc => format!("-Wl,--stack,{}", size),
-            CCompiler::Clang => format!("-Wl,-stack:{}", size),
+    pub fn stack_size_arg(&self, size: usize) -> Option<String> {
+        match self.kind {
+            CCompilerKind::Gcc => Some(format!("-Wl,--stack,{}", size)),
+            CCompilerKind::Clang => Some(format!("-Wl,-stack:{}", size)),
+            CCompilerKind::Tcc => None,
         }
     }
+    /// Whether the compiler takes the optional flags that GCC and Clang share,
+    /// like sanitizers, profiling, and path remapping
+    pub fn is_gcc_like(&self) -> bool {
+        self.kind != CCompilerKind::Tcc
+    }
 }
 
 impl FromStr for CCompiler {
     type Err = anyhow::Error;
+    /// Parse a compiler name or path
+    ///
+    /// The kind is guessed from the file name, and unknown compilers are
+    /// assumed to take GCC's arguments.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         use anyhow::anyhow;
-        Self::ALL
-            .iter()
-            .find(|comp| comp.name() == s)
-            .copied()
-            .ok_or_else(|| anyhow!("Unsupported or unknown compiler {:?}", s))
+        let file_name = Path::new(s)
+            .file_stem()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| anyhow!("Invalid compiler {:?}", s))?;
+        let kind = if file_name.contains("clang") {
+            CCompilerKind::Clang
+        } else if file_name.contains("tcc") {
+            CCompilerKind::Tcc
+        } else {
+            CCompilerKind::Gcc
+        };
+        Ok(CCompiler {
+            command: s.into(),
+            kind,
+        })
     }
 }
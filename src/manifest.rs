@@ -0,0 +1,169 @@
+//! Reading the `kin.toml` project manifest
+//!
+//! Only the parts of TOML that the manifest uses are supported: `[table]`
+//! and `[[array]]` headers, and `key = value` lines whose values are strings
+//! or bare words. `#` starts a comment.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The name of the manifest file
+pub const FILE_NAME: &str = "kin.toml";
+
+/// A `key = value` line
+pub struct Entry {
+    pub line: usize,
+    pub key: String,
+    pub value: String,
+}
+
+/// A `[table]` or an element of an `[[array]]` and the entries under it
+pub struct Section {
+    pub name: String,
+    pub line: usize,
+    pub entries: Vec<Entry>,
+}
+
+impl Section {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|entry| entry.key == key)
+            .map(|entry| entry.value.as_str())
+    }
+}
+
+pub struct Manifest {
+    pub path: PathBuf,
+    pub sections: Vec<Section>,
+}
+
+/// An executable built from its own entry file
+pub struct Bin {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl Manifest {
+    /// Load the manifest in a directory, if there is one
+    pub fn load(dir: &Path) -> Result<Option<Self>, String> {
+        let path = dir.join(FILE_NAME);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Unable to read {}: {}", path.display(), e)),
+        };
+        let sections = parse(&text).map_err(|e| format!("{}:{}", path.display(), e))?;
+        Ok(Some(Manifest { path, sections }))
+    }
+    /// Load the manifest in a directory or the closest of its ancestors
+    pub fn find(dir: &Path) -> Result<Option<Self>, String> {
+        for dir in dir.ancestors() {
+            if let Some(manifest) = Manifest::load(dir)? {
+                return Ok(Some(manifest));
+            }
+        }
+        Ok(None)
+    }
+    /// Get the sections with a name
+    pub fn sections<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Section> + 'a {
+        self.sections
+            .iter()
+            .filter(move |section| section.name == name)
+    }
+    /// Get the `[[bin]]` targets, with paths relative to the manifest
+    pub fn bins(&self) -> Result<Vec<Bin>, String> {
+        let dir = self.path.parent().unwrap_or_else(|| Path::new(""));
+        let mut bins: Vec<Bin> = Vec::new();
+        for section in self.sections("bin") {
+            let field = |key| {
+                section.get(key).ok_or_else(|| {
+                    format!(
+                        "{}:{}: [[bin]] is missing `{}`",
+                        self.path.display(),
+                        section.line,
+                        key
+                    )
+                })
+            };
+            let name = field("name")?;
+            if bins.iter().any(|bin| bin.name == name) {
+                return Err(format!(
+                    "{}:{}: there is already a bin named {:?}",
+                    self.path.display(),
+                    section.line,
+                    name
+                ));
+            }
+            bins.push(Bin {
+                name: name.into(),
+                path: dir.join(field("path")?),
+            });
+        }
+        Ok(bins)
+    }
+}
+
+/// Parse the text of a manifest into its sections
+///
+/// Entries before the first header go in a section with an empty name.
+fn parse(text: &str) -> Result<Vec<Section>, String> {
+    let mut sections = vec![Section {
+        name: String::new(),
+        line: 1,
+        entries: Vec::new(),
+    }];
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let header = line
+            .strip_prefix("[[")
+            .and_then(|line| line.strip_suffix("]]"))
+            .or_else(|| {
+                line.strip_prefix('[')
+                    .and_then(|line| line.strip_suffix(']'))
+            });
+        if let Some(name) = header {
+            sections.push(Section {
+                name: name.trim().into(),
+                line: line_number,
+                entries: Vec::new(),
+            });
+        } else if let Some((key, value)) = line.split_once('=') {
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            sections.last_mut().unwrap().entries.push(Entry {
+                line: line_number,
+                key: key.trim().into(),
+                value: value.into(),
+            });
+        } else {
+            return Err(format!(
+                "{}: expected `key = value` or a [header]",
+                line_number
+            ));
+        }
+    }
+    Ok(sections)
+}
+
+/// Remove a `#` comment that is not inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
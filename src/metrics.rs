@@ -0,0 +1,178 @@
+//! Measuring the complexity of each function in a file
+
+use std::fmt::Write;
+
+use crate::{ast::*, visit::*};
+
+/// The measurements of one function
+pub struct FunctionMetrics {
+    /// The name of the function, prefixed by the defs it is nested in
+    pub path: String,
+    pub line: usize,
+    /// The number of source lines the function spans
+    pub lines: usize,
+    /// One more than the number of branches
+    pub complexity: usize,
+    /// The deepest nesting of branches and closures
+    pub depth: usize,
+}
+
+/// Measure every function in some items, including nested ones
+pub fn metrics(items: &[Item]) -> Vec<FunctionMetrics> {
+    let mut collector = Collector {
+        scope: Vec::new(),
+        functions: Vec::new(),
+    };
+    walk_items(&mut collector, items);
+    collector.functions
+}
+
+struct Collector {
+    scope: Vec<String>,
+    functions: Vec<FunctionMetrics>,
+}
+
+impl<'a> Visit<'a> for Collector {
+    fn visit_def(&mut self, def: &Def<'a>) {
+        self.scope.push(def.ident.name.into());
+        if def.is_function() {
+            let start = def.ident.span.start_pos().line_col().0;
+            let end = def
+                .items
+                .last()
+                .map_or(&def.ident.span, Item::span)
+                .end_pos()
+                .line_col()
+                .0;
+            let mut measure = Measure {
+                branches: 0,
+                depth: 0,
+                max_depth: 0,
+            };
+            walk_items(&mut measure, &def.items);
+            self.functions.push(FunctionMetrics {
+                path: self.scope.join("."),
+                line: start,
+                lines: end - start + 1,
+                complexity: measure.branches + 1,
+                depth: measure.max_depth,
+            });
+        }
+        walk_def(self, def);
+        self.scope.pop();
+    }
+}
+
+/// Counts the branches in a function body and how deeply they nest
+///
+/// The right side of `and`, `or`, and `??` only runs sometimes, so each of
+/// those operators is a branch and its right side is nested one level
+/// deeper. Closure bodies are also nested one level deeper. Nested functions
+/// are measured on their own.
+struct Measure {
+    branches: usize,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl Measure {
+    fn nested(&mut self, f: impl FnOnce(&mut Self)) {
+        self.depth += 1;
+        self.max_depth = self.max_depth.max(self.depth);
+        f(self);
+        self.depth -= 1;
+    }
+}
+
+impl<'a> Visit<'a> for Measure {
+    fn visit_def(&mut self, def: &Def<'a>) {
+        if !def.is_function() {
+            walk_def(self, def)
+        }
+    }
+    fn visit_node(&mut self, node: &Node<'a>) {
+        match &node.kind {
+            NodeKind::BinExpr(expr)
+                if matches!(expr.op, BinOp::And | BinOp::Or | BinOp::Coalesce) =>
+            {
+                self.branches += 1;
+                self.visit_node(expr.left);
+                self.nested(|measure| measure.visit_node(expr.right));
+            }
+            _ => walk_node(self, node),
+        }
+    }
+    fn visit_closure(&mut self, closure: &Closure<'a>) {
+        self.nested(|measure| walk_closure(measure, closure));
+    }
+}
+
+/// Print the metrics of each function as an aligned table
+pub fn print_table(functions: &[FunctionMetrics]) {
+    let width = functions
+        .iter()
+        .map(|function| function.path.len())
+        .chain(Some("function".len()))
+        .max()
+        .unwrap();
+    println!(
+        "{:width$}  {:>5}  {:>5}  {:>10}  {:>5}",
+        "function",
+        "line",
+        "lines",
+        "complexity",
+        "depth",
+        width = width
+    );
+    for function in functions {
+        println!(
+            "{:width$}  {:>5}  {:>5}  {:>10}  {:>5}",
+            function.path,
+            function.line,
+            function.lines,
+            function.complexity,
+            function.depth,
+            width = width
+        );
+    }
+}
+
+/// Print the metrics of each function as a JSON array
+pub fn print_json(functions: &[FunctionMetrics]) {
+    let entries: Vec<String> = functions
+        .iter()
+        .map(|function| {
+            format!(
+                r#"  {{"function": {}, "line": {}, "lines": {}, "complexity": {}, "depth": {}}}"#,
+                json_string(&function.path),
+                function.line,
+                function.lines,
+                function.complexity,
+                function.depth
+            )
+        })
+        .collect();
+    if entries.is_empty() {
+        println!("[]");
+    } else {
+        println!("[\n{}\n]", entries.join(",\n"));
+    }
+}
+
+/// Quote and escape a string for JSON
+pub fn json_string(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
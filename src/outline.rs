@@ -0,0 +1,88 @@
+//! Listing the defs and closures of a file
+
+use pest::Span;
+
+use crate::{ast::*, visit::*};
+
+/// A def or closure and the symbols nested inside it
+pub struct Symbol {
+    pub kind: &'static str,
+    pub name: String,
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+    pub children: Vec<Symbol>,
+}
+
+/// Get the symbols of some items
+pub fn outline(items: &[Item]) -> Vec<Symbol> {
+    let mut outliner = Outliner {
+        symbols: Vec::new(),
+    };
+    walk_items(&mut outliner, items);
+    outliner.symbols
+}
+
+struct Outliner {
+    symbols: Vec<Symbol>,
+}
+
+impl Outliner {
+    fn push(&mut self, kind: &'static str, name: String, span: Span, items: &[Item]) {
+        self.symbols.push(Symbol {
+            kind,
+            name,
+            start: span.start_pos().line_col(),
+            end: span.end_pos().line_col(),
+            children: outline(items),
+        });
+    }
+}
+
+impl<'a> Visit<'a> for Outliner {
+    fn visit_def(&mut self, def: &Def<'a>) {
+        let kind = if !def
+            .ident
+            .name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_')
+        {
+            "operator"
+        } else if def.is_function() {
+            "function"
+        } else {
+            "value"
+        };
+        let name = def
+            .params
+            .iter()
+            .fold(def.ident.name.to_string(), |sig, param| {
+                sig + " " + param.ident.name
+            });
+        let end = def.items.last().map_or(&def.ident.span, Item::span);
+        let span = def.ident.span.start_pos().span(&end.end_pos());
+        self.push(kind, name, span, &def.items);
+    }
+    fn visit_closure(&mut self, closure: &Closure<'a>) {
+        let params = closure.params.iter().map(|param| param.ident.name);
+        let name = format!("|{}|", params.collect::<Vec<_>>().join(" "));
+        self.push("closure", name, closure.span.clone(), &closure.body);
+    }
+}
+
+/// Print symbols as an indented tree with their ranges
+pub fn print_outline(symbols: &[Symbol], depth: usize) {
+    for symbol in symbols {
+        println!(
+            "{:indent$}{} {} {}:{}-{}:{}",
+            "",
+            symbol.kind,
+            symbol.name,
+            symbol.start.0,
+            symbol.start.1,
+            symbol.end.0,
+            symbol.end.1,
+            indent = depth * 2
+        );
+        print_outline(&symbol.children, depth + 1);
+    }
+}
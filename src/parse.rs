@@ -1,6 +1,9 @@
 #![allow(clippy::upper_case_acronyms)]
 
-use std::{collections::HashMap, fmt};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
 
 use itertools::Itertools;
 use pest::{
@@ -9,28 +12,106 @@ use pest::{
     Parser, RuleType, Span,
 };
 
-use crate::ast::*;
+use crate::{ast::*, escape, transpile::CompileOptions};
 
 #[derive(Debug)]
 pub enum TranspileError<'a> {
-    UnknownDef(Ident<'a>),
+    UnknownDef(Ident<'a>, Option<&'a str>),
+    UnknownOperator(Span<'a>),
     Parse(PestError<Rule>),
     InvalidLiteral(Span<'a>),
     DefUnderscoreTerminus(Span<'a>),
     FunctionNamedUnderscore(Span<'a>),
-    ReturnReferencesLocal(Span<'a>),
+    /// The returned expression, the local it points at, and what that local is
+    ReturnReferencesLocal(Span<'a>, Span<'a>, String),
     ForbiddenRedefinition(Ident<'a>),
     LastItemNotExpression(Span<'a>),
+    NonBoolCondition(Span<'a>),
+    UnknownType(Span<'a>),
+    TypeMismatch(&'a str, Type, Span<'a>),
+    MisplacedDefer(Span<'a>),
+    UnsupportedVersion(Span<'a>),
+    MisplacedReturn(Span<'a>),
+    RedeclaredOperator(Span<'a>),
+}
+
+impl<'a> TranspileError<'a> {
+    /// The code of the error, which can be passed to `kin explain`
+    pub fn code(&self) -> &'static str {
+        match self {
+            TranspileError::UnknownDef(..) => "E0001",
+            TranspileError::UnknownOperator(_) => "E0002",
+            TranspileError::Parse(_) => "E0003",
+            TranspileError::InvalidLiteral(_) => "E0004",
+            TranspileError::DefUnderscoreTerminus(_) => "E0005",
+            TranspileError::FunctionNamedUnderscore(_) => "E0006",
+            TranspileError::ReturnReferencesLocal(..) => "E0007",
+            TranspileError::ForbiddenRedefinition(_) => "E0008",
+            TranspileError::LastItemNotExpression(_) => "E0009",
+            TranspileError::NonBoolCondition(_) => "E0010",
+            TranspileError::UnknownType(_) => "E0011",
+            TranspileError::TypeMismatch(..) => "E0012",
+            TranspileError::MisplacedDefer(_) => "E0013",
+            TranspileError::UnsupportedVersion(_) => "E0014",
+            TranspileError::MisplacedReturn(_) => "E0015",
+            TranspileError::RedeclaredOperator(_) => "E0016",
+        }
+    }
+    /// A fix for the error
+    pub fn suggestion(&self) -> Option<Suggestion> {
+        match self {
+            TranspileError::UnknownDef(ident, Some(similar)) => {
+                Some(Suggestion::new(&ident.span, *similar, false))
+            }
+            TranspileError::DefUnderscoreTerminus(span) => {
+                let trimmed = span.as_str().trim_matches('_');
+                if trimmed.is_empty() {
+                    None
+                } else {
+                    Some(Suggestion::new(span, trimmed, true))
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A replacement of a range of the source that fixes an error
+#[derive(Debug, Clone)]
+pub struct Suggestion {
+    pub start: usize,
+    pub end: usize,
+    pub replacement: String,
+    /// Whether the replacement is certainly what was meant, so it can be
+    /// applied without asking. Other suggestions are only guesses.
+    pub machine_applicable: bool,
+}
+
+impl Suggestion {
+    fn new(span: &Span, replacement: impl Into<String>, machine_applicable: bool) -> Self {
+        Suggestion {
+            start: span.start(),
+            end: span.end(),
+            replacement: replacement.into(),
+            machine_applicable,
+        }
+    }
 }
 
 impl<'a> fmt::Display for TranspileError<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "error[{}]", self.code())?;
         match self {
-            TranspileError::UnknownDef(ident) => format_span(
+            TranspileError::UnknownDef(ident, _) => format_span(
                 format!("Unknown def: {:?}", ident.name),
                 ident.span.clone(),
                 f,
             ),
+            TranspileError::UnknownOperator(span) => format_span(
+                format!("Unknown operator: {:?}", span.as_str()),
+                span.clone(),
+                f,
+            ),
             TranspileError::Parse(e) => e.fmt(f),
             TranspileError::InvalidLiteral(span) => format_span("Invalid literal", span.clone(), f),
             TranspileError::DefUnderscoreTerminus(span) => {
@@ -39,8 +120,20 @@ impl<'a> fmt::Display for TranspileError<'a> {
             TranspileError::FunctionNamedUnderscore(span) => {
                 format_span("Function cannot be named '_'", span.clone(), f)
             }
-            TranspileError::ReturnReferencesLocal(span) => {
-                format_span("Return value references local value", span.clone(), f)
+            TranspileError::ReturnReferencesLocal(span, local, what) => {
+                format_span(
+                    format!(
+                        "The returned value points at {}, which only lives until the function returns",
+                        what
+                    ),
+                    span.clone(),
+                    f,
+                )?;
+                if local != span {
+                    writeln!(f)?;
+                    format_span(format!("{} lives here", what), local.clone(), f)?;
+                }
+                Ok(())
             }
             TranspileError::ForbiddenRedefinition(ident) => format_span(
                 format!("{} cannot be redefined", ident.name),
@@ -52,11 +145,56 @@ impl<'a> fmt::Display for TranspileError<'a> {
                 span.clone(),
                 f,
             ),
+            TranspileError::NonBoolCondition(span) => format_span(
+                "Conditions must be bools in strict bool mode",
+                span.clone(),
+                f,
+            ),
+            TranspileError::UnknownType(span) => format_span(
+                format!("Unknown type: {:?}", span.as_str()),
+                span.clone(),
+                f,
+            ),
+            TranspileError::TypeMismatch(expected, found, span) => format_span(
+                format!("Expected {}, but found {}", expected, found.name()),
+                span.clone(),
+                f,
+            ),
+            TranspileError::MisplacedDefer(span) => format_span(
+                "defer can only be used at the top level of a function or file",
+                span.clone(),
+                f,
+            ),
+            TranspileError::UnsupportedVersion(span) => format_span(
+                format!(
+                    "Unsupported language version {}. The supported versions are: {}",
+                    span.as_str(),
+                    SUPPORTED_VERSIONS.join(", ")
+                ),
+                span.clone(),
+                f,
+            ),
+            TranspileError::MisplacedReturn(span) => {
+                format_span("return can only be used in a function", span.clone(), f)
+            }
+            TranspileError::RedeclaredOperator(span) => format_span(
+                format!("Operator {:?} is already declared in this scope", span.as_str()),
+                span.clone(),
+                f,
+            ),
+        }?;
+        if let Some(suggestion) = self.suggestion() {
+            if suggestion.machine_applicable {
+                write!(f, "\n  = help: try {:?}", suggestion.replacement)?;
+            } else {
+                write!(f, "\n  = help: did you mean {:?}?", suggestion.replacement)?;
+            }
         }
+        Ok(())
     }
 }
 
-fn format_span(message: impl Into<String>, span: Span, f: &mut fmt::Formatter) -> fmt::Result {
+pub fn format_span(message: impl Into<String>, span: Span, f: &mut fmt::Formatter) -> fmt::Result {
     let error = PestError::<Rule>::new_from_span(
         ErrorVariant::CustomError {
             message: message.into(),
@@ -66,6 +204,22 @@ fn format_span(message: impl Into<String>, span: Span, f: &mut fmt::Formatter) -
     write!(f, "{}", error)
 }
 
+/// The number of single-character edits needed to turn one string into another
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ac) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &bc) in b.iter().enumerate() {
+            let substitution = diagonal + (ac != bc) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
 fn only<R>(pair: Pair<R>) -> Pair<R>
 where
     R: RuleType,
@@ -75,17 +229,46 @@ where
 
 static FORBIDDEN_REDIFINITIONS: &[&str] = &["nil", "true", "false"];
 
+/// The language versions that a file can declare with `#version`
+///
+/// A breaking change to the syntax bumps the version, and files that declare
+/// an older version keep the old behavior.
+pub static SUPPORTED_VERSIONS: &[&str] = &["0.1"];
+
 #[derive(pest_derive::Parser)]
 #[grammar = "grammar.pest"]
 struct KinParser;
 
-pub fn parse(input: &str) -> Result<Items, Vec<TranspileError>> {
+/// Get the names of the grammar rules that match somewhere in some input, or
+/// `None` if it does not parse
+pub fn matched_rules(input: &str) -> Option<HashSet<String>> {
+    let pairs = KinParser::parse(Rule::file, input).ok()?;
+    Some(
+        pairs
+            .flatten()
+            .map(|pair| format!("{:?}", pair.as_rule()))
+            .collect(),
+    )
+}
+
+pub fn parse<'a>(
+    input: &'a str,
+    arena: &'a Arena<'a>,
+    options: &CompileOptions,
+) -> Result<Items<'a>, Vec<TranspileError<'a>>> {
     match KinParser::parse(Rule::file, input) {
         Ok(mut pairs) => {
             let mut state = ParseState {
                 input,
+                arena,
+                strict_bool: options.strict_bool,
                 scopes: vec![FunctionScope::default()],
+                type_sets: BUILTIN_TYPE_SETS
+                    .iter()
+                    .map(|&(name, types)| (name, TypeSet::of(types)))
+                    .collect(),
                 errors: Vec::new(),
+                next_node_id: 0,
             };
             for (name, _) in crate::transpile::BUILTIN_FUNCTIONS
                 .iter()
@@ -93,7 +276,21 @@ pub fn parse(input: &str) -> Result<Items, Vec<TranspileError>> {
             {
                 state.scope().bindings.insert(name, Binding::Builtin);
             }
-            let items = state.items(only(pairs.next().unwrap()), false);
+            let mut file = pairs.next().unwrap().into_inner();
+            let mut first = file.next().unwrap();
+            if first.as_rule() == Rule::version {
+                let number = only(first).as_span();
+                if !SUPPORTED_VERSIONS.contains(&number.as_str()) {
+                    state
+                        .errors
+                        .push(TranspileError::UnsupportedVersion(number));
+                }
+                first = file.next().unwrap();
+            }
+            let items = state.items(first);
+            if state.errors.is_empty() {
+                state.errors = escape::check(&items);
+            }
             if state.errors.is_empty() {
                 Ok(items)
             } else {
@@ -106,54 +303,64 @@ pub fn parse(input: &str) -> Result<Items, Vec<TranspileError>> {
 
 #[derive(Debug, Clone)]
 enum Binding<'a> {
-    Def(Def<'a>, Lifetime),
-    Param(u8),
+    /// A def with the type annotations of its params
+    Def(Vec<Option<TypeAnn<'a>>>),
+    Param,
     Builtin,
-    Unfinished(u8),
-}
-
-impl<'a> Binding<'a> {
-    pub fn lifetime(&self) -> Lifetime {
-        match self {
-            Binding::Def(_, lt) => *lt,
-            Binding::Param(depth) | Binding::Unfinished(depth) => Lifetime::new(*depth, *depth),
-            Binding::Builtin => Lifetime::STATIC,
-        }
-    }
+    Unfinished,
 }
 
 #[derive(Default)]
 struct ParenScope<'a> {
     bindings: HashMap<&'a str, Binding<'a>>,
+    /// The precedences of the custom operators declared in the scope
+    ops: HashMap<&'a str, u8>,
 }
 
 struct FunctionScope<'a> {
     scopes: Vec<ParenScope<'a>>,
-    min_refs: u8,
+    /// The return type annotation of the function
+    ret: Option<TypeAnn<'a>>,
 }
 
 impl<'a> Default for FunctionScope<'a> {
     fn default() -> Self {
         FunctionScope {
             scopes: vec![ParenScope::default()],
-            min_refs: 0,
+            ret: None,
         }
     }
 }
 
+/// An operator in an expression that is waiting for its right operand
+enum PendingOp<'a> {
+    Not(Pair<'a, Rule>),
+    Bin {
+        /// `None` for custom operators
+        op: Option<BinOp>,
+        prec: u8,
+        assoc: Assoc,
+        pair: Pair<'a, Rule>,
+    },
+}
+
 struct ParseState<'a> {
     input: &'a str,
+    arena: &'a Arena<'a>,
+    strict_bool: bool,
     scopes: Vec<FunctionScope<'a>>,
+    type_sets: HashMap<&'a str, TypeSet>,
     errors: Vec<TranspileError<'a>>,
+    /// The id of the next node to be built
+    next_node_id: u32,
 }
 
 impl<'a> ParseState<'a> {
     fn push_function_scope(&mut self) {
         self.scopes.push(FunctionScope::default());
     }
-    #[must_use]
-    fn pop_function_scope(&mut self) -> u8 {
-        self.scopes.pop().unwrap().min_refs
+    fn pop_function_scope(&mut self) {
+        self.scopes.pop();
     }
     fn push_paren_scope(&mut self) {
         self.function_scope().scopes.push(ParenScope::default());
@@ -170,52 +377,85 @@ impl<'a> ParseState<'a> {
     fn span(&self, start: usize, end: usize) -> Span<'a> {
         Span::new(self.input, start, end).unwrap()
     }
-    fn depth(&self) -> u8 {
-        // self.scopes
-        //     .iter()
-        //     .map(|scope| scope.scopes.len() as u8)
-        //     .sum()
-        self.scopes.len() as u8
-    }
-    fn bind_def(&mut self, def: Def<'a>, min_refs: u8) {
-        let depth = self.depth();
-        let refs = def.items.last().unwrap().lifetime().refs.max(min_refs);
-        self.scope().bindings.insert(
-            def.ident.name,
-            Binding::Def(def, Lifetime::new(depth, refs)),
-        );
+    /// Wrap a kind in a node with the next id
+    fn new_node(&mut self, kind: NodeKind<'a>) -> Node<'a> {
+        let id = NodeId(self.next_node_id);
+        self.next_node_id += 1;
+        Node { kind, id }
     }
-    fn bind_param(&mut self, name: &'a str) {
-        let depth = self.depth() - 1;
-        self.scope().bindings.insert(name, Binding::Param(depth));
+    fn depth(&self) -> usize {
+        self.scopes.len()
     }
-    fn bind_unfinished(&mut self, name: &'a str) {
-        let depth = self.depth();
+    fn bind_def(&mut self, def: &Def<'a>) {
+        let param_types = def.params.iter().map(|param| param.ty.clone()).collect();
         self.scope()
             .bindings
-            .insert(name, Binding::Unfinished(depth));
+            .insert(def.ident.name, Binding::Def(param_types));
+    }
+    fn bind_param(&mut self, name: &'a str) {
+        self.scope().bindings.insert(name, Binding::Param);
+    }
+    fn bind_unfinished(&mut self, name: &'a str) {
+        self.scope().bindings.insert(name, Binding::Unfinished);
+    }
+    fn binding(&self, name: &str) -> Option<&Binding<'a>> {
+        self.scopes.iter().rev().find_map(|fscope| {
+            fscope
+                .scopes
+                .iter()
+                .rev()
+                .find_map(|pscope| pscope.bindings.get(name))
+        })
+    }
+    /// Get the precedence of a custom operator declared in an enclosing scope
+    fn custom_op(&self, name: &str) -> Option<u8> {
+        self.scopes.iter().rev().find_map(|fscope| {
+            fscope
+                .scopes
+                .iter()
+                .rev()
+                .find_map(|pscope| pscope.ops.get(name).copied())
+        })
+    }
+    /// Find the bound name that is most similar to an unknown one
+    ///
+    /// Builtins and single-character names are never suggested, since almost
+    /// any short typo is close to them.
+    fn similar_name(&self, name: &str) -> Option<&'a str> {
+        let max_distance = (name.len() / 3).max(1);
+        self.scopes
+            .iter()
+            .flat_map(|fscope| &fscope.scopes)
+            .flat_map(|pscope| &pscope.bindings)
+            .filter(|&(bound, binding)| {
+                bound.chars().count() > 1 && !matches!(binding, Binding::Builtin)
+            })
+            .map(|(&bound, _)| (edit_distance(name, bound), bound))
+            .filter(|&(distance, _)| distance <= max_distance)
+            .min()
+            .map(|(_, bound)| bound)
+    }
+    /// Report an ident that is not bound
+    fn resolve(&mut self, ident: &Ident<'a>) {
+        if self.binding(ident.name).is_none() {
+            let similar = self.similar_name(ident.name);
+            self.errors
+                .push(TranspileError::UnknownDef(ident.clone(), similar));
+        }
     }
-    fn items(&mut self, pair: Pair<'a, Rule>, check_ref: bool) -> Items<'a> {
+    fn items(&mut self, pair: Pair<'a, Rule>) -> Items<'a> {
         let mut items = Vec::new();
         for pair in pair.into_inner() {
             match pair.as_rule() {
-                Rule::item => items.push(self.item(pair)),
+                Rule::item => items.extend(self.item(pair)),
                 Rule::EOI => {}
                 rule => unreachable!("{:?}", rule),
             }
         }
         if let Some(last_item) = items.last() {
-            if check_ref {
-                if let Item::Node(node) = last_item {
-                    if node.lifetime.refs == self.depth() && self.function_scope().scopes.len() == 1
-                    {
-                        self.errors.push(TranspileError::ReturnReferencesLocal(
-                            node.kind.span().clone(),
-                        ))
-                    }
-                }
-            }
-            if self.depth() > 1 && !matches!(last_item, Item::Node(_)) {
+            // Every block but the file itself has a value
+            let in_block = self.depth() > 1 || self.function_scope().scopes.len() > 1;
+            if in_block && !matches!(last_item, Item::Node(_) | Item::Return(_)) {
                 self.errors.push(TranspileError::LastItemNotExpression(
                     last_item.span().clone(),
                 ));
@@ -223,13 +463,21 @@ impl<'a> ParseState<'a> {
         }
         items
     }
-    fn item(&mut self, pair: Pair<'a, Rule>) -> Item<'a> {
+    fn item(&mut self, pair: Pair<'a, Rule>) -> Option<Item<'a>> {
         let pair = only(pair);
-        match pair.as_rule() {
+        Some(match pair.as_rule() {
             Rule::expr => Item::Node(self.expr(pair)),
             Rule::def => self.def(pair),
+            Rule::op_def => self.op_def(pair),
+            Rule::defer => self.defer(pair),
+            Rule::early_return => self.early_return(pair),
+            Rule::discard => Item::Discard(self.expr(only(pair))),
+            Rule::type_def => {
+                self.type_def(pair);
+                return None;
+            }
             rule => unreachable!("{:?}", rule),
-        }
+        })
     }
     fn ident(&mut self, pair: Pair<'a, Rule>) -> Ident<'a> {
         let name = pair.as_str();
@@ -251,19 +499,82 @@ impl<'a> ParseState<'a> {
     fn param(&mut self, pair: Pair<'a, Rule>) -> Param<'a> {
         let mut pairs = pair.into_inner();
         let ident = self.bound_ident(pairs.next().unwrap());
-        Param { ident }
+        let ty = pairs.next().and_then(|pair| self.type_name(pair));
+        Param { ident, ty }
     }
-    fn def(&mut self, pair: Pair<'a, Rule>) -> Item<'a> {
+    fn type_name(&mut self, pair: Pair<'a, Rule>) -> Option<TypeAnn<'a>> {
+        let span = pair.as_span();
+        let types = if let Some(ty) = Type::from_name(span.as_str()) {
+            ty.into()
+        } else if let Some(&types) = self.type_sets.get(span.as_str()) {
+            types
+        } else {
+            self.errors.push(TranspileError::UnknownType(span));
+            return None;
+        };
+        Some(TypeAnn { types, span })
+    }
+    /// Declare a named set of types
+    fn type_def(&mut self, pair: Pair<'a, Rule>) {
         let mut pairs = pair.into_inner();
+        let ident = self.ident(pairs.next().unwrap());
+        if Type::from_name(ident.name).is_some() {
+            self.errors
+                .push(TranspileError::ForbiddenRedefinition(ident.clone()));
+        }
+        pairs.next().unwrap();
+        let types = pairs
+            .filter_map(|pair| self.type_name(pair))
+            .fold(TypeSet::of(&[]), |set, ann| set.union(ann.types));
+        self.type_sets.insert(ident.name, types);
+    }
+    /// Report a value whose type is known to not match its annotation
+    fn check_type(&mut self, node: &Node<'a>, ann: &Option<TypeAnn<'a>>) {
+        if let (Some(ann), Some(found)) = (ann, node.kind.static_type()) {
+            if !ann.types.contains(found) {
+                let span = node.kind.span().clone();
+                self.errors.push(TranspileError::TypeMismatch(
+                    ann.span.as_str(),
+                    found,
+                    span,
+                ));
+            }
+        }
+    }
+    /// Report arguments whose types are known to not match the annotated
+    /// param types of the called def
+    fn check_args(&mut self, call: &CallExpr<'a>) {
+        let param_types: Vec<_> = match &call.caller.kind {
+            NodeKind::Term(Term::Ident(ident), _) => match self.binding(ident.name) {
+                Some(Binding::Def(param_types)) => param_types.clone(),
+                _ => return,
+            },
+            _ => return,
+        };
+        for (arg, ty) in call.args.iter().zip(&param_types) {
+            self.check_type(arg, ty);
+        }
+    }
+    fn def(&mut self, pair: Pair<'a, Rule>) -> Item<'a> {
+        let mut pairs = pair.into_inner().peekable();
+        let deprecated = if let Some(Rule::deprecated) = pairs.peek().map(|pair| pair.as_rule()) {
+            let note = pairs.next().unwrap().into_inner().next();
+            Some(note.map_or_else(String::new, |pair| self.string_literal(pair)))
+        } else {
+            None
+        };
         let ident = self.bound_ident(pairs.next().unwrap());
         let mut params = Vec::new();
-        for pair in pairs.by_ref() {
-            if let Rule::param = pair.as_rule() {
-                params.push(self.param(pair));
-            } else {
-                break;
-            }
+        while let Some(Rule::param) = pairs.peek().map(|pair| pair.as_rule()) {
+            params.push(self.param(pairs.next().unwrap()));
         }
+        let ret = if let Some(Rule::return_type) = pairs.peek().map(|pair| pair.as_rule()) {
+            let pair = pairs.next().unwrap().into_inner().next().unwrap();
+            self.type_name(pair)
+        } else {
+            None
+        };
+        pairs.next().unwrap();
         let is_function = !params.is_empty();
         if is_function {
             if ident.is_underscore() {
@@ -272,137 +583,231 @@ impl<'a> ParseState<'a> {
             }
             self.bind_unfinished(ident.name);
             self.push_function_scope();
+            self.function_scope().ret = ret.clone();
             for param in &params {
                 self.bind_param(param.ident.name);
             }
         }
         let pair = pairs.next().unwrap();
         let items_span = pair.as_span();
-        let items = self.function_body(pair, is_function);
-        let min_refs = if is_function {
-            self.pop_function_scope()
+        // The defs in a value's block are local to it
+        if !is_function {
+            self.push_paren_scope();
+        }
+        let items = self.function_body(pair);
+        if !is_function {
+            self.pop_paren_scope();
+        }
+        if let Some(Item::Node(node)) = items.last() {
+            self.check_type(node, &ret);
+        }
+        if is_function {
+            self.pop_function_scope();
         } else if ident.is_underscore() {
-            let refs = items.last().unwrap().lifetime().refs;
-            return Item::Node(
-                NodeKind::Term(Term::Expr(items), items_span).life(self.depth(), refs),
-            );
-        } else {
-            0
-        };
+            return Item::Discard(self.new_node(NodeKind::Term(Term::Expr(items), items_span)));
+        }
         let def = Def {
             ident,
             params,
+            ret,
             items,
+            deprecated,
         };
-        self.bind_def(def.clone(), min_refs);
+        self.bind_def(&def);
         Item::Def(def)
     }
-    fn expr(&mut self, pair: Pair<'a, Rule>) -> Node<'a> {
-        let pair = only(pair);
-        match pair.as_rule() {
-            Rule::expr_or => self.expr_or(pair),
-            rule => unreachable!("{:?}", rule),
+    fn defer(&mut self, pair: Pair<'a, Rule>) -> Item<'a> {
+        let span = pair.as_span();
+        let node = self.expr(only(pair));
+        if self.function_scope().scopes.len() > 1 {
+            self.errors.push(TranspileError::MisplacedDefer(span));
         }
+        Item::Defer(node)
     }
-    fn expr_or(&mut self, pair: Pair<'a, Rule>) -> Node<'a> {
-        let mut pairs = pair.into_inner();
-        let left = pairs.next().unwrap();
-        let mut span = left.as_span();
-        let mut left = self.expr_and(left);
-        for (op, right) in pairs.tuples() {
-            let op_span = op.as_span();
-            let op = match op.as_str() {
-                "or" => BinOp::Or,
-                rule => unreachable!("{:?}", rule),
-            };
-            span = self.span(span.start(), right.as_span().end());
-            let right = self.expr_and(right);
-            let refs = left.lifetime.refs.max(right.lifetime.refs);
-            left = NodeKind::BinExpr(BinExpr::new(left, right, op, span.clone(), op_span))
-                .life(self.depth(), refs);
+    fn early_return(&mut self, pair: Pair<'a, Rule>) -> Item<'a> {
+        let span = pair.as_span();
+        let node = self.expr(only(pair));
+        if self.depth() > 1 {
+            let ret = self.function_scope().ret.clone();
+            self.check_type(&node, &ret);
+        } else {
+            self.errors.push(TranspileError::MisplacedReturn(span));
         }
-        left
+        Item::Return(node)
     }
-    fn expr_and(&mut self, pair: Pair<'a, Rule>) -> Node<'a> {
+    /// Declare a custom infix operator
+    ///
+    /// The operator is bound like a value def named by its symbol, and uses of
+    /// it are parsed as calls to that value. Like a def, it is only known in
+    /// the scope it is declared in.
+    fn op_def(&mut self, pair: Pair<'a, Rule>) -> Item<'a> {
         let mut pairs = pair.into_inner();
-        let left = pairs.next().unwrap();
-        let mut span = left.as_span();
-        let mut left = self.expr_cmp(left);
-        for (op, right) in pairs.tuples() {
-            let op_span = op.as_span();
-            let op = match op.as_str() {
-                "and" => BinOp::And,
-                rule => unreachable!("{:?}", rule),
-            };
-            span = self.span(span.start(), right.as_span().end());
-            let right = self.expr_cmp(right);
-            let refs = left.lifetime.refs.max(right.lifetime.refs);
-            left = NodeKind::BinExpr(BinExpr::new(left, right, op, span.clone(), op_span))
-                .life(self.depth(), refs);
+        let op = pairs.next().unwrap();
+        let ident = Ident {
+            name: op.as_str(),
+            span: op.as_span(),
+        };
+        let prec_pair = pairs.next().unwrap();
+        let prec = match prec_pair.as_str().parse::<u8>() {
+            Ok(prec) => prec,
+            Err(_) => {
+                self.errors
+                    .push(TranspileError::InvalidLiteral(prec_pair.as_span()));
+                0
+            }
+        };
+        pairs.next().unwrap();
+        let items = self.function_body(pairs.next().unwrap());
+        if self.scope().ops.insert(ident.name, prec).is_some() {
+            self.errors
+                .push(TranspileError::RedeclaredOperator(ident.span.clone()));
         }
-        left
+        let def = Def {
+            ident,
+            params: Vec::new(),
+            ret: None,
+            items,
+            deprecated: None,
+        };
+        self.bind_def(&def);
+        Item::Def(def)
     }
-    fn expr_cmp(&mut self, pair: Pair<'a, Rule>) -> Node<'a> {
+    /// Parse a sequence of operands and infix operators by precedence
+    ///
+    /// Pending operators are kept on an explicit stack rather than the call
+    /// stack, so arbitrarily long expressions do not deepen the recursion.
+    fn expr(&mut self, pair: Pair<'a, Rule>) -> Node<'a> {
         let mut pairs = pair.into_inner();
-        let left = pairs.next().unwrap();
-        let mut span = left.as_span();
-        let mut left = self.expr_as(left);
-        for (op, right) in pairs.tuples() {
-            let op_span = op.as_span();
-            let op = match op.as_str() {
-                "==" => BinOp::Equals,
-                "!=" => BinOp::NotEquals,
-                "<=" => BinOp::LessOrEqual,
-                ">=" => BinOp::GreaterOrEqual,
-                "<" => BinOp::Less,
-                ">" => BinOp::Greater,
-                rule => unreachable!("{:?}", rule),
+        let mut operands: Vec<Node<'a>> = Vec::new();
+        let mut ops: Vec<PendingOp<'a>> = Vec::new();
+        loop {
+            let first = pairs.next().unwrap();
+            if let Rule::op_not = first.as_rule() {
+                ops.push(PendingOp::Not(first));
+                continue;
+            }
+            operands.push(self.expr_neg(first));
+            let op_pair = if let Some(op_pair) = pairs.next() {
+                op_pair
+            } else {
+                break;
             };
-            span = self.span(span.start(), right.as_span().end());
-            let right = self.expr_as(right);
-            left = NodeKind::BinExpr(BinExpr::new(left, right, op, span.clone(), op_span))
-                .life(self.depth(), 0);
+            let (op, prec) = if let Some(op) = BinOp::from_infix(op_pair.as_str()) {
+                (Some(op), op.precedence())
+            } else if let Some(prec) = self.custom_op(op_pair.as_str()) {
+                (None, prec)
+            } else {
+                self.errors
+                    .push(TranspileError::UnknownOperator(op_pair.as_span()));
+                (None, u8::MAX)
+            };
+            // Finish the pending operators that bind tighter than this one
+            while let Some(top) = ops.last() {
+                let reduce = match top {
+                    PendingOp::Not(_) => prec < UnOp::NOT_PRECEDENCE,
+                    PendingOp::Bin {
+                        prec: top_prec,
+                        assoc,
+                        ..
+                    } => prec < *top_prec || prec == *top_prec && *assoc == Assoc::Left,
+                };
+                if !reduce {
+                    break;
+                }
+                self.reduce_op(&mut operands, ops.pop().unwrap());
+            }
+            let assoc = op.map_or(Assoc::Left, |op| op.assoc());
+            ops.push(PendingOp::Bin {
+                op,
+                prec,
+                assoc,
+                pair: op_pair,
+            });
+        }
+        while let Some(op) = ops.pop() {
+            self.reduce_op(&mut operands, op);
         }
-        left
+        operands.pop().unwrap()
     }
-    fn expr_as(&mut self, pair: Pair<'a, Rule>) -> Node<'a> {
-        let mut pairs = pair.into_inner();
-        let left = pairs.next().unwrap();
-        let mut span = left.as_span();
-        let mut left = self.expr_mdr(left);
-        for (op, right) in pairs.tuples() {
-            let op_span = op.as_span();
-            let op = match op.as_str() {
-                "+" => BinOp::Add,
-                "-" => BinOp::Sub,
-                rule => unreachable!("{:?}", rule),
-            };
-            span = self.span(span.start(), right.as_span().end());
-            let right = self.expr_mdr(right);
-            left = NodeKind::BinExpr(BinExpr::new(left, right, op, span.clone(), op_span))
-                .life(self.depth(), 0);
+    /// Apply a pending operator to the operands on top of the stack
+    fn reduce_op(&mut self, operands: &mut Vec<Node<'a>>, op: PendingOp<'a>) {
+        let right = operands.pop().unwrap();
+        let node = match op {
+            PendingOp::Not(pair) => {
+                self.check_condition(&right);
+                let span = self.span(pair.as_span().start(), right.kind.span().end());
+                self.new_node(NodeKind::UnExpr(UnExpr::new(self.arena.node(right), UnOp::Not, span)))
+            }
+            PendingOp::Bin { op, pair, .. } => {
+                let left = operands.pop().unwrap();
+                if let Some(op) = op {
+                    self.bin_expr(left, right, op, pair.as_span())
+                } else {
+                    self.custom_op_call(left, right, pair)
+                }
+            }
+        };
+        operands.push(node);
+    }
+    /// In strict bool mode, reject conditions that can never be bools
+    fn check_condition(&mut self, node: &Node<'a>) {
+        if self.strict_bool && node.kind.is_never_bool() {
+            let span = node.kind.span().clone();
+            self.errors.push(TranspileError::NonBoolCondition(span));
         }
-        left
     }
-    fn expr_mdr(&mut self, pair: Pair<'a, Rule>) -> Node<'a> {
-        let mut pairs = pair.into_inner();
-        let left = pairs.next().unwrap();
-        let mut span = left.as_span();
-        let mut left = self.expr_neg(left);
-        for (op, right) in pairs.tuples() {
-            let op_span = op.as_span();
-            let op = match op.as_str() {
-                "*" => BinOp::Mul,
-                "/" => BinOp::Div,
-                "%" => BinOp::Rem,
-                rule => unreachable!("{:?}", rule),
-            };
-            span = self.span(span.start(), right.as_span().end());
-            let right = self.expr_neg(right);
-            left = NodeKind::BinExpr(BinExpr::new(left, right, op, span.clone(), op_span))
-                .life(self.depth(), 0);
+    fn bin_expr(
+        &mut self,
+        left: Node<'a>,
+        right: Node<'a>,
+        op: BinOp,
+        op_span: Span<'a>,
+    ) -> Node<'a> {
+        let span = self.span(left.kind.span().start(), right.kind.span().end());
+        if op.is_comparison() {
+            match left.kind {
+                NodeKind::BinExpr(left) if left.op.is_comparison() => {
+                    let chain = CmpChain {
+                        operands: vec![left.left.clone(), left.right.clone(), right],
+                        ops: vec![(left.op, left.op_span), (op, op_span)],
+                        span,
+                    };
+                    return self.new_node(NodeKind::CmpChain(chain));
+                }
+                NodeKind::CmpChain(mut chain) => {
+                    chain.operands.push(right);
+                    chain.ops.push((op, op_span));
+                    chain.span = span;
+                    return self.new_node(NodeKind::CmpChain(chain));
+                }
+                kind => {
+                    let left = self.arena.node(Node { kind, id: left.id });
+                    let expr = BinExpr::new(left, self.arena.node(right), op, span, op_span);
+                    return self.new_node(NodeKind::BinExpr(expr));
+                }
+            }
+        }
+        if let BinOp::Or | BinOp::And = op {
+            self.check_condition(&left);
+        }
+        let (left, right) = (self.arena.node(left), self.arena.node(right));
+        self.new_node(NodeKind::BinExpr(BinExpr::new(left, right, op, span, op_span)))
+    }
+    fn custom_op_call(&mut self, left: Node<'a>, right: Node<'a>, op: Pair<'a, Rule>) -> Node<'a> {
+        let span = self.span(left.kind.span().start(), right.kind.span().end());
+        let ident = Ident {
+            name: op.as_str(),
+            span: op.as_span(),
+        };
+        if self.custom_op(ident.name).is_some() {
+            self.resolve(&ident);
         }
-        left
+        let caller = self.new_node(NodeKind::Term(Term::Ident(ident), op.as_span()));
+        self.new_node(NodeKind::Call(CallExpr {
+            caller: self.arena.node(caller),
+            args: vec![left, right],
+            span,
+        }))
     }
     fn expr_neg(&mut self, pair: Pair<'a, Rule>) -> Node<'a> {
         let span = pair.as_span();
@@ -419,7 +824,7 @@ impl<'a> ParseState<'a> {
         };
         let inner = self.expr_call(inner);
         if let Some(op) = op {
-            NodeKind::UnExpr(UnExpr::new(inner, op, span)).life(self.depth(), 0)
+            self.new_node(NodeKind::UnExpr(UnExpr::new(self.arena.node(inner), op, span)))
         } else {
             inner
         }
@@ -433,40 +838,34 @@ impl<'a> ParseState<'a> {
                     let span = pair.as_span();
                     let mut pairs = pair.into_inner();
                     let caller = self.expr_dad(pairs.next().unwrap());
-                    calls.push(CallExpr {
-                        caller: caller.into(),
-                        args: pairs.map(|pair| self.expr_dad(pair)).collect(),
-                        span,
-                    });
+                    let args: Vec<Node> = pairs.map(|pair| self.expr_dad(pair)).collect();
+                    calls.push((caller, args, span));
                 }
                 rule => unreachable!("{:?}", rule),
             }
         }
         let mut calls = calls.into_iter();
-        let first_call = calls.next().unwrap();
-        let mut refs = first_call
-            .args
-            .iter()
-            .map(|node| node.lifetime.refs)
-            .max()
-            .unwrap_or(first_call.caller.lifetime.refs);
-        let mut call_node = if first_call.args.is_empty() {
-            *first_call.caller
+        let (caller, args, span) = calls.next().unwrap();
+        let mut call_node = if args.is_empty() {
+            caller
         } else {
-            NodeKind::Call(first_call).life(self.depth(), refs)
+            self.call_node(caller, args, span)
         };
-        for mut chained_call in calls {
-            refs = chained_call
-                .args
-                .iter()
-                .map(|node| node.lifetime.refs)
-                .max()
-                .unwrap_or(refs);
-            chained_call.args.insert(0, call_node);
-            call_node = NodeKind::Call(chained_call).life(self.depth(), refs);
+        for (caller, mut args, span) in calls {
+            args.insert(0, call_node);
+            call_node = self.call_node(caller, args, span);
         }
         call_node
     }
+    fn call_node(&mut self, caller: Node<'a>, args: Vec<Node<'a>>, span: Span<'a>) -> Node<'a> {
+        let call = CallExpr {
+            caller: self.arena.node(caller),
+            args,
+            span,
+        };
+        self.check_args(&call);
+        self.new_node(NodeKind::Call(call))
+    }
     fn expr_dad(&mut self, pair: Pair<'a, Rule>) -> Node<'a> {
         let mut pairs = pair.into_inner();
         let dad = pairs.next().unwrap();
@@ -479,10 +878,8 @@ impl<'a> ParseState<'a> {
                 rule => unreachable!("{:?}", rule),
             };
             span = self.span(span.start(), right.as_span().end());
-            let head = self.expr_mom(right);
-            let refs = dad.lifetime.depth;
-            dad = NodeKind::BinExpr(BinExpr::new(dad, head, op, span.clone(), op_span))
-                .life(self.depth(), refs);
+            let (dad_node, head) = (self.arena.node(dad), self.arena.node(self.expr_mom(right)));
+            dad = self.new_node(NodeKind::BinExpr(BinExpr::new(dad_node, head, op, span.clone(), op_span)));
         }
         dad
     }
@@ -498,10 +895,8 @@ impl<'a> ParseState<'a> {
                 rule => unreachable!("{:?}", rule),
             };
             span = self.span(head.as_span().end(), span.start());
-            let head = self.expr_head(head);
-            let refs = mom.lifetime.depth;
-            mom = NodeKind::BinExpr(BinExpr::new(head, mom, op, span.clone(), op_span))
-                .life(self.depth(), refs);
+            let (head, mom_node) = (self.arena.node(self.expr_head(head)), self.arena.node(mom));
+            mom = self.new_node(NodeKind::BinExpr(BinExpr::new(head, mom_node, op, span.clone(), op_span)));
         }
         mom
     }
@@ -520,7 +915,7 @@ impl<'a> ParseState<'a> {
         };
         let inner = self.term(inner);
         if let Some(op) = op {
-            NodeKind::UnExpr(UnExpr::new(inner, op, span)).life(self.depth(), 0)
+            self.new_node(NodeKind::UnExpr(UnExpr::new(self.arena.node(inner), op, span)))
         } else {
             inner
         }
@@ -528,62 +923,36 @@ impl<'a> ParseState<'a> {
     fn term(&mut self, pair: Pair<'a, Rule>) -> Node<'a> {
         let span = pair.as_span();
         let pair = only(pair);
-        let (term, lifetime) = match pair.as_rule() {
+        let term = match pair.as_rule() {
             Rule::int => match pair.as_str().parse::<i64>() {
-                Ok(i) => (Term::Int(i), Lifetime::new(self.depth(), 0)),
+                Ok(i) => Term::Int(i),
                 Err(_) => {
                     self.errors
                         .push(TranspileError::InvalidLiteral(pair.as_span()));
-                    (Term::Int(0), Lifetime::new(self.depth(), 0))
+                    Term::Int(0)
                 }
             },
             Rule::real => match pair.as_str().parse::<f64>() {
-                Ok(i) => (Term::Real(i), Lifetime::new(self.depth(), 0)),
+                Ok(i) => Term::Real(i),
                 Err(_) => {
                     self.errors
                         .push(TranspileError::InvalidLiteral(pair.as_span()));
-                    (Term::Real(0.0), Lifetime::new(self.depth(), 0))
+                    Term::Real(0.0)
                 }
             },
             Rule::ident => {
                 let ident = self.ident(pair);
-                let lifetime = if let Some((_, binding)) = self
-                    .scopes
-                    .iter()
-                    .enumerate()
-                    .rev()
-                    .find_map(|(fi, fscope)| {
-                        fscope.scopes.iter().rev().find_map(|pscope| {
-                            pscope.bindings.get(ident.name).map(|binding| (fi, binding))
-                        })
-                    }) {
-                    let lt = binding.lifetime();
-                    if lt.depth > 0 && lt.depth < self.depth() {
-                        let affected_scopes = (self.depth() - lt.depth) as usize;
-                        for fscope in self.scopes.iter_mut().rev().take(affected_scopes) {
-                            let min_refs = &mut fscope.min_refs;
-                            *min_refs = (*min_refs).max(lt.depth);
-                        }
-                    }
-                    lt
-                } else {
-                    self.errors.push(TranspileError::UnknownDef(ident.clone()));
-                    Lifetime::STATIC
-                };
-                (Term::Ident(ident), lifetime)
+                self.resolve(&ident);
+                Term::Ident(ident)
             }
-            Rule::paren_expr => {
+            Rule::paren_expr | Rule::do_block => {
                 let pair = only(pair);
                 self.push_paren_scope();
-                let items = self.items(pair, true);
+                let items = self.items(pair);
                 self.pop_paren_scope();
-                let lifetime = Lifetime::new(self.depth(), items.last().unwrap().lifetime().refs);
-                (Term::Expr(items), lifetime)
-            }
-            Rule::string => {
-                let string = self.string_literal(pair);
-                (Term::String(string), Lifetime::STATIC)
+                Term::Expr(items)
             }
+            Rule::string => Term::String(self.string_literal(pair)),
             Rule::closure => {
                 let span = pair.as_span();
                 let mut pairs = pair.into_inner();
@@ -594,40 +963,28 @@ impl<'a> ParseState<'a> {
                     self.bind_param(param.ident.name);
                 }
                 let pair = pairs.next().unwrap();
-                let body = self.function_body(pair, true);
-                let min_refs = self.pop_function_scope();
-                let lifetime = Lifetime::new(
-                    self.depth(),
-                    body.last().unwrap().lifetime().refs.max(min_refs),
-                );
-                (
-                    Term::Closure(Closure { span, params, body }.into()),
-                    lifetime,
-                )
+                let body = self.function_body(pair);
+                self.pop_function_scope();
+                Term::Closure(self.arena.closure(Closure { span, params, body }))
             }
             Rule::list_literal => {
                 let items: Vec<Node> = pair.into_inner().map(|pair| self.term(pair)).collect();
                 if items.is_empty() {
-                    (
-                        Term::Ident(Ident {
-                            name: "nil",
-                            span: span.clone(),
-                        }),
-                        Lifetime::STATIC,
-                    )
+                    Term::Ident(Ident {
+                        name: "nil",
+                        span: span.clone(),
+                    })
                 } else {
                     let mut items = items.into_iter().rev();
                     let mut tail = items.next().unwrap();
                     for item in items {
-                        let refs = tail.lifetime.depth;
-                        tail = NodeKind::BinExpr(BinExpr {
-                            left: item.into(),
-                            right: tail.into(),
+                        tail = self.new_node(NodeKind::BinExpr(BinExpr {
+                            left: self.arena.node(item),
+                            right: self.arena.node(tail),
                             span: span.clone(),
                             op_span: span.clone(),
                             op: BinOp::Mom,
-                        })
-                        .life(self.depth(), refs);
+                        }));
                     }
                     return tail;
                 }
@@ -637,32 +994,16 @@ impl<'a> ParseState<'a> {
                 let left = self.term(pairs.next().unwrap());
                 let middle = self.term(pairs.next().unwrap());
                 let right = self.term(pairs.next().unwrap());
-                let refs = left
-                    .lifetime
-                    .depth
-                    .max(middle.lifetime.depth)
-                    .max(right.lifetime.depth);
-                (
-                    Term::Tree(Box::new([left, middle, right])),
-                    Lifetime::new(self.depth(), refs),
-                )
+                Term::Tree(self.arena.tree([left, middle, right]))
             }
             rule => unreachable!("{:?}", rule),
         };
-        NodeKind::Term(term, span).life(lifetime.depth, lifetime.refs)
+        self.new_node(NodeKind::Term(term, span))
     }
-    fn function_body(&mut self, pair: Pair<'a, Rule>, check_ref: bool) -> Items<'a> {
+    fn function_body(&mut self, pair: Pair<'a, Rule>) -> Items<'a> {
         match pair.as_rule() {
-            Rule::items => self.items(pair, check_ref),
-            Rule::expr => {
-                let node = self.expr(pair);
-                if check_ref && node.lifetime.refs >= self.depth() {
-                    self.errors.push(TranspileError::ReturnReferencesLocal(
-                        node.kind.span().clone(),
-                    ))
-                }
-                vec![Item::Node(node)]
-            }
+            Rule::items => self.items(pair),
+            Rule::expr => vec![Item::Node(self.expr(pair))],
             rule => unreachable!("{:?}", rule),
         }
     }
@@ -682,21 +1023,12 @@ impl<'a> ParseState<'a> {
                     s => unreachable!("{}", s),
                 }),
                 Rule::byte => {
-                    let byte = pair
-                        .into_inner()
-                        .map(|pair| pair.as_str())
-                        .collect::<String>()
-                        .parse::<u8>()
-                        .unwrap();
+                    let byte = u8::from_str_radix(&pair.as_str()[1..], 16).unwrap();
                     s.push(byte as char);
                 }
                 Rule::unicode => {
-                    let u = pair
-                        .into_inner()
-                        .map(|pair| pair.as_str())
-                        .collect::<String>()
-                        .parse::<u32>()
-                        .unwrap();
+                    let hex = pair.as_str().trim_start_matches("u{").trim_end_matches('}');
+                    let u = u32::from_str_radix(hex, 16).unwrap();
                     s.push(
                         std::char::from_u32(u).unwrap_or_else(|| panic!("invalid unicode {}", u)),
                     );
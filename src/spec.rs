@@ -0,0 +1,402 @@
+//! Table-driven tests of each grammar construct
+//!
+//! Each case names the grammar rule it is about and says whether its source
+//! should be accepted, be rejected with an error code, or run and print some
+//! output. `kin dev spec` runs every case and prints which grammar rules the
+//! cases match.
+
+use std::{collections::HashSet, fs, path::Path, process::Command};
+
+use crate::{ast::Arena, dev::parallel_map, parse, transpile::CompileOptions};
+
+/// What should happen to the source of a case
+enum Expect {
+    /// It should check without errors
+    Accept,
+    /// It should fail to check with the error code
+    Reject(&'static str),
+    /// It should run and print exactly this to stdout
+    Output(&'static str),
+}
+
+use Expect::*;
+
+struct Case {
+    rule: &'static str,
+    source: &'static str,
+    expect: Expect,
+}
+
+const fn case(rule: &'static str, source: &'static str, expect: Expect) -> Case {
+    Case {
+        rule,
+        source,
+        expect,
+    }
+}
+
+static CASES: &[Case] = &[
+    // Numbers
+    case("int", "println 42", Output("42\n")),
+    case("int", "println -7", Output("-7\n")),
+    case("int", "println 99999999999999999999", Reject("E0004")),
+    case("real", "println 1.5", Output("1.5\n")),
+    case("real", "println 2.5e3", Output("2500\n")),
+    case("real", "println 1.0e300", Output("1e+300\n")),
+    case("real", "println 1.", Reject("E0003")),
+    // Strings
+    case("string", r#"println "hi""#, Output("hi\n")),
+    case("string", r#"println "unclosed"#, Reject("E0003")),
+    case("predefined", r#"println "a\tb""#, Output("a\tb\n")),
+    case("predefined", r#"println "\q""#, Reject("E0003")),
+    case("byte", r#"println "\x41""#, Output("A\n")),
+    case("unicode", r#"println "\u{263A}""#, Output("\u{263A}\n")),
+    // Idents
+    case("ident", "x = 1\nprintln x", Output("1\n")),
+    case("ident", "println undefined", Reject("E0001")),
+    case(
+        "starts_with_keyword",
+        "order = 1\nprintln order",
+        Output("1\n"),
+    ),
+    case("starts_with_keyword", "return_value = 1", Accept),
+    case("starts_with_keyword", "do_it = 2", Accept),
+    case("starts_with_keyword", "end_x = 3", Accept),
+    case("starts_with_keyword", "android = 4", Accept),
+    case("ident", "and = 1", Reject("E0003")),
+    case("ident", "do = 1", Reject("E0003")),
+    case("ident", "return = 1", Reject("E0003")),
+    case("ident", "println (ne 1 2)", Output("true\n")),
+    // Data structure literals
+    case("list_literal", "println [1 2 3]", Output("1\n")),
+    case("list_literal", "println [1 2", Reject("E0003")),
+    case("list_literal", "println ![\n    4,\n    5,\n]", Output("4\n")),
+    case("list_literal", "x = [1, 2\n    3 -- three\n]", Accept),
+    case("list_literal", "x = [1,, 2]", Reject("E0003")),
+    case("list_literal", "x = [, 1]", Reject("E0003")),
+    case("tree_literal", "x = {1 2 3}", Accept),
+    case("tree_literal", "x = {\n    1\n    2\n    3\n}", Accept),
+    case("tree_literal", "x = {1, 2, 3,}", Accept),
+    case(
+        "tree_literal",
+        "t = {1 2 3}\nprintln (tree_left t)\nprintln (tree_val t)\nprintln (tree_right t)",
+        Output("1\n2\n3\n"),
+    ),
+    case(
+        "tree_literal",
+        "tree_walk {{1 2 3} 4 5} println",
+        Output("1\n2\n3\n4\n5\n"),
+    ),
+    case("tree_literal", "x = {1 2}", Reject("E0003")),
+    // Operators
+    case("op_as", "println (1 + 2 - 4)", Output("-1\n")),
+    case("op_mdr", "println (7 // 2)", Output("3\n")),
+    case("op_mdr", "println (7 mod 3)", Output("1\n")),
+    case(
+        "op_mdr",
+        "println (recover (|| \"abc\" * 9223372036854775807))",
+        Output("Error: Attempted to repeat a string too many times\n"),
+    ),
+    case(
+        "op_mdr",
+        "println (recover (|| [1 2] * 9223372036854775807))",
+        Output("Error: Attempted to repeat a list too many times\n"),
+    ),
+    case(
+        "op_mdr",
+        "x = -9223372036854775807 - 1\nprintln (recover (|| x // -1))",
+        Output("Error: Attempted to divide the minimum int by -1, which overflows\n"),
+    ),
+    case(
+        "op_mdr",
+        "x = -9223372036854775807 - 1\nprintln (x mod -1)",
+        Output("0\n"),
+    ),
+    case("op_pow", "println (2 ^ 10)", Output("1024\n")),
+    case("op_pow", "println (3 ^ 39)", Output("4052555153018976267\n")),
+    case("op_pow", "println (2 ^ 64)", Output("1.8446744073709552e+19\n")),
+    case("op_comp", "println (1 < 2 < 3)", Output("true\n")),
+    case("op_and", "println (true and 2)", Output("2\n")),
+    case("op_or", "println (false or 3)", Output("3\n")),
+    case("op_coalesce", "println (nil ?? 5)", Output("5\n")),
+    case("op_custom", "println (1 <+> 2)", Reject("E0002")),
+    case("op_mdr", "println (2*-3)", Output("-6\n")),
+    case("op_mdr", "x = 2\nprintln (2*-x)", Output("-4\n")),
+    case("op_comp", "x = 1\nprintln (x==-1)", Output("false\n")),
+    case("op_comp", "println (1 <-1)", Output("false\n")),
+    case("op_as", "println (1+-1)", Output("0\n")),
+    case("op_pow", "println (2^-1)", Output("0.5\n")),
+    case("op_mdr", "x = 3\ny = x*-x", Accept),
+    case("op_custom", "println (1 <-> 2)", Reject("E0002")),
+    case("op_mom", "println !(1 : 2)", Output("1\n")),
+    case("op_dad", "x = 1 :: 2", Accept),
+    case("op_neg", "x = 3\nprintln (-x)", Output("-3\n")),
+    case("op_neg", "println (- (2 * 3 - 10))", Output("4\n")),
+    case("op_as", "println (-9223372036854775807 - 1)", Output("-9223372036854775808\n")),
+    case("op_not", "println (not true)", Output("false\n")),
+    case("op_head", "println ![4 5]", Output("4\n")),
+    case("op_as", "println (1 +)", Reject("E0003")),
+    // Expressions
+    case("paren_expr", "println (1 + (2 * 3))", Output("7\n")),
+    case("paren_expr", "println (1", Reject("E0003")),
+    case(
+        "do_block",
+        "x = 1\ny = do\n    x = 2\n    x * 10\nend\nprintln (x + y)",
+        Output("21\n"),
+    ),
+    case(
+        "do_block",
+        "println do\n    a = 3\n    a + 1\nend",
+        Output("4\n"),
+    ),
+    case("do_block", "x = do\n    y = 1\nend", Reject("E0009")),
+    case("do_block", "x = do\n    1\n", Reject("E0003")),
+    case("do_block", "x = do\n    y = 1\n    y\nend\nprintln y", Reject("E0001")),
+    case("closure", "f = |x| x * 2\nprintln (f 4)", Output("8\n")),
+    case(
+        "closure",
+        "f = |x|\n    y = x + 1\n    y * 2\nend\nprintln (f 4)",
+        Output("10\n"),
+    ),
+    case(
+        "param",
+        "double (x: int) = x * 2\nprintln (double 4)",
+        Output("8\n"),
+    ),
+    case("param", "f (x: nope) = x", Reject("E0011")),
+    case("return_type", "one -> int = 1\nprintln one", Output("1\n")),
+    case("chain_call", "5, println", Output("5\n")),
+    // Items
+    case("def", "sum a b = a + b\nprintln (sum 1 2)", Output("3\n")),
+    case(
+        "def",
+        "sum a b =\n    s = a + b\n    s\nend\nprintln (sum 1 2)",
+        Output("3\n"),
+    ),
+    case(
+        "def",
+        "x =\n    y = 1\n    y + 1\nend\nprintln y",
+        Reject("E0001"),
+    ),
+    case(
+        "paren_expr",
+        "x = 1\ny = (\n    x = 2\n    x\n)\nprintln (x + y)",
+        Output("3\n"),
+    ),
+    case("def", "x =\n    y = 1\nend", Reject("E0009")),
+    case("def", "f x =\n    y = x\nend", Reject("E0009")),
+    case("paren_expr", "x = (y = 1)", Reject("E0009")),
+    case("def", "true = 1", Reject("E0008")),
+    case(
+        "deprecated",
+        "#[deprecated \"use g\"]\nf x = x + 1\nprintln (f 1)",
+        Output("2\n"),
+    ),
+    case("deprecated", "#[deprecated]\n\nx = 1", Accept),
+    case("deprecated", "#[deprecated] x = 1", Reject("E0003")),
+    case("deprecated", "#[deprecated]\nprintln 1", Reject("E0003")),
+    case("def", "f a b = [a b]\nprintln !(f 1 2)", Output("1\n")),
+    case("def", "f a b c = [a b c]", Reject("E0007")),
+    case("def", "g a b = a : b\nf x y = g x y", Reject("E0007")),
+    case(
+        "def",
+        "f x =\n    y = x + 1\n    x : y\nend",
+        Reject("E0007"),
+    ),
+    case(
+        "def",
+        "f x =\n    g y = x + y\n    g\nend",
+        Reject("E0007"),
+    ),
+    case(
+        "def",
+        "f a =\n    b = a * 10\n    recover (|| b + 1)\nend\nprintln (f 2)",
+        Output("21\n"),
+    ),
+    case("closure", "f x = |y| x + y", Reject("E0007")),
+    case("def", "f x = error x", Reject("E0007")),
+    case("def", "f x = x > 2 and panic (x : \"big\") or x\nprintln (f 1)", Output("1\n")),
+    case(
+        "op_def",
+        "operator <+> precedence 4 = |a b| a * 10 + b\nprintln (1 <+> 2)",
+        Output("12\n"),
+    ),
+    case(
+        "type_def",
+        "type Num = int | real\nf (x: Num) = x\nprintln (f 1)",
+        Output("1\n"),
+    ),
+    case("type_def", "x = 2\ntyped = x\nprintln typed", Output("2\n")),
+    case("op_def", "operators = 3\nprintln operators", Output("3\n")),
+    case(
+        "op_def",
+        "operator <+> precedence 4 = |a b| a + b\noperator <+> precedence 5 = |a b| a * b",
+        Reject("E0016"),
+    ),
+    case(
+        "op_def",
+        "f x =\n    operator <+> precedence 4 = |a b| a + b\n    x <+> 1\nend\nprintln (1 <+> 2)",
+        Reject("E0002"),
+    ),
+    case(
+        "op_def",
+        "operator <+> precedence 4 = |a b| a + b\nf x =\n    operator <+> precedence 5 = |a b| a * b\n    x <+> 2\nend\nprintln (f 3 <+> 1)",
+        Output("7\n"),
+    ),
+    case(
+        "op_def",
+        "operator <| precedence 1 = |f x| f x\nprintln <| 1 + 2",
+        Output("3\n"),
+    ),
+    case(
+        "defer",
+        "defer println \"last\"\nprintln \"first\"",
+        Output("first\nlast\n"),
+    ),
+    case("defer", "deferred = 1\nprintln deferred", Output("1\n")),
+    case(
+        "discard",
+        "discard println 1\nprintln 2",
+        Output("1\n2\n"),
+    ),
+    case("discard", "_ = println 1\nprintln 2", Output("1\n2\n")),
+    case("discard", "discarded = 1\nprintln discarded", Output("1\n")),
+    case(
+        "early_return",
+        "f x =\n    x > 1 and (return \"big\")\n    \"small\"\nend\nprintln (f 2)\nprintln (f 0)",
+        Output("big\nsmall\n"),
+    ),
+    case("early_return", "return 1", Reject("E0015")),
+    case("early_return", "f x -> int =\n    return \"no\"\nend", Reject("E0012")),
+    case(
+        "early_return",
+        "f x =\n    x and (return |y| x + y)\n    nil\nend",
+        Reject("E0007"),
+    ),
+    case("discard", "f x =\n    discard x\nend", Reject("E0009")),
+    case("discard", "discard", Reject("E0003")),
+    case("file", "x = 1 \\\n    + 2\nprintln x", Output("3\n")),
+    case(
+        "file",
+        "f a b = a - b\nprintln (f 5 \\\n    2)",
+        Output("3\n"),
+    ),
+    case("file", "", Accept),
+    case("version", "#version 0.1\nprintln 1", Output("1\n")),
+    case("version", "#!/usr/bin/env kin\n#version 0.1", Accept),
+    case("version_number", "#version 9.0\nprintln 1", Reject("E0014")),
+    case("version_number", "#version 1\nprintln 1", Reject("E0003")),
+    case("version", "println 1\n#version 0.1", Reject("E0003")),
+    case("file", "#!/usr/bin/env kin\nprintln 1", Output("1\n")),
+    case(
+        "file",
+        "println 1 -- comment\n'''block'''\nprintln 2",
+        Output("1\n2\n"),
+    ),
+];
+
+/// The names of the grammar rules that can appear in a parse tree
+fn grammar_rules() -> Vec<&'static str> {
+    include_str!("grammar.pest")
+        .lines()
+        .filter_map(|line| {
+            let (name, body) = line.split_once(" = ")?;
+            if body.starts_with("_{") || name.contains(char::is_whitespace) {
+                None
+            } else {
+                Some(name)
+            }
+        })
+        .collect()
+}
+
+/// Check a case, returning why it failed if it did
+fn check(case: &Case, index: usize, exe: &Path) -> Result<(), String> {
+    let options = CompileOptions::default();
+    let arena = Arena::default();
+    let result = parse::parse(case.source, &arena, &options).map_err(|errors| errors[0].code());
+    match (&case.expect, result) {
+        (Accept, Ok(_)) => Ok(()),
+        (Reject(code), Err(found)) if *code == found => Ok(()),
+        (Reject(code), Err(found)) => Err(format!("expected {}, found {}", code, found)),
+        (Reject(code), Ok(_)) => Err(format!("expected {}, but it was accepted", code)),
+        (Accept, Err(found)) | (Output(_), Err(found)) => {
+            Err(format!("expected it to be accepted, found {}", found))
+        }
+        (Output(expected), Ok(_)) => {
+            let dir = std::env::temp_dir().join("kin-spec");
+            let path = dir.join(format!("case{}.kin", index));
+            fs::create_dir_all(&dir)
+                .and_then(|_| fs::write(&path, case.source))
+                .map_err(|e| format!("unable to write {}: {}", path.display(), e))?;
+            let output = Command::new(exe)
+                .arg(&path)
+                .output()
+                .map_err(|e| format!("unable to run: {}", e))?;
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            if stdout == *expected {
+                Ok(())
+            } else {
+                Err(format!(
+                    "expected output {:?}, found {:?}",
+                    expected, stdout
+                ))
+            }
+        }
+    }
+}
+
+/// Run every case and print a matrix of the grammar rules they cover
+pub fn run() -> bool {
+    let exe = std::env::current_exe().unwrap();
+    let indices: Vec<usize> = (0..CASES.len()).collect();
+    let results = parallel_map(&indices, |&i| check(&CASES[i], i, &exe));
+    let mut failures = 0;
+    for (case, result) in CASES.iter().zip(results) {
+        if let Err(reason) = result {
+            failures += 1;
+            println!("FAILED {} case {:?}\n  {}", case.rule, case.source, reason);
+        }
+    }
+    println!(
+        "{} of {} cases passed\n",
+        CASES.len() - failures,
+        CASES.len()
+    );
+
+    // Coverage
+    let mut matched = HashSet::new();
+    for case in CASES {
+        matched.extend(parse::matched_rules(case.source).unwrap_or_default());
+    }
+    let rules = grammar_rules();
+    let width = rules.iter().map(|rule| rule.len()).max().unwrap_or(0);
+    println!(
+        "{:width$}  accept  reject  output  matched",
+        "rule",
+        width = width
+    );
+    for rule in &rules {
+        let count = |f: fn(&Expect) -> bool| {
+            CASES
+                .iter()
+                .filter(|case| case.rule == *rule && f(&case.expect))
+                .count()
+        };
+        println!(
+            "{:width$}  {:>6}  {:>6}  {:>6}  {}",
+            rule,
+            count(|expect| matches!(expect, Accept)),
+            count(|expect| matches!(expect, Reject(_))),
+            count(|expect| matches!(expect, Output(_))),
+            if matched.contains(*rule) { "yes" } else { "no" },
+            width = width
+        );
+    }
+    println!(
+        "{} of {} grammar rules matched by a case",
+        rules.iter().filter(|rule| matched.contains(**rule)).count(),
+        rules.len()
+    );
+    failures == 0
+}
@@ -0,0 +1,172 @@
+//! Opt-in statistics about compiles, kept only on this machine
+//!
+//! Nothing is recorded unless the stats file exists, which `kin stats
+//! enable` creates. Each compile appends a line with when it happened, how
+//! long it took, the size of the source, and the codes of any errors.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// The file that stats are appended to
+pub fn path() -> PathBuf {
+    let base = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("state"))
+        })
+        .unwrap_or_else(std::env::temp_dir);
+    base.join("kin").join("stats.tsv")
+}
+
+/// Start recording stats
+pub fn enable() -> io::Result<()> {
+    let path = path();
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map(drop)
+}
+
+/// Stop recording stats and delete the ones recorded so far
+pub fn disable() -> io::Result<()> {
+    match fs::remove_file(path()) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// A compile whose stats are recorded when it finishes
+pub struct Session {
+    start: Instant,
+    bytes: usize,
+}
+
+impl Session {
+    pub fn start(input: &str) -> Self {
+        Session {
+            start: Instant::now(),
+            bytes: input.len(),
+        }
+    }
+    /// Record the compile if stats are enabled, along with the codes of the
+    /// errors that stopped it
+    ///
+    /// Failing to record is not an error.
+    pub fn finish(&self, codes: &[&str]) {
+        let path = path();
+        if !path.exists() {
+            return;
+        }
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let line = format!(
+            "{}\t{}\t{}\t{}\n",
+            time.as_secs(),
+            self.start.elapsed().as_millis(),
+            self.bytes,
+            if codes.is_empty() {
+                "-".into()
+            } else {
+                codes.join(",")
+            }
+        );
+        let _ = fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(line.as_bytes()));
+    }
+}
+
+/// One recorded compile
+struct Record {
+    millis: u64,
+    bytes: u64,
+    codes: Vec<String>,
+}
+
+fn records(text: &str) -> Vec<Record> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t').skip(1);
+            Some(Record {
+                millis: fields.next()?.parse().ok()?,
+                bytes: fields.next()?.parse().ok()?,
+                codes: match fields.next()? {
+                    "-" => Vec::new(),
+                    codes => codes.split(',').map(Into::into).collect(),
+                },
+            })
+        })
+        .collect()
+}
+
+/// Print a summary of the recorded stats
+pub fn print_summary() {
+    let path = path();
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(_) => {
+            println!("Stats are not enabled. Run `kin stats enable` to start recording them.");
+            return;
+        }
+    };
+    let records = records(&text);
+    println!("Stats from {}", path.display());
+    if records.is_empty() {
+        println!("No compiles have been recorded");
+        return;
+    }
+    let failed = records.iter().filter(|r| !r.codes.is_empty()).count();
+    println!("{} compiles, {} failed", records.len(), failed);
+    let millis: Vec<u64> = records.iter().map(|r| r.millis).collect();
+    let bytes: Vec<u64> = records.iter().map(|r| r.bytes).collect();
+    println!(
+        "Compile time: mean {:.2?}, max {:.2?}",
+        Duration::from_millis(mean(&millis)),
+        Duration::from_millis(max(&millis))
+    );
+    println!(
+        "Source size: mean {} bytes, max {} bytes",
+        mean(&bytes),
+        max(&bytes)
+    );
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for code in records.iter().flat_map(|r| &r.codes) {
+        *counts.entry(code).or_default() += 1;
+    }
+    if !counts.is_empty() {
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        println!("Errors:");
+        for (code, count) in counts {
+            println!("  {:<18} {}", code, count);
+        }
+    }
+}
+
+fn mean(values: &[u64]) -> u64 {
+    values.iter().sum::<u64>() / values.len().max(1) as u64
+}
+
+fn max(values: &[u64]) -> u64 {
+    values.iter().copied().max().unwrap_or_default()
+}
+
+/// Print the compiler version, platform, and stats summary for a bug report
+pub fn print_report() {
+    println!();
+    println!(
+        "kin {} on {}",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS
+    );
+    print_summary();
+}
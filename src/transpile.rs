@@ -1,18 +1,26 @@
 use std::{
-    collections::{BTreeMap, VecDeque},
-    fs::{self, File},
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
     io::{self, Write},
     iter::once,
+    mem::take,
+    path::Path,
 };
 
 use itertools::*;
+use pest::Span;
 use rpds::{RedBlackTreeMap, Vector};
 
-use crate::ast::*;
+use crate::{ast::*, const_fold};
+
+#[cfg(feature = "native")]
+use std::fs::{self, File};
 
 struct KinDef {
     is_function: bool,
     c_name: String,
+    /// The index in the function stack of the C function whose locals hold
+    /// the def, or `None` for builtins
+    owner: Option<usize>,
 }
 
 macro_rules! builtin_functions {
@@ -26,17 +34,55 @@ pub const BUILTIN_FUNCTIONS: &[(&str, &str)] = builtin_functions!(
     "dad",
     "print",
     "println",
+    "eprint",
+    "eprintln",
+    "log_debug",
+    "log_info",
+    "log_error",
     "error",
     "panic",
-    "not",
+    "recover",
     "assert",
+    "exit",
+    "bnot",
+    "apply",
+    "repeat",
+    "recur",
+    "trampoline",
+    "serialize",
+    "deserialize",
+    "config_parse",
+    "list_dir",
+    "mkdir",
+    "remove_file",
+    "path_join",
+    "basename",
+    "extension",
+    "glob",
+    "hash",
+    "md5",
+    "sha256",
+    "crc32",
+    "tree_left",
+    "tree_val",
+    "tree_right",
+    "tree_insert",
+    "tree_walk",
     ("add", "kin_add_fn"),
     ("sub", "kin_sub_fn"),
     ("mul", "kin_mul_fn"),
     ("div", "kin_div_fn"),
+    ("floor_div", "kin_floor_div_fn"),
     ("rem", "kin_rem_fn"),
+    ("modulo", "kin_mod_fn"),
+    ("pow", "kin_pow_fn"),
+    ("band", "kin_band_fn"),
+    ("bor", "kin_bor_fn"),
+    ("bxor", "kin_bxor_fn"),
+    ("shl", "kin_shl_fn"),
+    ("shr", "kin_shr_fn"),
     ("eq", "kin_eq_fn"),
-    ("ne", "kin_ne_fn"),
+    ("ne", "kin_neq_fn"),
     ("lt", "kin_lt_fn"),
     ("le", "kin_le_fn"),
     ("gt", "kin_gt_fn"),
@@ -49,6 +95,13 @@ pub const BUILTIN_VALUES: &[(&str, &str)] = &[
     ("false", "KIN_FALSE"),
 ];
 
+/// The Kin runtime, which is written next to the generated C source
+pub const KIN_HEADER: &str = include_str!("../clibs/kin.h");
+
+/// Expressions longer than this are bound to a temporary rather than
+/// nested further, which keeps long operator chains linear to emit
+const MAX_INLINE_EXPR_LEN: usize = 1024;
+
 static RESERVED_NAMES: &[&str] = &[
     // C keywords
     "auto",
@@ -108,6 +161,7 @@ impl<'a> TranspileStack<'a> {
                             KinDef {
                                 c_name: c_name.into(),
                                 is_function: true,
+                                owner: None,
                             },
                         )
                     })
@@ -117,6 +171,7 @@ impl<'a> TranspileStack<'a> {
                             KinDef {
                                 c_name: c_name.into(),
                                 is_function: false,
+                                owner: None,
                             },
                         )
                     }))
@@ -124,6 +179,17 @@ impl<'a> TranspileStack<'a> {
             ),
         }
     }
+    /// Start a scope for a function or block body
+    ///
+    /// The scopes match the ones the parser resolves names in. Because the
+    /// stack is persistent, a scope ends when the stack it was pushed onto is
+    /// dropped.
+    pub fn push_scope(self) -> Self {
+        TranspileStack {
+            kin_scopes: self.kin_scopes.push_back(RedBlackTreeMap::new()),
+        }
+    }
+    /// Bind a def in the innermost scope
     pub fn with_kin_def(self, name: &'a str, def: KinDef) -> Self {
         TranspileStack {
             kin_scopes: self
@@ -135,12 +201,28 @@ impl<'a> TranspileStack<'a> {
                 .unwrap(),
         }
     }
+    /// Find the def a name refers to, starting from the innermost scope
+    pub fn get(&self, name: &str) -> Option<&KinDef> {
+        self.kin_scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+    }
 }
 
 #[derive(Clone)]
 pub struct Transpilation<'a> {
+    /// Sorted so that the same input always produces the same source
     functions: BTreeMap<String, CFunction<'a>>,
     function_stack: Vec<String>,
+    var_names: HashSet<String>,
+    name_counts: HashMap<String, usize>,
+    options: CompileOptions,
+    /// The source the items were parsed from
+    input: &'a str,
+    /// The byte offset of the start of each line of the input, so that
+    /// finding a line does not rescan the whole input
+    line_starts: Vec<usize>,
 }
 
 #[derive(Clone)]
@@ -150,16 +232,35 @@ struct CFunction<'a> {
     lines: Vec<CLine>,
     captures: Vec<CCapture>,
     indent: usize,
+    deferred: Vec<(Node<'a>, TranspileStack<'a>)>,
+    /// The return type annotation that returned values are checked against
+    ret: Option<TypeAnn<'a>>,
+    /// The Kin source line of the node being transpiled, if line
+    /// directives are enabled
+    kin_line: Option<usize>,
+    /// The calls whose value the function returns
+    tail_nodes: HashSet<NodeId>,
+    /// The C functions of the user functions called in tail position
+    tail_callees: Vec<String>,
+    /// Whether the function returns thunks from its tail calls or runs them,
+    /// so it must start a trampoline when it is not a step of one
+    trampolined: bool,
 }
 
 impl<'a> CFunction<'a> {
-    pub fn new(kin_name: &'a str) -> CFunction {
+    pub fn new(kin_name: &'a str) -> CFunction<'a> {
         CFunction {
             kin_name,
             exprs: Default::default(),
             lines: Default::default(),
             captures: Default::default(),
+            deferred: Default::default(),
+            ret: None,
             indent: 0,
+            kin_line: None,
+            tail_nodes: Default::default(),
+            tail_callees: Default::default(),
+            trampolined: false,
         }
     }
 }
@@ -171,6 +272,7 @@ struct CLine {
     value: String,
     indent: usize,
     semicolon: bool,
+    kin_line: Option<usize>,
 }
 
 impl CLine {
@@ -207,6 +309,7 @@ impl<'a> CFunction<'a> {
             value: value.into(),
             indent: self.indent,
             semicolon: true,
+            kin_line: self.kin_line,
         };
         self.lines.push(line);
         self.lines.last_mut().unwrap()
@@ -240,28 +343,198 @@ impl<'a> CFunction<'a> {
     }
 }
 
-pub fn transpile(items: Items) -> Transpilation {
-    let mut transpilation = Transpilation::new();
+/// Get the runtime function for an operator that evaluates both operands,
+/// and whether it can fail
+///
+/// Operators that are lowered inline have no function.
+pub fn bin_op_fn(op: BinOp) -> Option<(&'static str, bool)> {
+    Some(match op {
+        BinOp::Equals => ("kin_eq", false),
+        BinOp::NotEquals => ("kin_neq", false),
+        BinOp::Less => ("kin_lt", true),
+        BinOp::LessOrEqual => ("kin_le", true),
+        BinOp::Greater => ("kin_gt", true),
+        BinOp::GreaterOrEqual => ("kin_ge", true),
+        BinOp::Add => ("kin_add", true),
+        BinOp::Sub => ("kin_sub", true),
+        BinOp::Mul => ("kin_mul", true),
+        BinOp::Div => ("kin_div", true),
+        BinOp::FloorDiv => ("kin_floor_div", true),
+        BinOp::Rem => ("kin_rem", true),
+        BinOp::Mod => ("kin_mod", true),
+        BinOp::Pow => ("kin_pow", true),
+        BinOp::Or | BinOp::And | BinOp::Coalesce | BinOp::Mom | BinOp::Dad => return None,
+    })
+}
+
+/// Turn a Kin name into a valid C identifier
+///
+/// Custom operators are bound to names made of symbols, so spell those out.
+fn c_ident(kin_name: &str) -> String {
+    if !kin_name.contains(|c: char| OPERATOR_CHAR_NAMES.iter().any(|&(oc, _)| oc == c)) {
+        return kin_name.into();
+    }
+    once("op")
+        .chain(kin_name.chars().map(|c| {
+            OPERATOR_CHAR_NAMES
+                .iter()
+                .find(|&&(oc, _)| oc == c)
+                .map_or("x", |&(_, name)| name)
+        }))
+        .intersperse("_")
+        .collect()
+}
+
+static OPERATOR_CHAR_NAMES: &[(char, &str)] = &[
+    ('+', "plus"),
+    ('-', "minus"),
+    ('*', "star"),
+    ('/', "slash"),
+    ('%', "percent"),
+    ('<', "lt"),
+    ('>', "gt"),
+    ('=', "eq"),
+    ('!', "bang"),
+    ('&', "amp"),
+    ('^', "caret"),
+    ('~', "tilde"),
+    ('?', "question"),
+    ('@', "at"),
+    ('$', "dollar"),
+    ('|', "pipe"),
+];
+
+/// Format a real as a C double literal that round-trips exactly
+///
+/// Rust's `Debug` output for `f64` is the shortest representation that parses
+/// back to the same value, is locale-independent, and always contains a `.` or
+/// an exponent, so C treats it as a double.
+fn c_real_literal(f: f64) -> String {
+    if f.is_nan() {
+        "NAN".into()
+    } else if f.is_infinite() {
+        if f > 0.0 { "INFINITY" } else { "-INFINITY" }.into()
+    } else {
+        format!("{:?}", f)
+    }
+}
+
+// Options that affect checking and code generation
+#[derive(Debug, Clone, Default, Hash, clap::Clap)]
+pub struct CompileOptions {
+    #[clap(
+        long = "strict-bool",
+        about = "Only allow bools as conditions of and, or, and not"
+    )]
+    pub strict_bool: bool,
+    #[clap(
+        long = "line-directives",
+        about = "Emit #line directives so that C tools report Kin source lines"
+    )]
+    pub line_directives: bool,
+    #[clap(
+        long = "allow",
+        number_of_values = 1,
+        about = "Do not report a lint"
+    )]
+    pub allow: Vec<String>,
+    #[clap(
+        long = "warn",
+        number_of_values = 1,
+        about = "Report a lint as a warning"
+    )]
+    pub warn: Vec<String>,
+    #[clap(
+        long = "deny",
+        number_of_values = 1,
+        about = "Report a lint as an error that stops compilation"
+    )]
+    pub deny: Vec<String>,
+    #[clap(
+        long = "attach-stats",
+        about = "If compilation fails, print the compiler version and local stats for a bug report"
+    )]
+    pub attach_stats: bool,
+    #[clap(
+        long = "debug-codegen",
+        about = "Panic if the transpiler pushes or pops the wrong number of C expressions for a node"
+    )]
+    pub debug_codegen: bool,
+}
+
+/// Collect the calls whose value a body returns
+fn tail_nodes(items: &[Item], ids: &mut HashSet<NodeId>) {
+    let mut node = match items.last() {
+        Some(Item::Node(node) | Item::Return(node)) => node,
+        _ => return,
+    };
+    loop {
+        match &node.kind {
+            NodeKind::Call(_) => {
+                ids.insert(node.id);
+                return;
+            }
+            NodeKind::BinExpr(expr)
+                if matches!(expr.op, BinOp::Or | BinOp::And | BinOp::Coalesce) =>
+            {
+                node = expr.right
+            }
+            NodeKind::Term(Term::Expr(items), _) => return tail_nodes(items, ids),
+            _ => return,
+        }
+    }
+}
+
+pub fn transpile<'a>(
+    items: Items<'a>,
+    input: &'a str,
+    arena: &'a Arena<'a>,
+    options: &CompileOptions,
+) -> Transpilation<'a> {
+    let items = const_fold::fold(items, arena);
+    let mut transpilation = Transpilation::new(input, options.clone());
     transpilation.items(items, TranspileStack::new());
+    transpilation.run_deferred();
     transpilation
 }
 
 impl<'a> Transpilation<'a> {
-    pub fn new() -> Self {
+    pub fn new(input: &'a str, options: CompileOptions) -> Self {
         Transpilation {
             functions: once("main")
                 // .chain(BUILTINS.iter().map(|bi| bi.0))
                 .map(|name| (name.into(), CFunction::new(name)))
                 .collect(),
             function_stack: once("main".into()).collect(),
+            var_names: HashSet::new(),
+            name_counts: HashMap::new(),
+            options,
+            input,
+            line_starts: once(0)
+                .chain(input.match_indices('\n').map(|(i, _)| i + 1))
+                .collect(),
         }
     }
-    pub fn write(self) -> io::Result<()> {
-        fs::create_dir_all("build")?;
-        let mut source = File::create("build/main.c")?;
-
+    /// The 1-based line and column of a byte offset in the input
+    fn line_col(&self, pos: usize) -> (usize, usize) {
+        let line = self.line_starts.partition_point(|&start| start <= pos);
+        let col = self.input[self.line_starts[line - 1]..pos].chars().count() + 1;
+        (line, col)
+    }
+    /// Write the C source and the runtime header to a build directory
+    #[cfg(feature = "native")]
+    pub fn write(self, build_dir: &Path, input_path: &Path) -> io::Result<()> {
+        fs::create_dir_all(build_dir)?;
+        fs::write(build_dir.join("kin.h"), KIN_HEADER)?;
+        let mut source = File::create(build_dir.join("main.c"))?;
+        self.write_source(&mut source, input_path)
+    }
+    /// Write the C source
+    ///
+    /// The input path is only used for line directives.
+    pub fn write_source(&self, source: &mut impl Write, input_path: &Path) -> io::Result<()> {
         // Write headers
-        writeln!(source, "#include \"../clibs/kin.h\"")?;
+        writeln!(source, "#include \"kin.h\"")?;
         writeln!(source)?;
 
         // Write function declarations
@@ -288,6 +561,7 @@ impl<'a> Transpilation<'a> {
             // Write signature
             if main {
                 writeln!(source, "int main(int argc, char** argv) {{")?;
+                writeln!(source, "    kin_init();")?;
             } else if cf.captures.is_empty() {
                 writeln!(
                     source,
@@ -301,8 +575,24 @@ impl<'a> Transpilation<'a> {
                     name
                 )?;
             }
+            // Run on a trampoline unless called by one
+            if cf.trampolined {
+                let value = if cf.captures.is_empty() {
+                    format!("new_function(&{})", name)
+                } else {
+                    format!("new_closure(&{}, captures)", name)
+                };
+                writeln!(
+                    source,
+                    "    if (!kin_is_tail_step((KinFn)&{})) return kin_enter_trampoline({}, count, args);",
+                    name, value
+                )?;
+            }
             // Write lines
             for line in &cf.lines {
+                if let Some(kin_line) = line.kin_line {
+                    writeln!(source, "#line {} {:?}", kin_line, input_path.display().to_string())?;
+                }
                 write!(source, "{:indent$}", "", indent = (line.indent + 1) * 4)?;
                 if let Some(type_name) = line.type_name {
                     write!(source, "{} ", type_name)?;
@@ -320,9 +610,10 @@ impl<'a> Transpilation<'a> {
             // Clean up main
             if main {
                 if let Some(expr) = cf.clone().pop_expr() {
-                    writeln!(source, "    {};", expr)?;
+                    writeln!(source, "    return kin_exit_status({});", expr)?;
+                } else {
+                    writeln!(source, "    return 0;")?;
                 }
-                writeln!(source, "    return 0;")?;
             }
             // Close function
             writeln!(source, "}}\n")?;
@@ -332,41 +623,91 @@ impl<'a> Transpilation<'a> {
     }
     fn c_name_exists(&self, c_name: &str, function: bool) -> bool {
         RESERVED_NAMES.contains(&c_name)
-            || function && self.functions.keys().any(|name| name == c_name)
-            || !function
-                && self
-                    .functions
-                    .values()
-                    .flat_map(|cf| &cf.lines)
-                    .filter_map(|cf| cf.var_name.as_ref())
-                    .any(|var_name| var_name == c_name)
+            || function && self.functions.contains_key(c_name)
+            || !function && self.var_names.contains(c_name)
     }
-    fn c_name_for(&self, kin_name: &str, function: bool) -> String {
+    fn c_name_for(&mut self, kin_name: &str, function: bool) -> String {
+        let kin_name = &c_ident(kin_name);
         let mut c_name = kin_name.to_owned();
         if c_name.starts_with("kin") || c_name.starts_with("Kin") {
             c_name = "_".to_owned() + &c_name;
         }
-        let mut i = 1;
+        // Resume numbering where the last name with this base left off
+        let mut i = self.name_counts.get(kin_name).copied().unwrap_or(1);
         while self.c_name_exists(&c_name, function) {
             i += 1;
             c_name = format!("{}_{}", kin_name, i);
         }
+        self.name_counts.insert(kin_name.into(), i);
+        if !function {
+            self.var_names.insert(c_name.clone());
+        }
         c_name
     }
+    /// Bind an expression to a new temporary variable and return its name
+    fn bind_temp(&mut self, expr: String, name: &str) -> String {
+        let name = self.c_name_for(name, false);
+        self.c_function().push_line(expr).name(&name);
+        name
+    }
     fn start_c_function(&mut self, c_name: String, kin_name: &'a str) {
         self.functions
             .insert(c_name.clone(), CFunction::new(kin_name));
         self.function_stack.push(c_name);
     }
+    /// Transpile the deferred expressions of the current function, most recent first
+    ///
+    /// The return value, if there is one, is bound before they run.
+    fn run_deferred(&mut self) {
+        let deferred = take(&mut self.c_function().deferred);
+        if deferred.is_empty() {
+            return;
+        }
+        if let Some(ret_expr) = self.c_function().pop_expr() {
+            let ret_name = self.deferred_lines(ret_expr, deferred);
+            self.push_expr(ret_name);
+        } else {
+            self.deferred_nodes(deferred);
+        }
+    }
+    /// Bind a return value and then transpile some deferred expressions,
+    /// most recent first, returning the name of the bound value
+    fn deferred_lines(
+        &mut self,
+        ret_expr: String,
+        deferred: Vec<(Node<'a>, TranspileStack<'a>)>,
+    ) -> String {
+        let ret_name = self.bind_temp(ret_expr, "ret");
+        self.deferred_nodes(deferred);
+        ret_name
+    }
+    /// Transpile some deferred expressions, most recent first
+    fn deferred_nodes(&mut self, deferred: Vec<(Node<'a>, TranspileStack<'a>)>) {
+        for (node, stack) in deferred.into_iter().rev() {
+            self.node(node, stack);
+            let expr = self.pop_expr();
+            self.c_function().push_line(expr);
+        }
+    }
+    /// Return a value from the current function
+    ///
+    /// The value is checked against the return type, and then the
+    /// expressions deferred so far run. A `return` inside one of them
+    /// skips the ones that have not run yet.
+    fn return_line(&mut self, mut ret_expr: String) {
+        if let Some(ann) = self.c_function().ret.clone() {
+            ret_expr = self.type_check(&ret_expr, &ann);
+        }
+        let deferred = take(&mut self.c_function().deferred);
+        if !deferred.is_empty() {
+            ret_expr = self.deferred_lines(ret_expr, deferred.clone());
+        }
+        self.c_function().deferred = deferred;
+        self.c_function().push_line(format!("return {}", ret_expr));
+    }
     fn finish_c_function(&mut self) {
-        let cf = self.c_function();
-        let ret_expr = cf
-            .exprs
-            .front()
-            .cloned()
-            .unwrap_or_else(|| "KIN_NIL".into());
-        cf.exprs.pop_front().unwrap();
-        cf.push_line(format!("return {}", ret_expr));
+        let ret_expr = self.pop_expr();
+        self.return_line(ret_expr);
         self.function_stack.pop().unwrap();
     }
     fn curr_c_function(&mut self) -> &mut CFunction<'a> {
@@ -386,15 +727,24 @@ impl<'a> Transpilation<'a> {
         self.c_function().push_expr(expr)
     }
     fn pop_expr(&mut self) -> String {
-        self.c_function()
-            .pop_expr()
-            .unwrap_or_else(|| "KIN_NIL".into())
+        let debug_codegen = self.options.debug_codegen;
+        self.c_function().pop_expr().unwrap_or_else(|| {
+            if debug_codegen {
+                panic!("Popped from an empty expression queue")
+            }
+            "KIN_NIL".into()
+        })
     }
     fn items(&mut self, items: Items<'a>, mut stack: TranspileStack<'a>) {
         let item_count = items.len();
         for (i, item) in items.into_iter().enumerate() {
+            // A block that ends by returning is never finished, but it
+            // still needs a value
+            let diverges = i == item_count - 1 && matches!(item, Item::Return(_));
             stack = self.item(item, stack);
-            if i < item_count - 1 {
+            if diverges {
+                self.push_expr("KIN_NIL".into());
+            } else if i < item_count - 1 {
                 let cf = self.c_function();
                 if let Some(expr) = cf.pop_expr() {
                     cf.push_line(expr);
@@ -410,11 +760,30 @@ impl<'a> Transpilation<'a> {
                 self.node(node, stack.clone());
                 stack
             }
+            Item::Discard(node) => {
+                self.node(node, stack.clone());
+                let expr = self.pop_expr();
+                // A name has no side effects, so only evaluate anything else
+                if !self.var_names.contains(&expr) {
+                    self.c_function().push_line(expr);
+                }
+                stack
+            }
+            Item::Defer(node) => {
+                self.c_function().deferred.push((node, stack.clone()));
+                stack
+            }
+            Item::Return(node) => {
+                self.node(node, stack.clone());
+                let expr = self.pop_expr();
+                self.return_line(expr);
+                stack
+            }
         }
     }
 
     fn def(&mut self, def: Def<'a>, stack: TranspileStack<'a>) -> TranspileStack<'a> {
-        let c_name = self.c_name_for(&def.ident.name, def.is_function());
+        let c_name = self.c_name_for(def.ident.name, def.is_function());
         if def.is_function() {
             // Function
             let stack = stack.with_kin_def(
@@ -422,52 +791,148 @@ impl<'a> Transpilation<'a> {
                 KinDef {
                     c_name: c_name.clone(),
                     is_function: true,
+                    owner: Some(self.function_stack.len() - 1),
                 },
             );
-            self.function(c_name, def.ident.name, def.params, def.items, stack.clone());
+            self.function(
+                c_name,
+                def.ident.name,
+                def.params,
+                def.ret,
+                def.items,
+                stack.clone(),
+            );
             stack
         } else {
             // Value
-            self.items(def.items, stack.clone());
-            let cf = self.c_function();
-            let line = cf.pop_expr();
-            if let Some(line) = line {
-                cf.push_line(line).name(c_name.clone());
+            self.items(def.items, stack.clone().push_scope());
+            let line = self.c_function().pop_expr();
+            if let Some(mut line) = line {
+                if let Some(ann) = &def.ret {
+                    line = self.type_check(&line, ann);
+                }
+                self.c_function().push_line(line).name(c_name.clone());
             }
             stack.with_kin_def(
                 def.ident.name,
                 KinDef {
                     c_name,
                     is_function: false,
+                    owner: Some(self.function_stack.len() - 1),
                 },
             )
         }
     }
     fn node(&mut self, node: Node<'a>, stack: TranspileStack<'a>) {
+        if self.options.line_directives {
+            let (line, _) = self.line_col(node.kind.span().start());
+            self.c_function().kin_line = Some(line);
+        }
+        // Every node should leave exactly one expression on the queue of the
+        // function it is in
+        let checked = if self.options.debug_codegen {
+            let span = node.kind.span().clone();
+            let depth = self.c_function().exprs.len();
+            Some((span, self.function_stack.len(), depth))
+        } else {
+            None
+        };
         match node.kind {
             NodeKind::Term(term, _) => self.term(term, stack),
             NodeKind::BinExpr(expr) => self.bin_expr(expr, stack),
             NodeKind::UnExpr(expr) => self.un_expr(expr, stack),
-            NodeKind::Call(expr) => self.call_expr(expr, stack),
+            NodeKind::Call(expr) => self.call_expr(expr, node.id, stack),
+            NodeKind::CmpChain(chain) => self.cmp_chain(chain, stack),
+        }
+        if let Some((span, function_depth, depth)) = checked {
+            assert_eq!(
+                function_depth,
+                self.function_stack.len(),
+                "Transpiling {:?} did not finish the functions it started",
+                span.as_str()
+            );
+            let found = self.c_function().exprs.len();
+            assert_eq!(
+                found,
+                depth + 1,
+                "Transpiling {:?} at {:?} left {} expressions on the queue instead of 1",
+                span.as_str(),
+                self.line_col(span.start()),
+                found as isize - depth as isize
+            );
         }
     }
     fn bin_expr(&mut self, expr: BinExpr<'a>, stack: TranspileStack<'a>) {
-        self.node(*expr.left, stack.clone());
-        let left = self.pop_expr();
-        let (f, can_fail) = match expr.op {
-            BinOp::Or | BinOp::And => {
-                let or = expr.op == BinOp::Or;
+        if expr.op == BinOp::Mom {
+            self.mom_chain(expr, stack);
+            return;
+        }
+        // Walk the left spine with a worklist so that long operator
+        // chains do not recurse once per operator
+        let mut spine = vec![(expr.right.clone(), expr.op, expr.op_span)];
+        let mut left = expr.left;
+        let first = loop {
+            match &left.kind {
+                NodeKind::BinExpr(inner) if inner.op != BinOp::Mom => {
+                    spine.push((inner.right.clone(), inner.op, inner.op_span.clone()));
+                    left = inner.left;
+                }
+                _ => break left.clone(),
+            }
+        };
+        self.node(first, stack.clone());
+        for (right, op, op_span) in spine.into_iter().rev() {
+            let mut left = self.pop_expr();
+            if left.len() > MAX_INLINE_EXPR_LEN {
+                left = self.bind_temp(left, "temp");
+            }
+            self.bin_op(left, op, right, op_span, stack.clone());
+        }
+    }
+    fn mom_chain(&mut self, expr: BinExpr<'a>, stack: TranspileStack<'a>) {
+        // Lists nest to the right, so walk the right spine instead
+        let mut heads = Vec::new();
+        let mut expr = &expr;
+        let tail = loop {
+            self.node(expr.left.clone(), stack.clone());
+            heads.push(self.pop_expr());
+            match &expr.right.kind {
+                NodeKind::BinExpr(inner) if inner.op == BinOp::Mom => expr = inner,
+                _ => break expr.right.clone(),
+            }
+        };
+        self.node(tail, stack);
+        let mut tail = self.pop_expr();
+        for head in heads.into_iter().rev() {
+            let head_name = self.c_name_for("head", false);
+            let cf = self.c_function();
+            cf.push_line(head).name(&head_name);
+            cf.push_line(format!("{}.mom = &{}", head_name, tail));
+            tail = head_name;
+        }
+        self.push_expr(tail);
+    }
+    fn bin_op(
+        &mut self,
+        left: String,
+        op: BinOp,
+        right: Node<'a>,
+        op_span: Span<'a>,
+        stack: TranspileStack<'a>,
+    ) {
+        let (f, can_fail) = match op {
+            BinOp::Or | BinOp::And | BinOp::Coalesce => {
                 let temp_name = self.c_name_for("temp", false);
+                self.c_function().push_line(left).name(&temp_name);
+                let condition = match op {
+                    BinOp::Or => format!("!{}", self.truthiness(&temp_name, &op_span)),
+                    BinOp::And => self.truthiness(&temp_name, &op_span),
+                    _ => format!("{}.type == Nil", temp_name),
+                };
                 let cf = self.c_function();
-                cf.push_line(left).name(&temp_name);
-                cf.push_line(format!(
-                    "if ({}kin_is_true({})) {{",
-                    if or { "!" } else { "" },
-                    temp_name
-                ))
-                .no_semicolon();
+                cf.push_line(format!("if ({}) {{", condition)).no_semicolon();
                 cf.indent();
-                self.node(*expr.right, stack);
+                self.node(right, stack);
                 let right = self.pop_expr();
                 let cf = self.c_function();
                 cf.push_line(right).name(&temp_name).no_type();
@@ -476,69 +941,143 @@ impl<'a> Transpilation<'a> {
                 cf.push_expr(temp_name);
                 return;
             }
-            BinOp::Mom | BinOp::Dad => {
-                let mom = expr.op == BinOp::Mom;
-                self.node(*expr.right, stack);
+            BinOp::Mom => unreachable!("moms are transpiled as chains"),
+            BinOp::Dad => {
+                self.node(right, stack);
                 let right = self.pop_expr();
                 let head_name = self.c_name_for("head", false);
                 let cf = self.c_function();
-                cf.push_line(if mom { left.clone() } else { right.clone() })
-                    .name(&head_name);
-                cf.push_line(if mom {
-                    format!("{}.mom = &{}", head_name, right)
-                } else {
-                    format!("{}.dad = &{}", head_name, left)
-                });
+                cf.push_line(right).name(&head_name);
+                cf.push_line(format!("{}.dad = &{}", head_name, left));
                 cf.push_expr(head_name);
                 return;
             }
-            BinOp::Equals => ("kin_eq", false),
-            BinOp::NotEquals => ("kin_neq", false),
-            BinOp::Less => ("kin_lt", true),
-            BinOp::LessOrEqual => ("kin_le", true),
-            BinOp::Greater => ("kin_gt", true),
-            BinOp::GreaterOrEqual => ("kin_ge", true),
-            BinOp::Add => ("kin_add", true),
-            BinOp::Sub => ("kin_sub", true),
-            BinOp::Mul => ("kin_mul", true),
-            BinOp::Div => ("kin_div", true),
-            BinOp::Rem => ("kin_rem", true),
+            _ => bin_op_fn(op).unwrap(),
         };
-        self.node(*expr.right, stack);
-        let right = self.pop_expr();
+        self.node(right, stack);
+        let mut right = self.pop_expr();
+        // Right-associative chains nest on this side
+        if right.len() > MAX_INLINE_EXPR_LEN {
+            right = self.bind_temp(right, "temp");
+        }
+        let call = self.bin_op_call(f, can_fail, &left, &right, &op_span);
+        self.push_expr(call)
+    }
+    fn bin_op_call(
+        &mut self,
+        f: &str,
+        can_fail: bool,
+        left: &str,
+        right: &str,
+        op_span: &Span<'a>,
+    ) -> String {
         if can_fail {
-            let function_name = &self.curr_c_function().kin_name;
-            let (line, col) = expr.op_span.split().0.line_col();
-            let call_line = format!(
-                "kin_call_bin_op({}, {}, {}, \"{} {}:{}\")",
-                f, left, right, function_name, line, col
-            );
-            self.push_expr(call_line)
+            let call_site = self.call_site(op_span);
+            format!(
+                "kin_call_bin_op({}, {}, {}, {})",
+                f, left, right, call_site
+            )
+        } else {
+            format!("{}({}, {})", f, left, right)
+        }
+    }
+    fn cmp_chain(&mut self, chain: CmpChain<'a>, stack: TranspileStack<'a>) {
+        // Each comparison after the first is only evaluated if the previous
+        // ones were true, and each inner operand is evaluated once
+        let mut operands = chain.operands.into_iter();
+        self.node(operands.next().unwrap(), stack.clone());
+        let mut left = self.pop_expr();
+        let result_name = self.c_name_for("cmp", false);
+        let mut depth = 0;
+        for (i, ((op, op_span), right)) in chain.ops.into_iter().zip(operands).enumerate() {
+            if i > 0 {
+                let cf = self.c_function();
+                cf.push_line(format!("if (kin_is_true({})) {{", result_name))
+                    .no_semicolon();
+                cf.indent();
+                depth += 1;
+            }
+            let right = self.node_expr(right, "operand", stack.clone());
+            let (f, can_fail) = bin_op_fn(op).unwrap();
+            let call = self.bin_op_call(f, can_fail, &left, &right, &op_span);
+            let line = self.c_function().push_line(call);
+            line.name(&result_name);
+            if i > 0 {
+                line.no_type();
+            }
+            left = right;
+        }
+        let cf = self.c_function();
+        for _ in 0..depth {
+            cf.deindent();
+            cf.push_line("}").no_semicolon();
+        }
+        cf.push_expr(result_name);
+    }
+    /// The C string literal that identifies a location in a function for backtraces
+    fn call_site(&mut self, span: &Span<'a>) -> String {
+        let (line, col) = self.line_col(span.start());
+        let function_name = &self.curr_c_function().kin_name;
+        format!("\"{} {}:{}\"", function_name, line, col)
+    }
+    /// The C expression that checks a value against its type annotation
+    fn type_check(&mut self, value: &str, ann: &TypeAnn<'a>) -> String {
+        let call_site = self.call_site(&ann.span);
+        format!(
+            "kin_check_type({}, {}, {:?}, {})",
+            value,
+            ann.types.c_mask(),
+            ann.span.as_str(),
+            call_site
+        )
+    }
+    /// The C expression that tests whether a value used as a condition is true
+    fn truthiness(&mut self, value: &str, span: &Span<'a>) -> String {
+        if self.options.strict_bool {
+            let call_site = self.call_site(span);
+            format!("kin_check_bool({}, {})", value, call_site)
         } else {
-            self.push_expr(format!("{}({}, {})", f, left, right))
+            format!("kin_is_true({})", value)
         }
     }
     fn un_expr(&mut self, expr: UnExpr<'a>, stack: TranspileStack<'a>) {
-        self.node(*expr.inner, stack);
-        let inner = self.pop_expr();
+        self.node(expr.inner.clone(), stack);
+        let mut inner = self.pop_expr();
+        if inner.len() > MAX_INLINE_EXPR_LEN {
+            inner = self.bind_temp(inner, "temp");
+        }
+        if matches!(expr.op, UnOp::Not) && self.options.strict_bool {
+            let truth = self.truthiness(&inner, &expr.span);
+            self.push_expr(format!("new_bool(!{})", truth));
+            return;
+        }
         let f = match expr.op {
             UnOp::Neg => "kin_neg",
+            UnOp::Not => "kin_not",
             UnOp::Head => "kin_head",
         };
         self.push_expr(format!("{}({})", f, inner))
     }
-    fn call_expr(&mut self, call: CallExpr<'a>, stack: TranspileStack<'a>) {
-        self.node(*call.caller, stack.clone());
+    fn call_expr(&mut self, call: CallExpr<'a>, id: NodeId, stack: TranspileStack<'a>) {
+        let lowered = self.lowers_tail_call(&call, id, &stack);
+        self.node(call.caller.clone(), stack.clone());
         let f = self.pop_expr();
         let mut params = Vec::new();
         for node in call.args {
             let param = self.node_expr(node, "arg", stack.clone());
             params.push(param)
         }
+        if lowered {
+            // Return a thunk for the trampoline to run instead of calling
+            let count = params.len() + 1;
+            let args: String = once(f).chain(params).intersperse(", ".into()).collect();
+            self.push_expr(format!("kin_recur({}, (KinValue[]) {{ {} }})", count, args));
+            return;
+        }
         let param_count = params.len();
         let params: String = params.into_iter().intersperse(", ".into()).collect();
+        let (line, col) = self.line_col(call.span.start());
         let function_name = &self.curr_c_function().kin_name;
-        let (line, col) = call.span.split().0.line_col();
         let params = if param_count == 1 {
             format!("&{}", params)
         } else {
@@ -550,6 +1089,60 @@ impl<'a> Transpilation<'a> {
         );
         self.push_expr(call_line)
     }
+    /// Check whether a call in tail position is lowered to a thunk, which
+    /// keeps self and mutual recursion from growing the stack
+    ///
+    /// A call is lowered if it calls back into a function that is being
+    /// transpiled, or one that calls back into this one in tail position.
+    /// Functions with return types or deferred expressions are skipped,
+    /// because their values must be known before they return.
+    fn lowers_tail_call(
+        &mut self,
+        call: &CallExpr<'a>,
+        id: NodeId,
+        stack: &TranspileStack<'a>,
+    ) -> bool {
+        if self.function_stack.len() == 1 || !self.c_function().tail_nodes.contains(&id) {
+            return false;
+        }
+        let callee = match &call.caller.kind {
+            NodeKind::Term(Term::Ident(ident), _) => match stack.get(ident.name) {
+                Some(def) if def.is_function && def.owner.is_some() => def.c_name.clone(),
+                _ => return false,
+            },
+            _ => return false,
+        };
+        let cf = self.c_function();
+        cf.tail_callees.push(callee.clone());
+        if cf.ret.is_some() || !cf.deferred.is_empty() {
+            return false;
+        }
+        // A closure's captures live on the stack of the function that made it
+        if !self.functions[&callee].captures.is_empty() {
+            return false;
+        }
+        let curr = self.function_stack.last().unwrap().clone();
+        if !self.function_stack.contains(&callee) && !self.tail_calls_reach(&callee, &curr) {
+            return false;
+        }
+        self.c_function().trampolined = true;
+        self.functions.get_mut(&callee).unwrap().trampolined = true;
+        true
+    }
+    /// Check whether a function reaches another through calls in tail position
+    fn tail_calls_reach(&self, from: &str, to: &str) -> bool {
+        let mut seen = HashSet::new();
+        let mut queue = vec![from];
+        while let Some(name) = queue.pop() {
+            if name == to {
+                return true;
+            }
+            if seen.insert(name) {
+                queue.extend(self.functions[name].tail_callees.iter().map(String::as_str));
+            }
+        }
+        false
+    }
     fn node_expr(&mut self, node: Node<'a>, name: &str, stack: TranspileStack<'a>) -> String {
         if node.kind.is_const() {
             self.node(node, stack.clone());
@@ -557,24 +1150,23 @@ impl<'a> Transpilation<'a> {
         } else {
             self.node(node, stack.clone());
             let left = self.pop_expr();
-            let name = self.c_name_for(name, false);
-            self.c_function().push_line(left).name(&name);
-            name
+            self.bind_temp(left, name)
         }
     }
     fn term(&mut self, term: Term<'a>, stack: TranspileStack<'a>) {
         match term {
             Term::Int(i) => self.push_expr(format!("new_int({})", i)),
-            Term::Real(f) => self.push_expr(format!("new_real({})", f)),
+            Term::Real(f) => self.push_expr(format!("new_real({})", c_real_literal(f))),
             Term::String(s) => self.push_expr(format!("new_string({:?}, {})", s, s.len())),
-            Term::Expr(items) => self.items(items, stack),
+            Term::Expr(items) => self.items(items, stack.push_scope()),
             Term::Closure(closure) => {
                 let c_name = self.c_name_for("anon", true);
                 self.function(
                     c_name.clone(),
                     "closure",
-                    closure.params,
-                    closure.body,
+                    closure.params.clone(),
+                    None,
+                    closure.body.clone(),
                     stack,
                 );
                 if self.functions.get(&c_name).unwrap().captures.is_empty() {
@@ -584,47 +1176,15 @@ impl<'a> Transpilation<'a> {
                 }
             }
             Term::Tree(terms) => {
-                let [left, middle, right] = *terms;
+                let [left, middle, right] = terms.clone();
                 let left = self.node_expr(left, "left", stack.clone());
                 let middle = self.node_expr(middle, "middle", stack.clone());
                 let right = self.node_expr(right, "right", stack.clone());
                 self.push_expr(format!("new_tree(&{}, &{}, &{})", left, middle, right))
             }
             Term::Ident(ident) => {
-                if let Some(def) = stack
-                    .kin_scopes
-                    .iter()
-                    .rev()
-                    .find_map(|scope| scope.get(ident.name))
-                {
-                    if let Some((ident_i, value_name)) = self
-                        .function_stack
-                        .iter()
-                        .enumerate()
-                        .find_map(|(i, c_name)| {
-                            let cf = self.functions.get(c_name).unwrap();
-                            cf.lines
-                                .iter()
-                                .find_map(|line| {
-                                    line.var_name.as_ref().and_then(|vn| {
-                                        if vn == &def.c_name
-                                            || vn == &format!("{}_closure", def.c_name)
-                                        {
-                                            Some(vn.clone())
-                                        } else {
-                                            let derefed = format!("*{}", vn);
-                                            if derefed == def.c_name {
-                                                Some(derefed)
-                                            } else {
-                                                None
-                                            }
-                                        }
-                                    })
-                                })
-                                .map(|name| (i, name))
-                        })
-                        .filter(|(i, _)| self.function_stack.len() - i > 1)
-                    {
+                if let Some(def) = stack.get(ident.name) {
+                    if let Some((ident_i, value_name)) = self.capture_source(def) {
                         // Captures
                         let curr_stack_i = self.function_stack.len() - 1;
                         let mut prev = None;
@@ -650,7 +1210,7 @@ impl<'a> Transpilation<'a> {
                             let is_closure = self
                                 .functions
                                 .get(&def.c_name)
-                                .map_or(false, |cf| !cf.captures.is_empty());
+                                .is_some_and(|cf| !cf.captures.is_empty());
                             if is_closure {
                                 format!("{}_closure", def.c_name)
                             } else {
@@ -671,30 +1231,66 @@ impl<'a> Transpilation<'a> {
             }
         }
     }
+    /// Get where a def that is referenced in the current function lives and
+    /// the name of the C value that holds it, if it must be captured
+    ///
+    /// A def must be captured if it is a local of an enclosing function. A
+    /// function def is only a local if it is a closure that has been
+    /// finished, since otherwise it is referenced by its C function.
+    fn capture_source(&self, def: &KinDef) -> Option<(usize, String)> {
+        let owner = def.owner?;
+        if self.function_stack.len() - owner <= 1 {
+            return None;
+        }
+        if def.is_function {
+            let is_closure = !self.function_stack.contains(&def.c_name)
+                && self
+                    .functions
+                    .get(&def.c_name)
+                    .is_some_and(|cf| !cf.captures.is_empty());
+            is_closure.then(|| (owner, format!("{}_closure", def.c_name)))
+        } else {
+            Some((owner, def.c_name.clone()))
+        }
+    }
     fn function(
         &mut self,
         c_name: String,
         kin_name: &'a str,
         params: Params<'a>,
+        ret: Option<TypeAnn<'a>>,
         items: Items<'a>,
         stack: TranspileStack<'a>,
     ) {
         self.start_c_function(c_name.clone(), kin_name);
+        self.c_function().ret = ret;
+        tail_nodes(&items, &mut self.c_function().tail_nodes);
+        self.var_names
+            .extend((0..params.len()).map(|i| format!("{}_arg{}", c_name, i)));
         let cf = self.c_function();
         for i in 0..params.len() {
             cf.push_line(format!("{i} < count ? &args[{i}] : &KIN_NIL", i = i))
                 .name(format!("{}_arg{}", c_name, i))
                 .ty("KinValue*");
         }
+        // Check annotated param types
+        for (i, param) in params.iter().enumerate() {
+            if let Some(ann) = &param.ty {
+                let check = self.type_check(&format!("*{}_arg{}", c_name, i), ann);
+                self.c_function().push_line(check);
+            }
+        }
+        let owner = Some(self.function_stack.len() - 1);
         let stack = params
             .into_iter()
             .enumerate()
-            .fold(stack, |stack, (i, param)| {
+            .fold(stack.push_scope(), |stack, (i, param)| {
                 stack.with_kin_def(
                     param.ident.name,
                     KinDef {
                         c_name: format!("*{}_arg{}", c_name, i),
                         is_function: false,
+                        owner,
                     },
                 )
             });
@@ -708,6 +1304,8 @@ impl<'a> Transpilation<'a> {
         }
         let captures_name = format!("{}_captures", c_name);
         let closure_name = format!("{}_closure", c_name);
+        self.var_names.insert(captures_name.clone());
+        self.var_names.insert(closure_name.clone());
         self.c_function()
             .push_line(format!("KinValue {}[{}]", captures_name, captures.len()));
         let cf = self.c_function();
@@ -0,0 +1,197 @@
+//! Traits for traversing and rewriting the AST
+//!
+//! Implementors override the methods for the nodes they care about and call
+//! the matching `walk_` or `fold_` function to recurse into the rest.
+
+use pest::Span;
+
+use crate::ast::*;
+
+/// Traverse the AST by reference
+pub trait Visit<'a> {
+    fn visit_item(&mut self, item: &Item<'a>) {
+        walk_item(self, item)
+    }
+    fn visit_def(&mut self, def: &Def<'a>) {
+        walk_def(self, def)
+    }
+    fn visit_node(&mut self, node: &Node<'a>) {
+        walk_node(self, node)
+    }
+    fn visit_term(&mut self, term: &Term<'a>, _span: &Span<'a>) {
+        walk_term(self, term)
+    }
+    fn visit_closure(&mut self, closure: &Closure<'a>) {
+        walk_closure(self, closure)
+    }
+    fn visit_ident(&mut self, _ident: &Ident<'a>) {}
+}
+
+pub fn walk_items<'a, V: Visit<'a> + ?Sized>(visitor: &mut V, items: &[Item<'a>]) {
+    for item in items {
+        visitor.visit_item(item);
+    }
+}
+
+pub fn walk_item<'a, V: Visit<'a> + ?Sized>(visitor: &mut V, item: &Item<'a>) {
+    match item {
+        Item::Node(node) | Item::Defer(node) | Item::Discard(node) | Item::Return(node) => {
+            visitor.visit_node(node)
+        }
+        Item::Def(def) => visitor.visit_def(def),
+    }
+}
+
+pub fn walk_def<'a, V: Visit<'a> + ?Sized>(visitor: &mut V, def: &Def<'a>) {
+    visitor.visit_ident(&def.ident);
+    for param in &def.params {
+        visitor.visit_ident(&param.ident);
+    }
+    walk_items(visitor, &def.items);
+}
+
+pub fn walk_node<'a, V: Visit<'a> + ?Sized>(visitor: &mut V, node: &Node<'a>) {
+    match &node.kind {
+        NodeKind::Term(term, span) => visitor.visit_term(term, span),
+        NodeKind::BinExpr(expr) => {
+            visitor.visit_node(expr.left);
+            visitor.visit_node(expr.right);
+        }
+        NodeKind::UnExpr(expr) => visitor.visit_node(expr.inner),
+        NodeKind::Call(expr) => {
+            visitor.visit_node(expr.caller);
+            for arg in &expr.args {
+                visitor.visit_node(arg);
+            }
+        }
+        NodeKind::CmpChain(chain) => {
+            for operand in &chain.operands {
+                visitor.visit_node(operand);
+            }
+        }
+    }
+}
+
+pub fn walk_term<'a, V: Visit<'a> + ?Sized>(visitor: &mut V, term: &Term<'a>) {
+    match term {
+        Term::Expr(items) => walk_items(visitor, items),
+        Term::Ident(ident) => visitor.visit_ident(ident),
+        Term::Tree(nodes) => {
+            for node in nodes.iter() {
+                visitor.visit_node(node);
+            }
+        }
+        Term::Closure(closure) => visitor.visit_closure(closure),
+        Term::Int(_) | Term::Real(_) | Term::String(_) => {}
+    }
+}
+
+pub fn walk_closure<'a, V: Visit<'a> + ?Sized>(visitor: &mut V, closure: &Closure<'a>) {
+    for param in &closure.params {
+        visitor.visit_ident(&param.ident);
+    }
+    walk_items(visitor, &closure.body);
+}
+
+/// Rewrite the AST by value
+pub trait Fold<'a> {
+    /// The arena that rewritten children are allocated in
+    fn arena(&self) -> &'a Arena<'a>;
+    fn fold_items(&mut self, items: Items<'a>) -> Items<'a> {
+        fold_items(self, items)
+    }
+    fn fold_item(&mut self, item: Item<'a>) -> Item<'a> {
+        fold_item(self, item)
+    }
+    fn fold_def(&mut self, def: Def<'a>) -> Def<'a> {
+        fold_def(self, def)
+    }
+    fn fold_node(&mut self, node: Node<'a>) -> Node<'a> {
+        fold_node(self, node)
+    }
+    fn fold_term(&mut self, term: Term<'a>) -> Term<'a> {
+        fold_term(self, term)
+    }
+    fn fold_closure(&mut self, closure: Closure<'a>) -> Closure<'a> {
+        fold_closure(self, closure)
+    }
+}
+
+pub fn fold_items<'a, F: Fold<'a> + ?Sized>(folder: &mut F, items: Items<'a>) -> Items<'a> {
+    items.into_iter().map(|item| folder.fold_item(item)).collect()
+}
+
+pub fn fold_item<'a, F: Fold<'a> + ?Sized>(folder: &mut F, item: Item<'a>) -> Item<'a> {
+    match item {
+        Item::Node(node) => Item::Node(folder.fold_node(node)),
+        Item::Def(def) => Item::Def(folder.fold_def(def)),
+        Item::Defer(node) => Item::Defer(folder.fold_node(node)),
+        Item::Discard(node) => Item::Discard(folder.fold_node(node)),
+        Item::Return(node) => Item::Return(folder.fold_node(node)),
+    }
+}
+
+pub fn fold_def<'a, F: Fold<'a> + ?Sized>(folder: &mut F, def: Def<'a>) -> Def<'a> {
+    Def {
+        items: folder.fold_items(def.items),
+        ..def
+    }
+}
+
+pub fn fold_node<'a, F: Fold<'a> + ?Sized>(folder: &mut F, node: Node<'a>) -> Node<'a> {
+    let kind = match node.kind {
+        NodeKind::Term(term, span) => NodeKind::Term(folder.fold_term(term), span),
+        NodeKind::BinExpr(expr) => NodeKind::BinExpr(BinExpr {
+            left: folder.arena().node(folder.fold_node(expr.left.clone())),
+            right: folder.arena().node(folder.fold_node(expr.right.clone())),
+            ..expr
+        }),
+        NodeKind::UnExpr(expr) => NodeKind::UnExpr(UnExpr {
+            inner: folder.arena().node(folder.fold_node(expr.inner.clone())),
+            ..expr
+        }),
+        NodeKind::Call(expr) => NodeKind::Call(CallExpr {
+            caller: folder.arena().node(folder.fold_node(expr.caller.clone())),
+            args: expr
+                .args
+                .into_iter()
+                .map(|arg| folder.fold_node(arg))
+                .collect(),
+            span: expr.span,
+        }),
+        NodeKind::CmpChain(chain) => NodeKind::CmpChain(CmpChain {
+            operands: chain
+                .operands
+                .into_iter()
+                .map(|operand| folder.fold_node(operand))
+                .collect(),
+            ..chain
+        }),
+    };
+    Node { kind, ..node }
+}
+
+pub fn fold_term<'a, F: Fold<'a> + ?Sized>(folder: &mut F, term: Term<'a>) -> Term<'a> {
+    match term {
+        Term::Expr(items) => Term::Expr(folder.fold_items(items)),
+        Term::Tree(nodes) => {
+            let [a, b, c] = nodes.clone();
+            Term::Tree(folder.arena().tree([
+                folder.fold_node(a),
+                folder.fold_node(b),
+                folder.fold_node(c),
+            ]))
+        }
+        Term::Closure(closure) => {
+            Term::Closure(folder.arena().closure(folder.fold_closure(closure.clone())))
+        }
+        term => term,
+    }
+}
+
+pub fn fold_closure<'a, F: Fold<'a> + ?Sized>(folder: &mut F, closure: Closure<'a>) -> Closure<'a> {
+    Closure {
+        body: folder.fold_items(closure.body),
+        ..closure
+    }
+}
@@ -0,0 +1,67 @@
+//! The entry point for compiling Kin in a browser
+
+use std::path::Path;
+
+use wasm_bindgen::prelude::*;
+
+use crate::{ast::Arena, lint, parse::parse, transpile::*};
+
+/// The result of compiling a source string
+#[wasm_bindgen]
+pub struct Compiled {
+    c: Option<String>,
+    diagnostics: Vec<String>,
+}
+
+#[wasm_bindgen]
+impl Compiled {
+    /// The C source, or nothing if there were errors
+    #[wasm_bindgen(getter)]
+    pub fn c(&self) -> Option<String> {
+        self.c.clone()
+    }
+    /// The errors and warnings, formatted as the command line prints them
+    #[wasm_bindgen(getter)]
+    pub fn diagnostics(&self) -> Vec<String> {
+        self.diagnostics.clone()
+    }
+}
+
+/// Check source and transpile it to C
+///
+/// Lints are at their default levels, since there is no `kin.toml`. The C
+/// source includes `kin.h`, which is `KIN_HEADER`.
+#[wasm_bindgen]
+pub fn compile(source: &str) -> Compiled {
+    let options = CompileOptions::default();
+    let arena = Arena::default();
+    let items = match parse(source, &arena, &options) {
+        Ok(items) => items,
+        Err(errors) => {
+            return Compiled {
+                c: None,
+                diagnostics: errors.iter().map(ToString::to_string).collect(),
+            }
+        }
+    };
+    let (levels, mut diagnostics) = lint::Levels::from_options(&options);
+    let reports = lint::lint(&items, &levels);
+    diagnostics.extend(reports.iter().map(ToString::to_string));
+    if reports.iter().any(|report| report.level == lint::Level::Deny) {
+        return Compiled { c: None, diagnostics };
+    }
+    let mut c = Vec::new();
+    transpile(items, source, &arena, &options)
+        .write_source(&mut c, Path::new("main.kin"))
+        .expect("writing to a Vec cannot fail");
+    Compiled {
+        c: Some(String::from_utf8(c).expect("C source is UTF-8")),
+        diagnostics,
+    }
+}
+
+/// The runtime header that the C source includes
+#[wasm_bindgen]
+pub fn header() -> String {
+    KIN_HEADER.into()
+}
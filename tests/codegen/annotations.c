@@ -0,0 +1,26 @@
+#include "kin.h"
+
+KinValue describe(uint8_t count, KinValue* args);
+KinValue square(uint8_t count, KinValue* args);
+
+KinValue describe(uint8_t count, KinValue* args) {
+    KinValue* describe_arg0 = 0 < count ? &args[0] : &KIN_NIL;
+    kin_check_type(*describe_arg0, 1 << Int | 1 << String, "Key", "describe 3:16");
+    return kin_check_type(new_string("key", 3), 1 << String, "string", "describe 3:24");
+}
+
+int main(int argc, char** argv) {
+    kin_init();
+    KinValue arg = kin_call(new_function(&square), 1, &new_int(3), "main 4:10");
+    kin_call(new_function(&kin_println), 1, &arg, "main 4:1");
+    KinValue arg_2 = kin_call(new_function(&describe), 1, &new_string("a", 1), "main 5:10");
+    kin_call(new_function(&kin_println), 1, &arg_2, "main 5:1");
+    return kin_exit_status(new_int(0));
+}
+
+KinValue square(uint8_t count, KinValue* args) {
+    KinValue* square_arg0 = 0 < count ? &args[0] : &KIN_NIL;
+    kin_check_type(*square_arg0, 1 << Int | 1 << Real, "number", "square 2:12");
+    return kin_check_type(kin_call_bin_op(kin_mul, *square_arg0, *square_arg0, "square 2:34"), 1 << Int | 1 << Real, "number", "square 2:23");
+}
+
@@ -0,0 +1,26 @@
+#include "kin.h"
+
+
+int main(int argc, char** argv) {
+    kin_init();
+    KinValue a = new_int(5);
+    KinValue b = new_real(2.5);
+    KinValue arg = kin_call_bin_op(kin_add, a, kin_call_bin_op(kin_mul, b, new_int(2), "main 3:16"), "main 3:12");
+    kin_call(new_function(&kin_println), 1, &arg, "main 3:1");
+    KinValue head = new_int(2);
+    head.mom = &a;
+    KinValue head_2 = new_int(3);
+    head_2.mom = &new_int(2);
+    KinValue arg_2 = kin_call_bin_op(kin_mod, kin_call_bin_op(kin_floor_div, a, head, "main 4:12"), kin_call_bin_op(kin_pow, head_2, new_int(10), "main 4:31"), "main 4:21");
+    kin_call(new_function(&kin_println), 1, &arg_2, "main 4:1");
+    KinValue cmp = kin_call_bin_op(kin_lt, new_int(1), a, "main 5:12");
+    if (kin_is_true(cmp)) {
+        cmp = kin_call_bin_op(kin_le, a, new_int(5), "main 5:16");
+    }
+    KinValue arg_3 = cmp;
+    kin_call(new_function(&kin_println), 1, &arg_3, "main 5:1");
+    KinValue arg_4 = kin_call_bin_op(kin_add, new_int(86399), a, "main 6:27");
+    kin_call(new_function(&kin_println), 1, &arg_4, "main 6:1");
+    return kin_exit_status(new_int(0));
+}
+
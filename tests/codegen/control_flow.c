@@ -0,0 +1,40 @@
+#include "kin.h"
+
+KinValue classify(uint8_t count, KinValue* args);
+
+KinValue classify(uint8_t count, KinValue* args) {
+    KinValue* classify_arg0 = 0 < count ? &args[0] : &KIN_NIL;
+    KinValue temp = kin_call_bin_op(kin_lt, *classify_arg0, new_int(0), "classify 1:16");
+    if (kin_is_true(temp)) {
+        temp = new_string("negative", 8);
+    }
+    KinValue temp_2 = temp;
+    if (!kin_is_true(temp_2)) {
+        KinValue temp_3 = kin_eq(*classify_arg0, new_int(0));
+        if (kin_is_true(temp_3)) {
+            temp_3 = new_string("zero", 4);
+        }
+        temp_2 = temp_3;
+    }
+    KinValue temp_4 = temp_2;
+    if (!kin_is_true(temp_4)) {
+        temp_4 = new_string("positive", 8);
+    }
+    return temp_4;
+}
+
+int main(int argc, char** argv) {
+    kin_init();
+    KinValue arg = kin_call(new_function(&classify), 1, &new_int(-3), "main 2:10");
+    kin_call(new_function(&kin_println), 1, &arg, "main 2:1");
+    KinValue temp_5 = KIN_NIL;
+    if (temp_5.type == Nil) {
+        temp_5 = new_string("default", 7);
+    }
+    KinValue arg_2 = temp_5;
+    kin_call(new_function(&kin_println), 1, &arg_2, "main 3:1");
+    KinValue arg_3 = kin_not(KIN_FALSE);
+    kin_call(new_function(&kin_println), 1, &arg_3, "main 4:1");
+    return kin_exit_status(new_int(0));
+}
+
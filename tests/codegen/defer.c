@@ -0,0 +1,27 @@
+#include "kin.h"
+
+KinValue f(uint8_t count, KinValue* args);
+KinValue work(uint8_t count, KinValue* args);
+
+KinValue f(uint8_t count, KinValue* args) {
+    KinValue* f_arg0 = 0 < count ? &args[0] : &KIN_NIL;
+    return *f_arg0;
+}
+
+int main(int argc, char** argv) {
+    kin_init();
+    KinValue arg = kin_call(new_function(&work), 1, &new_int(21), "main 6:10");
+    kin_call(new_function(&kin_println), 1, &arg, "main 6:1");
+    kin_call(new_function(&kin_println), 1, &new_string("hi", 2), "main 8:1");
+    kin_call(new_function(&kin_println), 1, &new_string("bye", 3), "main 7:7");
+    return 0;
+}
+
+KinValue work(uint8_t count, KinValue* args) {
+    KinValue* work_arg0 = 0 < count ? &args[0] : &KIN_NIL;
+    kin_call(new_function(&kin_println), 1, &new_string("working", 7), "work 3:5");
+    KinValue ret = kin_call_bin_op(kin_mul, *work_arg0, new_int(2), "work 4:7");
+    kin_call(new_function(&kin_println), 1, &new_string("cleanup", 7), "work 2:11");
+    return ret;
+}
+
@@ -0,0 +1,68 @@
+#include "kin.h"
+
+KinValue add(uint8_t count, KinValue* args);
+KinValue anon(uint8_t count, KinValue* args, KinValue* captures);
+KinValue count_down(uint8_t count, KinValue* args);
+KinValue sum(uint8_t count, KinValue* args);
+
+KinValue add(uint8_t count, KinValue* args) {
+    KinValue* add_arg0 = 0 < count ? &args[0] : &KIN_NIL;
+    KinValue* add_arg1 = 1 < count ? &args[1] : &KIN_NIL;
+    return kin_call_bin_op(kin_add, *add_arg0, *add_arg1, "add 1:13");
+}
+
+KinValue anon(uint8_t count, KinValue* args, KinValue* captures) {
+    KinValue* anon_arg0 = 0 < count ? &args[0] : &KIN_NIL;
+    return kin_call_bin_op(kin_add, *anon_arg0, captures[0], "closure 3:14");
+}
+
+KinValue count_down(uint8_t count, KinValue* args) {
+    if (!kin_is_tail_step((KinFn)&count_down)) return kin_enter_trampoline(new_function(&count_down), count, args);
+    KinValue* count_down_arg0 = 0 < count ? &args[0] : &KIN_NIL;
+    KinValue temp_3 = kin_eq(*count_down_arg0, new_int(0));
+    if (!kin_is_true(temp_3)) {
+        KinValue arg_8 = kin_call_bin_op(kin_sub, *count_down_arg0, new_int(1), "count_down 9:40");
+        temp_3 = kin_recur(2, (KinValue[]) { new_function(&count_down), arg_8 });
+    }
+    return temp_3;
+}
+
+int main(int argc, char** argv) {
+    kin_init();
+    KinValue n = new_int(5);
+    KinValue anon_captures[1];
+    anon_captures[0] = n;
+    KinValue anon_closure = new_closure(&anon, anon_captures);
+    KinValue add5 = anon_closure;
+    KinValue arg = kin_call(new_function(&add), 2, (KinValue[]) { new_int(1), new_int(2) }, "main 4:10");
+    kin_call(new_function(&kin_println), 1, &arg, "main 4:1");
+    KinValue arg_2 = kin_call(add5, 1, &new_int(10), "main 5:10");
+    kin_call(new_function(&kin_println), 1, &arg_2, "main 5:1");
+    KinValue head = new_int(3);
+    head.mom = &new_int(4);
+    KinValue arg_3 = head;
+    KinValue arg_4 = kin_call(new_function(&kin_apply), 2, (KinValue[]) { new_function(&add), arg_3 }, "main 6:10");
+    kin_call(new_function(&kin_println), 1, &arg_4, "main 6:1");
+    KinValue arg_7 = kin_call(new_function(&kin_trampoline), 3, (KinValue[]) { new_function(&sum), new_int(100), new_int(0) }, "main 8:10");
+    kin_call(new_function(&kin_println), 1, &arg_7, "main 8:1");
+    KinValue arg_9 = kin_call(new_function(&count_down), 1, &new_int(100), "main 10:10");
+    kin_call(new_function(&kin_println), 1, &arg_9, "main 10:1");
+    return kin_exit_status(new_int(0));
+}
+
+KinValue sum(uint8_t count, KinValue* args) {
+    KinValue* sum_arg0 = 0 < count ? &args[0] : &KIN_NIL;
+    KinValue* sum_arg1 = 1 < count ? &args[1] : &KIN_NIL;
+    KinValue temp = kin_eq(*sum_arg0, new_int(0));
+    if (kin_is_true(temp)) {
+        temp = *sum_arg1;
+    }
+    KinValue temp_2 = temp;
+    if (!kin_is_true(temp_2)) {
+        KinValue arg_5 = kin_call_bin_op(kin_sub, *sum_arg0, new_int(1), "sum 7:44");
+        KinValue arg_6 = kin_call_bin_op(kin_add, *sum_arg1, *sum_arg0, "sum 7:54");
+        temp_2 = kin_call(new_function(&kin_recur), 3, (KinValue[]) { new_function(&sum), arg_5, arg_6 }, "sum 7:31");
+    }
+    return temp_2;
+}
+
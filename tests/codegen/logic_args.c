@@ -0,0 +1,95 @@
+#include "kin.h"
+
+KinValue pick(uint8_t count, KinValue* args);
+KinValue plus(uint8_t count, KinValue* args);
+
+int main(int argc, char** argv) {
+    kin_init();
+    KinValue arg = kin_call(new_function(&pick), 3, (KinValue[]) { new_int(1), KIN_NIL, new_int(3) }, "main 4:10");
+    kin_call(new_function(&kin_println), 1, &arg, "main 4:1");
+    KinValue temp_3 = KIN_NIL;
+    if (!kin_is_true(temp_3)) {
+        KinValue temp_4 = new_int(1);
+        if (kin_is_true(temp_4)) {
+            temp_4 = new_int(2);
+        }
+        temp_3 = temp_4;
+    }
+    KinValue arg_2 = temp_3;
+    KinValue temp_5 = KIN_FALSE;
+    if (kin_is_true(temp_5)) {
+        temp_5 = new_int(5);
+    }
+    KinValue arg_3 = temp_5;
+    KinValue temp_6 = KIN_NIL;
+    if (temp_6.type == Nil) {
+        temp_6 = KIN_NIL;
+    }
+    KinValue temp_7 = temp_6;
+    if (temp_7.type == Nil) {
+        temp_7 = new_int(4);
+    }
+    KinValue arg_4 = temp_7;
+    KinValue arg_5 = kin_call(new_function(&pick), 3, (KinValue[]) { arg_2, arg_3, arg_4 }, "main 5:10");
+    kin_call(new_function(&kin_println), 1, &arg_5, "main 5:1");
+    KinValue temp_8 = new_int(1);
+    if (kin_is_true(temp_8)) {
+        temp_8 = new_int(7);
+    }
+    KinValue arg_6 = temp_8;
+    KinValue temp_9 = KIN_NIL;
+    if (!kin_is_true(temp_9)) {
+        KinValue temp_10 = new_int(3);
+        if (kin_is_true(temp_10)) {
+            KinValue temp_11 = KIN_NIL;
+            if (temp_11.type == Nil) {
+                temp_11 = new_int(9);
+            }
+            temp_10 = temp_11;
+        }
+        temp_9 = temp_10;
+    }
+    KinValue arg_7 = temp_9;
+    KinValue arg_8 = kin_call(new_function(&plus), 2, (KinValue[]) { arg_6, arg_7 }, "main 6:10");
+    kin_call(new_function(&kin_println), 1, &arg_8, "main 6:1");
+    KinValue temp_12 = kin_call(new_function(&pick), 3, (KinValue[]) { KIN_NIL, new_int(1), new_int(2) }, "main 7:16");
+    if (!kin_is_true(temp_12)) {
+        temp_12 = new_int(10);
+    }
+    KinValue arg_9 = temp_12;
+    KinValue temp_13 = KIN_FALSE;
+    if (!kin_is_true(temp_13)) {
+        temp_13 = KIN_NIL;
+    }
+    KinValue arg_10 = temp_13;
+    KinValue temp_14 = KIN_NIL;
+    if (temp_14.type == Nil) {
+        temp_14 = new_int(5);
+    }
+    KinValue arg_11 = temp_14;
+    KinValue arg_12 = kin_call(new_function(&pick), 3, (KinValue[]) { new_int(1), arg_10, arg_11 }, "main 7:37");
+    KinValue arg_13 = kin_call(new_function(&plus), 2, (KinValue[]) { arg_9, arg_12 }, "main 7:10");
+    return kin_exit_status(kin_call(new_function(&kin_println), 1, &arg_13, "main 7:1"));
+}
+
+KinValue pick(uint8_t count, KinValue* args) {
+    KinValue* pick_arg0 = 0 < count ? &args[0] : &KIN_NIL;
+    KinValue* pick_arg1 = 1 < count ? &args[1] : &KIN_NIL;
+    KinValue* pick_arg2 = 2 < count ? &args[2] : &KIN_NIL;
+    KinValue temp = *pick_arg0;
+    if (kin_is_true(temp)) {
+        KinValue temp_2 = *pick_arg1;
+        if (!kin_is_true(temp_2)) {
+            temp_2 = *pick_arg2;
+        }
+        temp = temp_2;
+    }
+    return temp;
+}
+
+KinValue plus(uint8_t count, KinValue* args) {
+    KinValue* plus_arg0 = 0 < count ? &args[0] : &KIN_NIL;
+    KinValue* plus_arg1 = 1 < count ? &args[1] : &KIN_NIL;
+    return kin_call_bin_op(kin_add, *plus_arg0, *plus_arg1, "plus 3:14");
+}
+
@@ -0,0 +1,21 @@
+#include "kin.h"
+
+KinValue anon(uint8_t count, KinValue* args);
+
+KinValue anon(uint8_t count, KinValue* args) {
+    KinValue* anon_arg0 = 0 < count ? &args[0] : &KIN_NIL;
+    KinValue* anon_arg1 = 1 < count ? &args[1] : &KIN_NIL;
+    return kin_call_bin_op(kin_add, kin_call_bin_op(kin_mul, *anon_arg0, new_int(10), "closure 1:37"), *anon_arg1, "closure 1:42");
+}
+
+int main(int argc, char** argv) {
+    kin_init();
+    KinValue op_lt_plus_gt = new_function(&anon);
+    KinValue arg = kin_call(op_lt_plus_gt, 2, (KinValue[]) { new_int(1), new_int(2) }, "main 2:10");
+    KinValue arg_2 = kin_call(op_lt_plus_gt, 2, (KinValue[]) { arg, new_int(3) }, "main 2:10");
+    kin_call(new_function(&kin_println), 1, &arg_2, "main 2:1");
+    KinValue arg_3 = kin_call_bin_op(kin_mul, new_string("ab", 2), new_int(3), "main 3:15");
+    kin_call(new_function(&kin_println), 1, &arg_3, "main 3:1");
+    return kin_exit_status(new_int(0));
+}
+